@@ -6,7 +6,7 @@ use criterion::Criterion;
 use rtforth::core::Core;
 use rtforth::loader::HasLoader;
 use rtforth::memory::Memory;
-use rtforth::mock_vm::VM;
+use rtforth::mock_vm::{NewVM, VM};
 
 fn bench_noop(c: &mut Criterion) {
     c.bench_function("noop", |b| {
@@ -4,7 +4,6 @@
 : space ( -- )   32 emit ;
 : spaces ( n -- )   0 begin 2dup > while 1+ space repeat 2drop ;
 : . ( n -- )   0 .r space ;
-: f. ( F: r -- )   0 7 f.r space ;
 : ? ( addr -- )   @ . ;
 : decimal   10 base ! ;
 : hex   16 base ! ;
@@ -42,6 +41,15 @@
 : 2variable   create  0 , 0 , ;
 : fvariable   create falign 0e f, does> faligned ;
 : +field ( n1 n2 -- n3 )   create over , + does> @ + ;
+: ffield: ( n1 "name" -- n2 )   faligned 1 floats +field ;
+: sffield: ( n1 "name" -- n2 )   sfaligned 1 sfloats +field ;
+: dffield: ( n1 "name" -- n2 )   dfaligned 1 dfloats +field ;
+\ The body of a value starts with 0 for VALUE and 1 for FVALUE.
+: value ( x "name" -- )   create 0 , ,  does> cell+ @ ;
+: fvalue ( F: r "name" -- )   create 1 , falign f,  does> cell+ faligned f@ ;
+: _to ( i*x a-addr -- )   dup @ if cell+ faligned f! else cell+ ! then ;
+: to ( i*x "name" -- )
+    ' >body  compiling? if postpone lit , postpone _to else _to then ; immediate
 : defer   create ['] noop ,  does> @ execute ;
 : defer@ ( xt1 -- xt2 )   >body @ ;
 : defer! ( xt2 xt1 -- )   >body ! ;
@@ -138,18 +146,17 @@ variable load-line#
       evaluate-input  flush-output
       1 load-line# +!
     repeat  drop ;
-: included ( c-addr u -- )
-    2dup  r/o open-file 0= if
-        save-source
-        ( c-addr u file-id ) open-source source-id!
-        postpone [
-        1 load-line# !
-        load-source-file
-        source-id  restore-source  close-source
-    else
-        abort
-    then
-;
+: _include-source ( source-id -- )
+    save-source
+    source-id!
+    postpone [
+    1 load-line# !
+    load-source-file
+    source-id  restore-source  close-source ;
+: include-file ( file-id -- )   here 0 rot open-source _include-source ;
+: included ( c-addr u -- )   false _open-include _include-source ;
 : include ( "path" -- )   32 word count included ;
+: required ( c-addr u -- )   true _open-include ?dup if _include-source then ;
+: require ( "path" -- )   32 word count required ;
 : \\ ( -- )   source-id   begin  dup load-line  while  drop  repeat  2drop ;
 marker -work
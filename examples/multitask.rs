@@ -1,15 +1,14 @@
 #[macro_use(primitive)]
 extern crate rtforth;
-mod vm;
 
 use rtforth::core::Core;
 use rtforth::exception;
 use rtforth::output::Output;
+use rtforth::vm::{VMBuilder, VM};
 use std::process;
-use vm::VM;
 
 fn main() {
-    let mut vm = VM::new(400 * 1024);
+    let mut vm = VMBuilder::new().data_capacity(400 * 1024).build();
     vm.add_primitive("bye", bye);
 
     vm.set_source(
@@ -22,7 +21,7 @@ fn main() {
     vm.evaluate_input();
     match vm.last_error() {
         Some(e) => {
-            println!("{}", exception::description(e));
+            vm.error_sink().write_line(exception::description(e));
             vm.reset();
         }
         None => {}
@@ -1,12 +1,11 @@
 extern crate rtforth;
-mod vm;
 
 use rtforth::core::Core;
-use vm::VM;
+use rtforth::vm::VMBuilder;
 
 // Evaluate "1 ."
 fn main() {
-    let vm = &mut VM::new(400 * 1024);
+    let vm = &mut VMBuilder::new().data_capacity(400 * 1024).build();
     vm.set_source("1 . flush-output");
     vm.evaluate_input();
     match vm.last_error() {
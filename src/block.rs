@@ -0,0 +1,657 @@
+//! Block word set
+//!
+//! Blocks are records of `BLOCK_SIZE` bytes in a block file. Block `u` is
+//! stored at offset `(u - 1) * BLOCK_SIZE` of the file, block 0 does not
+//! exist. Parts of the file which have never been written read as spaces.
+//!
+//! Blocks are accessed through a fixed number of buffers in data space,
+//! see `VMBuilder::block_buffers`. When a block is not in a buffer, the
+//! least recently used buffer is reassigned, and written back first if it
+//! was updated.
+//!
+//! ```
+//! use rtforth::block::Block;
+//! use rtforth::core::Core;
+//! use rtforth::vm::VMBuilder;
+//! use std::fs;
+//! # use std::env;
+//!
+//! # let path = env::temp_dir().join("rtforth-block-doctest.blk");
+//! # let path = path.to_str().unwrap();
+//! let mut block = b"6 7 *".to_vec();
+//! block.resize(1024, b' ');
+//! fs::write(path, &block).unwrap();
+//!
+//! let mut vm = VMBuilder::new().build();
+//! vm.open_block_file(path).unwrap();
+//! vm.set_source("1 load");
+//! vm.evaluate_input();
+//! assert_eq!(vm.s_stack().pop(), 42);
+//! # fs::remove_file(path).unwrap();
+//! ```
+
+use core::Core;
+use exception::{
+    ForthError, BLOCK_READ_EXCEPTION, BLOCK_WRITE_EXCEPTION, INVALID_BLOCK_NUMBER,
+    INVALID_NUMERIC_ARGUMENT,
+};
+use memory::Memory;
+use output::Output;
+use std::fmt::Write as FmtWrite;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem;
+use Result;
+
+/// Size of a block in bytes
+pub const BLOCK_SIZE: usize = 1024;
+
+/// Number of block buffers of `VMBuilder`
+pub const DEFAULT_BLOCK_BUFFERS: usize = 4;
+
+/// Length of a line displayed by LIST and skipped by `\` in a block
+const LINE_LENGTH: usize = 64;
+
+/// Largest block number whose offset is a positive isize
+const MAX_BLOCK: usize = (!0 >> 1) / BLOCK_SIZE;
+
+#[derive(Clone, Copy, Default)]
+struct BlockBuffer {
+    // Assigned block, 0 if none.
+    block: usize,
+    updated: bool,
+    // Time of last access, for LRU replacement.
+    used: u64,
+}
+
+/// Block file and buffers of a VM
+pub struct Blocks {
+    file: Option<File>,
+    buffers: Vec<BlockBuffer>,
+    current: Option<usize>,
+    clock: u64,
+    // Forth addresses set by `Block::add_block`.
+    start: usize,
+    blk: usize,
+    scr: usize,
+}
+
+impl Blocks {
+    /// `count` block buffers, at least 1, without block file.
+    pub fn new(count: usize) -> Blocks {
+        Blocks {
+            file: None,
+            buffers: vec![BlockBuffer::default(); count.max(1)],
+            current: None,
+            clock: 0,
+            start: 0,
+            blk: 0,
+            scr: 0,
+        }
+    }
+
+    /// Number of block buffers.
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Is there a block file?
+    pub fn has_file(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Unassign all buffers without writing them back.
+    pub fn empty(&mut self) {
+        for b in &mut self.buffers {
+            *b = BlockBuffer::default();
+        }
+        self.current = None;
+    }
+
+    /// Forth address of buffer `i`.
+    fn address(&self, i: usize) -> usize {
+        self.start + i * BLOCK_SIZE
+    }
+
+    /// Buffer assigned to block `u`.
+    fn find(&self, u: usize) -> Option<usize> {
+        self.buffers.iter().position(|b| b.block == u)
+    }
+
+    /// Buffer to be reassigned: an unassigned one, or else the least
+    /// recently used.
+    fn victim(&self) -> usize {
+        match self.buffers.iter().position(|b| b.block == 0) {
+            Some(i) => i,
+            None => {
+                let mut i = 0;
+                for (j, b) in self.buffers.iter().enumerate() {
+                    if b.used < self.buffers[i].used {
+                        i = j;
+                    }
+                }
+                i
+            }
+        }
+    }
+
+    /// Make buffer `i` the current and most recently used buffer.
+    fn touch(&mut self, i: usize) {
+        self.clock += 1;
+        self.buffers[i].used = self.clock;
+        self.current = Some(i);
+    }
+}
+
+/// Offset of block `u` in the block file.
+fn offset(u: usize) -> u64 {
+    ((u - 1) * BLOCK_SIZE) as u64
+}
+
+pub trait Block: Core + Output {
+    /// Allot the block buffers and the variables BLK and SCR in data space
+    /// and add block primitives. Abort with `DICTIONARY_OVERFLOW` and add
+    /// nothing if there is no space for the buffers.
+    fn add_block(&mut self) {
+        // BLK, SCR and the buffers, after padding for alignment.
+        let len = self.blocks().buffer_count() * BLOCK_SIZE;
+        if !self.check_space(3 * mem::size_of::<isize>() + len) {
+            return;
+        }
+        self.data_space().align();
+        let blk = self.data_space().here();
+        self.data_space().compile_isize(0);
+        let scr = self.data_space().here();
+        self.data_space().compile_isize(0);
+        let start = self.data_space().here();
+        self.data_space().allot(len as isize);
+        {
+            let blocks = self.blocks();
+            blocks.start = start;
+            blocks.blk = blk;
+            blocks.scr = scr;
+        }
+
+        self.add_primitive("block", Block::block);
+        self.add_primitive("buffer", Block::p_buffer);
+        self.add_primitive("update", Block::p_update);
+        self.add_primitive("save-buffers", Block::save_buffers);
+        self.add_primitive("empty-buffers", Block::empty_buffers);
+        self.add_primitive("flush", Block::p_flush);
+        self.add_primitive("load", Block::p_load);
+        self.add_primitive("thru", Block::thru);
+        self.add_primitive("list", Block::p_list);
+        self.add_primitive("blk", Block::blk);
+        self.add_primitive("scr", Block::scr);
+        self.add_primitive("use-block-file", Block::use_block_file);
+    }
+
+    /// Use file `path` as block file, creating it if it does not exist.
+    /// Updated buffers of the previous block file are written back first.
+    fn open_block_file(&mut self, path: &str) -> Result {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| ForthError::io(path, e))?;
+        self.set_block_file(Some(file))
+    }
+
+    /// Replace the block file by `file`. Updated buffers are written back
+    /// to the previous block file and all buffers are unassigned.
+    fn set_block_file(&mut self, file: Option<File>) -> Result {
+        self.save_all_buffers()?;
+        let blocks = self.blocks();
+        blocks.empty();
+        blocks.file = file;
+        Ok(())
+    }
+
+    /// Assign block `u` to a buffer and return the buffer's address. The
+    /// block is read from the block file if `read` is true.
+    fn assign_block(&mut self, u: usize, read: bool) -> Result<usize> {
+        if u == 0 || u > MAX_BLOCK {
+            return Err(ForthError::from(INVALID_BLOCK_NUMBER));
+        }
+        if !self.blocks().has_file() {
+            return Err(ForthError::from(BLOCK_READ_EXCEPTION));
+        }
+        let i = match self.blocks().find(u) {
+            Some(i) => i,
+            None => {
+                let i = self.blocks().victim();
+                self.write_back(i)?;
+                self.blocks().buffers[i].block = 0;
+                if read {
+                    self.read_block(u, i)?;
+                }
+                self.blocks().buffers[i].block = u;
+                i
+            }
+        };
+        self.blocks().touch(i);
+        Ok(self.blocks().address(i))
+    }
+
+    /// Read block `u` into buffer `i`.
+    fn read_block(&mut self, u: usize, i: usize) -> Result {
+        let addr = self.blocks().address(i);
+        let mut file = match self.blocks().file.take() {
+            Some(f) => f,
+            None => return Err(ForthError::from(BLOCK_READ_EXCEPTION)),
+        };
+        let result = {
+            let buf = unsafe {
+                self.data_space()
+                    .buffer_from_raw_parts_mut(addr, BLOCK_SIZE)
+            };
+            let mut result = file.seek(SeekFrom::Start(offset(u))).map(|_| ());
+            let mut n = 0;
+            while result.is_ok() && n < BLOCK_SIZE {
+                match file.read(&mut buf[n..]) {
+                    Ok(0) => break,
+                    Ok(m) => n += m,
+                    Err(e) => result = Err(e),
+                }
+            }
+            for b in &mut buf[n..] {
+                *b = b' ';
+            }
+            result
+        };
+        self.blocks().file = Some(file);
+        result.or(Err(ForthError::from(BLOCK_READ_EXCEPTION)))
+    }
+
+    /// Write buffer `i` back to the block file if it was updated.
+    fn write_back(&mut self, i: usize) -> Result {
+        let b = self.blocks().buffers[i];
+        if b.block == 0 || !b.updated {
+            return Ok(());
+        }
+        let addr = self.blocks().address(i);
+        let mut file = match self.blocks().file.take() {
+            Some(f) => f,
+            None => return Err(ForthError::from(BLOCK_WRITE_EXCEPTION)),
+        };
+        let result = {
+            let buf = unsafe { self.data_space().buffer_from_raw_parts(addr, BLOCK_SIZE) };
+            file.seek(SeekFrom::Start(offset(b.block)))
+                .and_then(|_| file.write_all(buf))
+        };
+        self.blocks().file = Some(file);
+        match result {
+            Ok(_) => {
+                self.blocks().buffers[i].updated = false;
+                Ok(())
+            }
+            Err(_) => Err(ForthError::from(BLOCK_WRITE_EXCEPTION)),
+        }
+    }
+
+    /// Write all updated buffers back to the block file.
+    fn save_all_buffers(&mut self) -> Result {
+        for i in 0..self.blocks().buffer_count() {
+            self.write_back(i)?;
+        }
+        if let Some(ref mut f) = self.blocks().file {
+            f.flush().or(Err(ForthError::from(BLOCK_WRITE_EXCEPTION)))?;
+        }
+        Ok(())
+    }
+
+    /// Interpret block `u`, see LOAD. Errors of the interpretation are left
+    /// in `last_error`.
+    fn load_block(&mut self, u: usize) -> Result {
+        let addr = self.assign_block(u, true)?;
+        // One line per LINE_LENGTH characters, so that `\` ends at the end
+        // of a line of the block.
+        let mut text = String::with_capacity(BLOCK_SIZE + BLOCK_SIZE / LINE_LENGTH);
+        {
+            let buf = unsafe { self.data_space().buffer_from_raw_parts(addr, BLOCK_SIZE) };
+            for line in buf.chunks(LINE_LENGTH) {
+                text.extend(line.iter().map(|&c| c as char));
+                text.push('\n');
+            }
+        }
+        let blk = self.blocks().blk;
+        let saved_blk = unsafe { self.data_space().get_isize(blk) };
+        let saved_source_id = self.source_id();
+        let saved_source_index = self.state().source_index;
+        let ip = self.state().instruction_pointer;
+        self.state().source_id = 0;
+        let saved_buffer = self.input_buffer().take();
+        self.set_input_buffer(text);
+        self.state().source_index = 0;
+        unsafe { self.data_space().put_isize(u as isize, blk) };
+        self.state().instruction_pointer = 0;
+        self.evaluate_input();
+        unsafe { self.data_space().put_isize(saved_blk, blk) };
+        *self.input_buffer() = saved_buffer;
+        self.state().source_id = saved_source_id;
+        self.state().source_index = saved_source_index;
+        if self.last_error().is_none() {
+            self.state().instruction_pointer = ip;
+        }
+        Ok(())
+    }
+
+    // Run-time: ( u -- a-addr )
+    //
+    // a-addr is the address of the first character of the block buffer
+    // assigned to block u. If block u is not already in a buffer, it is
+    // read from the block file, after writing back the buffer to be
+    // reassigned if it was updated.
+    primitive! {fn block(&mut self) {
+        let u = self.s_stack().pop();
+        match self.assign_block(u as usize, true) {
+            Ok(a) => self.s_stack().push(a as isize),
+            Err(e) => self.abort_with(e.code()),
+        }
+    }}
+
+    // Run-time: ( u -- a-addr )
+    //
+    // Like BLOCK, but the block is not read from the block file if it is
+    // not already in a buffer. The contents of the buffer are unspecified.
+    primitive! {fn p_buffer(&mut self) {
+        let u = self.s_stack().pop();
+        match self.assign_block(u as usize, false) {
+            Ok(a) => self.s_stack().push(a as isize),
+            Err(e) => self.abort_with(e.code()),
+        }
+    }}
+
+    // Run-time: ( -- )
+    //
+    // Mark the current block buffer as modified.
+    primitive! {fn p_update(&mut self) {
+        let blocks = self.blocks();
+        if let Some(i) = blocks.current {
+            blocks.buffers[i].updated = true;
+        }
+    }}
+
+    // Run-time: ( -- )
+    //
+    // Write all updated block buffers back to the block file.
+    primitive! {fn save_buffers(&mut self) {
+        if let Err(e) = self.save_all_buffers() {
+            self.abort_with(e.code());
+        }
+    }}
+
+    // Run-time: ( -- )
+    //
+    // Unassign all block buffers without writing them back.
+    primitive! {fn empty_buffers(&mut self) {
+        self.blocks().empty();
+    }}
+
+    // Run-time: ( -- )
+    //
+    // SAVE-BUFFERS, then unassign all block buffers.
+    primitive! {fn p_flush(&mut self) {
+        match self.save_all_buffers() {
+            Ok(_) => self.blocks().empty(),
+            Err(e) => self.abort_with(e.code()),
+        }
+    }}
+
+    // Run-time: ( i*x u -- j*x )
+    //
+    // Save the input source, store u in BLK, interpret block u and restore
+    // the input source.
+    primitive! {fn p_load(&mut self) {
+        let u = self.s_stack().pop();
+        if let Err(e) = self.load_block(u as usize) {
+            self.abort_with(e.code());
+        }
+    }}
+
+    // Run-time: ( i*x u1 u2 -- j*x )
+    //
+    // LOAD blocks u1 through u2 in order.
+    primitive! {fn thru(&mut self) {
+        let (u1, u2) = self.s_stack().pop2();
+        for u in u1..u2.saturating_add(1) {
+            if let Err(e) = self.load_block(u as usize) {
+                self.abort_with(e.code());
+            }
+            if self.last_error().is_some() {
+                break;
+            }
+        }
+    }}
+
+    // Run-time: ( u -- )
+    //
+    // Display block u with line numbers and store u in SCR.
+    primitive! {fn p_list(&mut self) {
+        let u = self.s_stack().pop();
+        match self.assign_block(u as usize, true) {
+            Ok(a) => {
+                let scr = self.blocks().scr;
+                unsafe { self.data_space().put_isize(u, scr) };
+                let mut text = String::with_capacity(BLOCK_SIZE * 2);
+                {
+                    let buf = unsafe { self.data_space().buffer_from_raw_parts(a, BLOCK_SIZE) };
+                    write!(text, "Screen {}", u).unwrap();
+                    for (n, line) in buf.chunks(LINE_LENGTH).enumerate() {
+                        write!(text, "\n{:2} ", n).unwrap();
+                        text.extend(line.iter().map(|&c| c as char));
+                    }
+                    text.push('\n');
+                }
+                self.push_output(&text);
+            }
+            Err(e) => self.abort_with(e.code()),
+        }
+    }}
+
+    // Run-time: ( -- a-addr )
+    //
+    // Address of the number of the block being interpreted, 0 if the input
+    // source is not a block.
+    primitive! {fn blk(&mut self) {
+        let blk = self.blocks().blk;
+        self.s_stack().push(blk as isize);
+    }}
+
+    // Run-time: ( -- a-addr )
+    //
+    // Address of the number of the block most recently LISTed.
+    primitive! {fn scr(&mut self) {
+        let scr = self.blocks().scr;
+        self.s_stack().push(scr as isize);
+    }}
+
+    // Run-time: ( fileid -- )
+    //
+    // Use the file fileid, opened read-write, as block file. Updated
+    // buffers of the previous block file are written back first.
+    //
+    // Like OPEN-SOURCE, the file is owned by the block word set
+    // afterwards, and fileid can no more be used with file access words.
+    primitive! {fn use_block_file(&mut self) {
+        let id = self.s_stack().pop();
+        if id > 0 && id - 1 < self.files().len() as isize {
+            match self.files_mut()[id as usize - 1].take() {
+                Some(handle) => match handle.into_file() {
+                    Ok(file) => {
+                        if let Err(e) = self.set_block_file(Some(file)) {
+                            self.abort_with(e.code());
+                        }
+                    }
+                    Err(_) => self.abort_with(BLOCK_READ_EXCEPTION),
+                },
+                None => self.abort_with(INVALID_NUMERIC_ARGUMENT),
+            }
+        } else {
+            self.abort_with(INVALID_NUMERIC_ARGUMENT);
+        }
+    }}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Block, BLOCK_SIZE};
+    use core::Core;
+    use exception::{
+        BLOCK_READ_EXCEPTION, DICTIONARY_OVERFLOW, INVALID_BLOCK_NUMBER, UNDEFINED_WORD,
+    };
+    use std::env;
+    use std::fs;
+    use vm::VMBuilder;
+
+    fn block_path(name: &str) -> String {
+        env::temp_dir()
+            .join(format!("rtforth-{}-{}.blk", name, ::std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // Block file with `blocks` as text of blocks 1, 2, ...
+    fn block_file(name: &str, blocks: &[&str]) -> String {
+        let path = block_path(name);
+        let mut bytes = Vec::new();
+        for b in blocks {
+            let mut block = b.as_bytes().to_vec();
+            block.resize(BLOCK_SIZE, b' ');
+            bytes.extend(block);
+        }
+        fs::write(&path, &bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_block_update_flush() {
+        let path = block_file("update", &["abc"]);
+        let vm = &mut VMBuilder::new().build();
+        vm.open_block_file(&path).unwrap();
+        vm.set_source("1 block c@  1 block 1+ c@  3 block c@");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [b'a' as isize, b'b' as isize, 32]);
+        vm.s_stack().reset();
+        vm.set_source("char x 1 block c!  update  char y 3 buffer c!  update  save-buffers");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 3 * BLOCK_SIZE);
+        assert_eq!(&bytes[..3], b"xbc");
+        assert_eq!(bytes[2 * BLOCK_SIZE], b'y');
+        // Not updated, so not written.
+        vm.set_source("char z 1 block c!  flush  1 block c@");
+        vm.evaluate_input();
+        assert_eq!(vm.s_stack().pop(), b'x' as isize);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_lru_write_back() {
+        let path = block_file("lru", &["1", "2", "3"]);
+        let vm = &mut VMBuilder::new().block_buffers(2).build();
+        vm.open_block_file(&path).unwrap();
+        vm.set_source("char a 1 block c! update  2 block drop  1 block drop  3 block drop");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        // Block 2 was least recently used, so block 1 is still in a buffer.
+        assert_eq!(fs::read(&path).unwrap()[0], b'1');
+        vm.set_source("2 block drop");
+        vm.evaluate_input();
+        assert_eq!(fs::read(&path).unwrap()[0], b'a');
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_thru() {
+        let path = block_file(
+            "load",
+            &[
+                "1 blk @ \\ 100 comment to the end of the line",
+                ": two 2 ;",
+                "two 3 +",
+            ],
+        );
+        let vm = &mut VMBuilder::new().build();
+        vm.open_block_file(&path).unwrap();
+        vm.set_source("1 load  blk @  2 3 thru  : x 1 load 10 ;  x 20");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [1, 1, 0, 5, 1, 1, 10, 20]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_error() {
+        let path = block_file("load-error", &["1 undefined-word 2"]);
+        let vm = &mut VMBuilder::new().build();
+        vm.open_block_file(&path).unwrap();
+        vm.set_source(": x 1 load 3 ; x 4");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(UNDEFINED_WORD));
+        assert_eq!(vm.s_stack().len(), 0);
+        vm.reset();
+        vm.set_source("5");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [5]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_list() {
+        let path = block_file("list", &["", "hello"]);
+        let vm = &mut VMBuilder::new().build();
+        vm.open_block_file(&path).unwrap();
+        vm.set_output_buffer(String::new());
+        vm.set_source("2 list scr @");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [2]);
+        let output = vm.output_buffer().take().unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 17);
+        assert_eq!(lines[0], "Screen 2");
+        assert_eq!(lines[1].trim_end(), " 0 hello");
+        assert_eq!(lines[16].trim_end(), "15");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_block_buffers_overflow() {
+        let vm = &mut VMBuilder::new()
+            .data_capacity(0x10000)
+            .block_buffers(100)
+            .core_fs(false)
+            .build();
+        assert_eq!(vm.last_error(), Some(DICTIONARY_OVERFLOW));
+        assert!(vm.find("block").is_none());
+    }
+
+    #[test]
+    fn test_block_errors() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source("1 block");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(BLOCK_READ_EXCEPTION));
+        let path = block_path("errors");
+        vm.open_block_file(&path).unwrap();
+        vm.reset();
+        vm.set_source("0 block");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_BLOCK_NUMBER));
+        vm.reset();
+        vm.set_source(&format!(
+            ": path s\" {}\" ;  path r/w open-file drop use-block-file  5 block c@",
+            path
+        ));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [32]);
+        fs::remove_file(&path).unwrap();
+    }
+}
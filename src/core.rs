@@ -1,21 +1,27 @@
 extern crate libc;
+use block::Blocks;
 use exception::{
-    self, ABORT, CONTROL_STRUCTURE_MISMATCH, DIVISION_BY_ZERO, FLOATING_POINT_STACK_OVERFLOW,
-    FLOATING_POINT_STACK_UNDERFLOW, FLOATING_POINT_UNIDENTIFIED_FAULT, INTEGER_UNIDENTIFIED_FAULT,
+    self, ABORT, CONTROL_STRUCTURE_MISMATCH, DICTIONARY_OVERFLOW, DIVISION_BY_ZERO,
+    FLOATING_POINT_STACK_OVERFLOW, FLOATING_POINT_STACK_UNDERFLOW,
     INTERPRETING_A_COMPILE_ONLY_WORD, INVALID_EXECUTION_TOKEN, INVALID_MEMORY_ADDRESS,
     INVALID_NUMERIC_ARGUMENT, RESULT_OUT_OF_RANGE, RETURN_STACK_OVERFLOW, RETURN_STACK_UNDERFLOW,
-    SEARCH_ORDER_OVERFLOW, STACK_OVERFLOW, STACK_UNDERFLOW, UNDEFINED_WORD, UNEXPECTED_END_OF_FILE,
+    SANDBOX_DATA_LIMIT, SANDBOX_DEFINITION, SANDBOX_INSTRUCTION_LIMIT, SEARCH_ORDER_OVERFLOW,
+    STACK_OVERFLOW, STACK_UNDERFLOW, UNDEFINED_WORD, UNEXPECTED_END_OF_FILE,
+    WRITE_TO_A_READ_ONLY_LOCATION,
 };
+use file_access::FileHandle;
 use hibitset::{BitSet, BitSetLike};
-use loader::Source;
+use loader::{Includes, Source};
 use memory::{DataSpace, Memory};
+use output::Sink;
 use parser;
+use sandbox::Sandbox;
 use std::fmt::Write;
 use std::fmt::{self, Display};
-use std::fs::File;
 use std::mem;
 use std::ops::{Index, IndexMut};
 use std::str;
+use units::{Dimension, DimensionTag, Tag};
 use {FALSE, NUM_TASKS, TRUE};
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -36,20 +42,27 @@ pub enum WordType {
     Does,
     // Words defined with Marker
     Marker,
+    // Words implemented with a rust closure in the closure table
+    Closure,
+    // Words defined with UNIT: or `Units::add_unit`
+    Unit,
 }
 
+/// Rust closure used as the action of a word, see `Core::add_closure`.
+pub type Closure<Target> = Box<dyn FnMut(&mut Target) + Send>;
+
 // Word
 pub struct Word<Target> {
-    word_type: WordType,
-    wordlist: usize,
-    is_immediate: bool,
-    is_compile_only: bool,
-    hidden: bool,
-    link: usize,
-    hash: u32,
-    nfa: usize,
-    dfa: usize,
-    doer: usize,
+    pub(crate) word_type: WordType,
+    pub(crate) wordlist: usize,
+    pub(crate) is_immediate: bool,
+    pub(crate) is_compile_only: bool,
+    pub(crate) hidden: bool,
+    pub(crate) link: usize,
+    pub(crate) hash: u32,
+    pub(crate) nfa: usize,
+    pub(crate) dfa: usize,
+    pub(crate) doer: usize,
     pub action: primitive! { fn (&mut Target) },
     pub compilation_semantics: primitive! { fn(&mut Target) },
     // Minimum execution time in [ns]
@@ -137,18 +150,18 @@ impl<Target> Word<Target> {
 pub const WORDLISTS: usize = 10;
 pub const FORTH_WORDLIST: usize = 0;
 pub const OPTIMIZER_WORDLIST: usize = 1;
-const BUCKET_SIZE: usize = 64;
+pub(crate) const BUCKET_SIZE: usize = 64;
 
 /// Wordlist
 pub struct Wordlist<Target> {
-    words: Vec<Word<Target>>,
-    buckets: [usize; BUCKET_SIZE * WORDLISTS],
-    temp_buckets: [usize; BUCKET_SIZE * WORDLISTS],
+    pub(crate) words: Vec<Word<Target>>,
+    pub(crate) buckets: [usize; BUCKET_SIZE * WORDLISTS],
+    pub(crate) temp_buckets: [usize; BUCKET_SIZE * WORDLISTS],
     pub(crate) search_order: [usize; WORDLISTS],
     pub(crate) search_order_len: usize,
     pub(crate) current: usize,
     pub(crate) last_wordlist: usize,
-    last: usize,
+    pub(crate) last: usize,
 }
 
 impl<Target> Wordlist<Target> {
@@ -232,63 +245,253 @@ impl<Target> IndexMut<usize> for Wordlist<Target> {
     }
 }
 
-pub struct Stack<T: Default> {
+/// Maximum depth of a stack.
+pub const MAX_STACK_DEPTH: usize = 128;
+
+/// Default depth of a stack.
+pub const DEFAULT_STACK_DEPTH: usize = 64;
+
+/// Stack of a task
+///
+/// Elements are kept in a ring of 256, so that indexing with a `u8` never
+/// leaves the array. Depths up to `capacity()` are valid, depths above it up
+/// to `MAX_STACK_DEPTH` are overflows, and larger ones are underflows.
+///
+/// Primitives may change `len` directly. Such errors are found afterwards
+/// by `overflow` and `underflow` with the help of canaries. With feature
+/// `checked-stacks`, `push` and `pop` and their variants also check every
+/// access. They neither write beyond the capacity nor wrap below zero, and
+/// return the same values as without the feature. A push onto a full
+/// stack is not performed, and the inner interpreter aborts with the
+/// overflow right after the word which pushed.
+pub struct Stack<T: Default, D = NoDims> {
     pub inner: [T; 256],
     pub len: u8,
     pub canary: T,
+    cap: u8,
+    #[cfg(feature = "checked-stacks")]
+    overflowed: bool,
+    #[cfg(feature = "checked-stacks")]
+    underflowed: bool,
+    dims: D,
+}
+
+/// Dimensions of the elements of a stack, indexed like `Stack::inner`.
+pub trait StackDims {
+    fn new() -> Self;
+    fn get(&self, i: u8) -> Tag;
+    fn set(&mut self, i: u8, tag: Tag);
+}
+
+/// Dimensions of a stack other than the floating-point stack, which are
+/// not kept.
+pub struct NoDims;
+
+impl StackDims for NoDims {
+    #[inline(always)]
+    fn new() -> Self {
+        NoDims
+    }
+
+    #[inline(always)]
+    fn get(&self, _i: u8) -> Tag {
+        Tag::default()
+    }
+
+    #[inline(always)]
+    fn set(&mut self, _i: u8, _tag: Tag) {}
 }
 
-impl<T: Default + Copy + PartialEq + Display> Stack<T> {
+/// Dimensions of the floating-point stack. Zero-sized without feature
+/// `checked-units`.
+pub struct FloatDims([Tag; 256]);
+
+impl StackDims for FloatDims {
+    #[inline(always)]
+    fn new() -> Self {
+        FloatDims([Tag::default(); 256])
+    }
+
+    #[inline(always)]
+    fn get(&self, i: u8) -> Tag {
+        self.0[i as usize]
+    }
+
+    #[inline(always)]
+    fn set(&mut self, i: u8, tag: Tag) {
+        self.0[i as usize] = tag;
+    }
+}
+
+impl<T: Default + Copy + PartialEq + Display, D: StackDims> Stack<T, D> {
     pub fn new(canary: T) -> Self {
+        Self::with_capacity(canary, DEFAULT_STACK_DEPTH)
+    }
+
+    /// Stack of depth `cap`, which is limited to 1..=MAX_STACK_DEPTH.
+    pub fn with_capacity(canary: T, cap: usize) -> Self {
         let mut result = Stack {
             inner: [T::default(); 256],
             len: 0,
             canary,
+            cap: 1,
+            #[cfg(feature = "checked-stacks")]
+            overflowed: false,
+            #[cfg(feature = "checked-stacks")]
+            underflowed: false,
+            dims: D::new(),
         };
-        result.reset();
+        result.set_capacity(cap);
         result
     }
 
+    /// Maximum depth.
+    pub fn capacity(&self) -> usize {
+        self.cap as usize
+    }
+
+    /// Set maximum depth to `cap`, limited to 1..=MAX_STACK_DEPTH, and
+    /// reset the stack.
+    pub fn set_capacity(&mut self, cap: usize) {
+        self.cap = if cap < 1 {
+            1
+        } else if cap > MAX_STACK_DEPTH {
+            MAX_STACK_DEPTH as u8
+        } else {
+            cap as u8
+        };
+        self.reset();
+    }
+
     pub fn reset(&mut self) {
         self.len = 0;
         for i in 0..256 {
             self.inner[i] = self.canary;
         }
+        #[cfg(feature = "checked-stacks")]
+        {
+            self.overflowed = false;
+            self.underflowed = false;
+        }
+        self.dims = D::new();
+    }
+
+    /// Dimension of the element at `depth`, 0 being the top of the stack.
+    /// Pushed elements are of unknown dimension. Only the floating-point
+    /// stack keeps dimensions, see `FloatDims`.
+    #[inline(always)]
+    pub fn dim(&self, depth: u8) -> Tag {
+        self.dims.get(self.len.wrapping_sub(depth.wrapping_add(1)))
     }
 
+    /// Set the dimension of the element at `depth`.
+    #[inline(always)]
+    pub fn set_dim(&mut self, depth: u8, tag: Tag) {
+        self.dims
+            .set(self.len.wrapping_sub(depth.wrapping_add(1)), tag);
+    }
+
+    #[cfg(not(feature = "checked-stacks"))]
+    pub fn underflow(&self) -> bool {
+        (self.inner[255] != self.canary) || (self.len as usize > MAX_STACK_DEPTH)
+    }
+
+    #[cfg(feature = "checked-stacks")]
     pub fn underflow(&self) -> bool {
-        (self.inner[255] != self.canary) || (self.len > 128)
+        self.underflowed
+            || (self.inner[255] != self.canary)
+            || (self.len as usize > MAX_STACK_DEPTH)
+    }
+
+    #[cfg(not(feature = "checked-stacks"))]
+    pub fn overflow(&self) -> bool {
+        (self.inner[self.cap as usize] != self.canary)
+            || (self.len > self.cap && self.len as usize <= MAX_STACK_DEPTH)
     }
 
+    #[cfg(feature = "checked-stacks")]
     pub fn overflow(&self) -> bool {
-        (self.inner[64] != self.canary) || (self.len > 64 && self.len <= 128)
+        self.overflowed
+            || (self.inner[self.cap as usize] != self.canary)
+            || (self.len > self.cap && self.len as usize <= MAX_STACK_DEPTH)
+    }
+
+    // Can `n` more elements be pushed? Records an overflow if not.
+    #[cfg(feature = "checked-stacks")]
+    #[inline(always)]
+    fn check_push(&mut self, n: u8) -> bool {
+        if self.len as usize + n as usize <= self.cap as usize {
+            true
+        } else {
+            self.overflowed = true;
+            false
+        }
+    }
+
+    #[cfg(not(feature = "checked-stacks"))]
+    #[inline(always)]
+    fn check_push(&mut self, _n: u8) -> bool {
+        true
+    }
+
+    // Length after popping `n` elements. Records an underflow instead of
+    // wrapping around.
+    #[cfg(feature = "checked-stacks")]
+    #[inline(always)]
+    fn popped(&mut self, n: u8) -> u8 {
+        if n <= self.len {
+            self.len - n
+        } else {
+            self.underflowed = true;
+            0
+        }
+    }
+
+    #[cfg(not(feature = "checked-stacks"))]
+    #[inline(always)]
+    fn popped(&mut self, n: u8) -> u8 {
+        self.len.wrapping_sub(n)
     }
 
     pub fn push(&mut self, v: T) {
-        let len = self.len.wrapping_add(1);
-        self.len = len;
-        self.inner[len.wrapping_sub(1) as usize] = v;
+        if self.check_push(1) {
+            let len = self.len.wrapping_add(1);
+            self.len = len;
+            self.inner[len.wrapping_sub(1) as usize] = v;
+            self.set_dim(0, Tag::default());
+        }
     }
 
     pub fn pop(&mut self) -> T {
         let result = self.inner[self.len.wrapping_sub(1) as usize];
-        self.len = self.len.wrapping_sub(1);
+        self.len = self.popped(1);
         result
     }
 
     pub fn push2(&mut self, v1: T, v2: T) {
+        if !self.check_push(2) {
+            return;
+        }
         let len = self.len.wrapping_add(2);
         self.len = len;
         self.inner[self.len.wrapping_sub(2) as usize] = v1;
         self.inner[self.len.wrapping_sub(1) as usize] = v2;
+        self.set_dim(1, Tag::default());
+        self.set_dim(0, Tag::default());
     }
 
     pub fn push3(&mut self, v1: T, v2: T, v3: T) {
+        if !self.check_push(3) {
+            return;
+        }
         let len = self.len.wrapping_add(3);
         self.len = len;
         self.inner[self.len.wrapping_sub(3) as usize] = v1;
         self.inner[self.len.wrapping_sub(2) as usize] = v2;
         self.inner[self.len.wrapping_sub(1) as usize] = v3;
+        self.set_dim(2, Tag::default());
+        self.set_dim(1, Tag::default());
+        self.set_dim(0, Tag::default());
     }
 
     pub fn pop2(&mut self) -> (T, T) {
@@ -296,7 +499,7 @@ impl<T: Default + Copy + PartialEq + Display> Stack<T> {
             self.inner[self.len.wrapping_sub(2) as usize],
             self.inner[self.len.wrapping_sub(1) as usize],
         );
-        self.len = self.len.wrapping_sub(2);
+        self.len = self.popped(2);
         result
     }
 
@@ -306,7 +509,7 @@ impl<T: Default + Copy + PartialEq + Display> Stack<T> {
             self.inner[self.len.wrapping_sub(2) as usize],
             self.inner[self.len.wrapping_sub(1) as usize],
         );
-        self.len = self.len.wrapping_sub(3);
+        self.len = self.popped(3);
         result
     }
 
@@ -326,6 +529,28 @@ impl<T: Default + Copy + PartialEq + Display> Stack<T> {
         self.len == 0
     }
 
+    /// Shorten the stack to `depth` elements, discarding an overflow.
+    /// A stack shallower than `depth` is left as it is.
+    pub fn truncate(&mut self, depth: usize) {
+        if depth <= self.len as usize && depth <= self.cap as usize {
+            self.len = depth as u8;
+            self.inner[self.cap as usize] = self.canary;
+            #[cfg(feature = "checked-stacks")]
+            {
+                self.overflowed = false;
+            }
+        }
+    }
+
+    /// Replace the elements with `values`, of unknown dimension, discarding
+    /// an overflow or underflow.
+    pub fn restore(&mut self, values: &[T]) {
+        self.reset();
+        for &v in values {
+            self.push(v);
+        }
+    }
+
     /// # Safety
     /// Because the implementer (me) is still learning Rust, it is uncertain if as_slice is safe.
     pub fn as_slice(&self) -> &[T] {
@@ -348,7 +573,7 @@ impl IndexMut<u8> for Stack<isize> {
     }
 }
 
-impl Index<u8> for Stack<f64> {
+impl Index<u8> for Stack<f64, FloatDims> {
     type Output = f64;
     #[inline(always)]
     fn index(&self, index: u8) -> &f64 {
@@ -356,7 +581,7 @@ impl Index<u8> for Stack<f64> {
     }
 }
 
-impl IndexMut<u8> for Stack<f64> {
+impl IndexMut<u8> for Stack<f64, FloatDims> {
     #[inline(always)]
     fn index_mut(&mut self, index: u8) -> &mut f64 {
         &mut self.inner[index as usize]
@@ -389,7 +614,7 @@ impl fmt::Debug for Stack<isize> {
     }
 }
 
-impl fmt::Debug for Stack<f64> {
+impl fmt::Debug for Stack<f64, FloatDims> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.len == 0 {
         } else {
@@ -427,6 +652,8 @@ pub struct ForwardReferences {
     pub idx__postpone: usize,
     pub idx_to_r: usize,
     pub idx__does: usize,
+    /// Transient buffers of S", see `Output::s_quote`.
+    pub s_quote_buffers: usize,
 }
 
 impl ForwardReferences {
@@ -450,10 +677,24 @@ impl ForwardReferences {
             idx__postpone: 0,
             idx_to_r: 0,
             idx__does: 0,
+            s_quote_buffers: 0,
         }
     }
 }
 
+/// Status returned by the bounded inner interpreters `run_for` and `run_until`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunStatus {
+    /// Step budget or time slice used up, the program can be continued.
+    Yielded,
+    /// Current task executed `pause` and another task was resumed.
+    Paused,
+    /// Instruction pointer left the data space.
+    Finished,
+    /// An exception was raised.
+    Error(isize),
+}
+
 pub struct State {
     pub is_compiling: bool,
     pub instruction_pointer: usize,
@@ -530,6 +771,10 @@ pub trait Core: Sized {
     fn output_buffer(&mut self) -> &mut Option<String>;
     /// Set `output_buffer` to `Some(buffer)`.
     fn set_output_buffer(&mut self, buffer: String);
+    /// Sink written by FLUSH-OUTPUT, see `output::Sink`.
+    fn output_sink(&mut self) -> &mut Box<dyn Sink>;
+    /// Sink written by FLUSH-TO-ERR.
+    fn error_sink(&mut self) -> &mut Box<dyn Sink>;
     /// Input source identifier
     ///
     /// > 0: input from source at `self.sources[source_id] and input buffer
@@ -540,23 +785,31 @@ pub trait Core: Sized {
     fn input_buffer(&mut self) -> &mut Option<String>;
     /// Set `input_buffer` to `Some(buffer)`.
     fn set_input_buffer(&mut self, buffer: String);
-    fn files(&self) -> &Vec<Option<File>>;
-    fn files_mut(&mut self) -> &mut Vec<Option<File>>;
+    fn files(&self) -> &Vec<Option<FileHandle>>;
+    fn files_mut(&mut self) -> &mut Vec<Option<FileHandle>>;
     fn sources(&self) -> &Vec<Option<Source>>;
     fn sources_mut(&mut self) -> &mut Vec<Option<Source>>;
     fn lines(&self) -> &Vec<Option<String>>;
     fn lines_mut(&mut self) -> &mut Vec<Option<String>>;
+    /// Files included and library path, see `HasLoader::resolve_include`.
+    fn includes(&mut self) -> &mut Includes;
     fn last_token(&mut self) -> &mut Option<String>;
     fn set_last_token(&mut self, buffer: String);
     fn s_stack(&mut self) -> &mut Stack<isize>;
     fn r_stack(&mut self) -> &mut Stack<isize>;
     fn c_stack(&mut self) -> &mut Stack<Control>;
-    fn f_stack(&mut self) -> &mut Stack<f64>;
+    fn f_stack(&mut self) -> &mut Stack<f64, FloatDims>;
     /// Last definition, 0 if last define fails.
     fn wordlist_mut(&mut self) -> &mut Wordlist<Self>;
     fn wordlist(&self) -> &Wordlist<Self>;
     fn state(&mut self) -> &mut State;
     fn references(&mut self) -> &mut ForwardReferences;
+    /// Closures of words added by `add_closure`.
+    fn closures(&mut self) -> &mut Vec<Option<Closure<Self>>>;
+    /// Sandbox of the current task, see `set_sandbox`.
+    fn sandbox(&mut self) -> &mut Option<Sandbox>;
+    /// Block file and buffers, see `block::Block`.
+    fn blocks(&mut self) -> &mut Blocks;
     fn system_time_ns(&self) -> u64;
     /// Current task
     fn current_task(&self) -> usize;
@@ -564,6 +817,10 @@ pub trait Core: Sized {
     ///
     /// No operation if there is no task `i`.
     fn set_current_task(&mut self, i: usize);
+    /// Number of tasks
+    fn task_count(&self) -> usize {
+        NUM_TASKS
+    }
     /// Is task `i` awake?
     ///
     /// False if there is no task `i`.
@@ -609,7 +866,7 @@ pub trait Core: Sized {
         self.add_compile_only("2>r", Core::two_to_r);
         self.add_compile_only("2r>", Core::two_r_from);
         self.add_compile_only("2r@", Core::two_r_fetch);
-        self.add_compile_only("compile,", Core::compile_comma);
+        self.add_compile_only("compile,", Core::p_compile_comma);
         self.add_compile_only("_postpone", Core::_postpone);
         self.add_compile_only("_does", Core::_does);
 
@@ -642,6 +899,7 @@ pub trait Core: Sized {
         self.add_primitive("!", Core::store);
         self.add_primitive("char+", Core::char_plus);
         self.add_primitive("here", Core::here);
+        self.add_primitive("unused", Core::unused);
         self.add_primitive("allot", Core::allot);
         self.add_primitive("aligned", Core::aligned);
         self.add_primitive("aligned16", Core::aligned16);
@@ -816,6 +1074,59 @@ pub trait Core: Sized {
         self.wordlist_mut().push(name, word);
     }
 
+    /// Add a word implemented with closure `f` to word list.
+    ///
+    /// Unlike `add_primitive`, `f` can capture state, for example a
+    /// hardware handle or a channel sender. The closure is kept in a side
+    /// table and the index into the table is stored in the data field of
+    /// the word. A closure cannot execute itself recursively, and is
+    /// dropped when a marker removes its word. Abort with
+    /// `DICTIONARY_OVERFLOW` if there is no space for the word.
+    fn add_closure<F>(&mut self, name: &str, f: F)
+    where
+        F: FnMut(&mut Self) + Send + 'static,
+    {
+        // Length of name, name, padding and the index of the closure.
+        if !self.check_space(name.len() + 3 * mem::size_of::<usize>()) {
+            return;
+        }
+        let i = self.closures().len();
+        self.closures().push(Some(Box::new(f)));
+        let nfa = self.data_space().compile_str(name);
+        self.data_space().align();
+        let word = Word::new(
+            WordType::Closure,
+            self.wordlist().current,
+            Core::p_closure,
+            Core::compile_comma,
+            nfa,
+            self.data_space().here(),
+        );
+        self.data_space().compile_usize(i);
+        self.wordlist_mut().push(name, word);
+    }
+
+    // Run-time of words added by `add_closure`.
+    primitive! {fn p_closure(&mut self) {
+        let wp = self.state().word_pointer;
+        let dfa = self.wordlist()[wp].dfa();
+        let i = unsafe{ self.data_space().get_usize(dfa) };
+        let f = match self.closures().get_mut(i) {
+            Some(f) => f.take(),
+            None => None,
+        };
+        match f {
+            Some(mut f) => {
+                f(self);
+                // The closure may have removed its own word with a marker.
+                if let Some(slot) = self.closures().get_mut(i) {
+                    *slot = Some(f);
+                }
+            }
+            None => self.abort_with(INVALID_EXECUTION_TOKEN),
+        }
+    }}
+
     /// Set the last definition immediate.
     primitive! {fn immediate(&mut self) {
         let def = self.wordlist().last;
@@ -852,6 +1163,14 @@ pub trait Core: Sized {
         self.state().word_pointer = i;
         if i < self.wordlist().len() {
             (self.wordlist()[i].action())(self);
+            // Report the first overflow or underflow right after the word
+            // causing it, not at the end of the evaluation.
+            #[cfg(feature = "checked-stacks")]
+            {
+                if self.last_error().is_none() {
+                    self.check_stacks();
+                }
+            }
         } else {
             self.abort_with(INVALID_EXECUTION_TOKEN);
         }
@@ -873,7 +1192,13 @@ pub trait Core: Sized {
                             let nfa = self.wordlist()[w].nfa();
                             let w_name = unsafe { self.data_space().get_str(nfa) };
                             if w_name.eq_ignore_ascii_case(name) {
-                                return Some(w);
+                                let allowed = match *self.sandbox() {
+                                    Some(ref sandbox) => sandbox.allows_xt(w),
+                                    None => true,
+                                };
+                                if allowed {
+                                    return Some(w);
+                                }
                             }
                         }
                     }
@@ -888,14 +1213,27 @@ pub trait Core: Sized {
     // Token threaded code
     // -------------------------------
 
+    /// Is there a cell of threaded code at `ip` in data space?
+    #[inline]
+    fn ip_in_bounds(&self, ip: usize) -> bool {
+        let data_space = self.data_space_const();
+        data_space.start() <= ip && ip + mem::size_of::<isize>() <= data_space.limit()
+    }
+
     /// Evaluate a compiled program following self.state().instruction_pointer.
     /// Any exception causes termination of inner loop.
     #[inline(never)]
     fn run(&mut self) {
+        let limited = match *self.sandbox() {
+            Some(ref sandbox) => sandbox.limits_instructions(),
+            None => false,
+        };
+        if limited {
+            self.run_sandboxed();
+            return;
+        }
         let mut ip = self.state().instruction_pointer;
-        while self.data_space().start() <= ip
-            && ip + mem::size_of::<isize>() <= self.data_space().limit()
-        {
+        while self.ip_in_bounds(ip) {
             let w = unsafe { self.data_space().get_isize(ip) as usize };
             self.state().instruction_pointer += mem::size_of::<isize>();
             self.execute_word(w);
@@ -903,27 +1241,146 @@ pub trait Core: Sized {
         }
     }
 
+    /// `run` counting instructions against the limit of the sandbox.
+    fn run_sandboxed(&mut self) {
+        let mut ip = self.state().instruction_pointer;
+        while self.ip_in_bounds(ip) {
+            if self.count_instruction() {
+                let w = unsafe { self.data_space().get_isize(ip) as usize };
+                self.state().instruction_pointer += mem::size_of::<isize>();
+                self.execute_word(w);
+            }
+            ip = self.state().instruction_pointer;
+        }
+    }
+
+    /// Count an instruction against the limit of the sandbox, if any.
+    ///
+    /// Returns false if the limit is exceeded, after aborting with
+    /// `SANDBOX_INSTRUCTION_LIMIT`. If the error is not handled, the
+    /// instruction pointer is cleared to stop the program.
+    fn count_instruction(&mut self) -> bool {
+        let within_limit = match *self.sandbox() {
+            Some(ref mut sandbox) => sandbox.count(),
+            None => true,
+        };
+        if !within_limit {
+            // Give the handler a fresh budget.
+            if let Some(ref mut sandbox) = *self.sandbox() {
+                sandbox.executed = 0;
+            }
+            self.abort_with(SANDBOX_INSTRUCTION_LIMIT);
+            if self.last_error().is_some() {
+                // Not handled, stop the endless loop.
+                self.state().instruction_pointer = 0;
+            }
+        }
+        within_limit
+    }
+
+    /// Execute the word at self.state().instruction_pointer.
+    ///
+    /// The word is counted against the instruction limit of the sandbox
+    /// as in `run`.
+    ///
+    /// Returns `RunStatus::Yielded` if the caller may continue with the next
+    /// step. An error left from before the step is cleared, so that
+    /// `RunStatus::Error` always comes from this step.
+    fn step(&mut self) -> RunStatus {
+        self.set_error(None);
+        let ip = self.state().instruction_pointer;
+        if self.ip_in_bounds(ip) {
+            let task = self.current_task();
+            if self.count_instruction() {
+                let w = unsafe { self.data_space().get_isize(ip) as usize };
+                self.state().instruction_pointer += mem::size_of::<isize>();
+                self.execute_word(w);
+            }
+            if let Some(e) = self.last_error() {
+                RunStatus::Error(e)
+            } else if self.current_task() != task {
+                RunStatus::Paused
+            } else {
+                RunStatus::Yielded
+            }
+        } else {
+            RunStatus::Finished
+        }
+    }
+
+    /// Evaluate at most `steps` words of the compiled program following
+    /// self.state().instruction_pointer.
+    ///
+    /// Different from `run`, control returns to the caller when the
+    /// budget is used up, when the current task pauses, or when an
+    /// exception is raised. Call `run_for` again in the next cycle to
+    /// continue. Upon `RunStatus::Error(e)` the error handler has already
+    /// been executed and the error is still in `last_error`.
+    fn run_for(&mut self, steps: usize) -> RunStatus {
+        for _ in 0..steps {
+            match self.step() {
+                RunStatus::Yielded => {}
+                status => return status,
+            }
+        }
+        let ip = self.state().instruction_pointer;
+        if self.ip_in_bounds(ip) {
+            RunStatus::Yielded
+        } else {
+            RunStatus::Finished
+        }
+    }
+
+    /// Evaluate the compiled program following
+    /// self.state().instruction_pointer until `system_time_ns()` reaches
+    /// `deadline_ns`.
+    ///
+    /// See `run_for` for the returned status.
+    ///
+    /// The clock is only read between steps. With a clock which does not
+    /// advance by itself, such as `vm::Clock::Manual`, a deadline later
+    /// than `system_time_ns()` is never reached and `run_until` only
+    /// returns when the program finishes, pauses or fails. Use `run_for`
+    /// with such clocks.
+    fn run_until(&mut self, deadline_ns: u64) -> RunStatus {
+        while self.system_time_ns() < deadline_ns {
+            match self.step() {
+                RunStatus::Yielded => {}
+                status => return status,
+            }
+        }
+        let ip = self.state().instruction_pointer;
+        if self.ip_in_bounds(ip) {
+            RunStatus::Yielded
+        } else {
+            RunStatus::Finished
+        }
+    }
+
     primitive! {fn compile_var(&mut self) {
         let compile_var_vector = self.data_space().system_variables().compile_var_vector();
         unsafe {
-            let compile_var_vector: *const primitive!{fn (&mut Self)} = mem::transmute (compile_var_vector);
-            (*compile_var_vector)(self);
+            let compile_var_vector: primitive!{fn (&mut Self)} =
+                mem::transmute(self.data_space().get_usize(compile_var_vector));
+            compile_var_vector(self);
         }
     }}
 
     primitive! {fn compile_const(&mut self) {
         let compile_const_vector = self.data_space().system_variables().compile_const_vector();
         unsafe {
-            let compile_const_vector: *const primitive!{fn (&mut Self)} = mem::transmute (compile_const_vector);
-            (*compile_const_vector)(self);
+            let compile_const_vector: primitive!{fn (&mut Self)} =
+                mem::transmute(self.data_space().get_usize(compile_const_vector));
+            compile_const_vector(self);
         }
     }}
 
     primitive! {fn compile_fconst(&mut self) {
         let compile_fconst_vector = self.data_space().system_variables().compile_fconst_vector();
         unsafe {
-            let compile_fconst_vector: *const primitive!{fn (&mut Self)} = mem::transmute (compile_fconst_vector);
-            (*compile_fconst_vector)(self);
+            let compile_fconst_vector: primitive!{fn (&mut Self)} =
+                mem::transmute(self.data_space().get_usize(compile_fconst_vector));
+            compile_fconst_vector(self);
         }
     }}
 
@@ -942,6 +1399,9 @@ pub trait Core: Sized {
     ///
     /// Run-time: ( n -- )
     primitive! {fn tt_compile_integer(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let n = self.s_stack().pop();
         let idx = self.references().idx_lit;
         self.s_stack().push(idx as isize);
@@ -955,8 +1415,9 @@ pub trait Core: Sized {
     primitive! {fn compile_integer(&mut self) {
         let compile_integer_vector = self.data_space().system_variables().compile_integer_vector();
         unsafe {
-            let compile_integer_vector: *const primitive!{fn (&mut Self)} = mem::transmute (compile_integer_vector);
-            (*compile_integer_vector)(self);
+            let compile_integer_vector: primitive!{fn (&mut Self)} =
+                mem::transmute(self.data_space().get_usize(compile_integer_vector));
+            compile_integer_vector(self);
         }
     }}
 
@@ -966,6 +1427,7 @@ pub trait Core: Sized {
         let flen = self.f_stack().len.wrapping_add(1);
         self.f_stack().len = flen;
         self.f_stack()[flen.wrapping_sub(1)] = v;
+        self.f_stack().set_dim(0, Tag::known(Dimension::NONE));
         self.state().instruction_pointer = ip + mem::size_of::<f64>();
     }}
 
@@ -975,6 +1437,10 @@ pub trait Core: Sized {
     ///
     /// Run-time: ( f -- )
     primitive! {fn tt_compile_float(&mut self) {
+        // One cell for flit, and a float possibly after padding.
+        if !self.check_space(mem::size_of::<isize>() + 2 * mem::size_of::<f64>()) {
+            return;
+        }
         let f = self.f_stack().pop();
         let idx_flit = self.references().idx_flit;
         self.s_stack().push(idx_flit as isize);
@@ -989,18 +1455,17 @@ pub trait Core: Sized {
     primitive! {fn compile_float(&mut self) {
         let compile_float_vector = self.data_space().system_variables().compile_float_vector();
         unsafe {
-            let compile_float_vector: *const primitive!{fn (&mut Self)} = mem::transmute (compile_float_vector);
-            (*compile_float_vector)(self);
+            let compile_float_vector: primitive!{fn (&mut Self)} =
+                mem::transmute(self.data_space().get_usize(compile_float_vector));
+            compile_float_vector(self);
         }
     }}
 
     /// Runtime of S"
     primitive! {fn p_s_quote(&mut self) {
         let ip = self.state().instruction_pointer;
-        let (addr, cnt) = {
-            let s = unsafe{ self.data_space().get_str(ip) };
-            (s.as_ptr() as isize, s.len() as isize)
-        };
+        let cnt = unsafe{ self.data_space().get_usize(ip) as isize };
+        let addr = (ip + mem::size_of::<usize>()) as isize;
         let slen = self.s_stack().len.wrapping_add(2);
         self.s_stack().len = slen;
         self.s_stack()[slen.wrapping_sub(1)] = cnt;
@@ -1247,6 +1712,9 @@ pub trait Core: Sized {
     ///         ip
     ///
     primitive! {fn compile_if(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let here = self.compile_zero_branch(0);
         self.c_stack().push(Control::If(here));
     }}
@@ -1264,6 +1732,9 @@ pub trait Core: Sized {
     ///             ip               +-------+
     ///
     primitive! {fn compile_else(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let if_part = match self.c_stack().pop() {
             Control::If(if_part) => if_part,
             _ => {
@@ -1329,6 +1800,9 @@ pub trait Core: Sized {
     }}
 
     primitive! {fn compile_of(&mut self) {
+        if !self.check_space(5 * mem::size_of::<isize>()) {
+            return;
+        }
         match self.c_stack().pop() {
             Control::Case => {
                 self.c_stack().push(Control::Case);
@@ -1359,6 +1833,9 @@ pub trait Core: Sized {
     }}
 
     primitive! {fn compile_endof(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let of_part = match self.c_stack().pop() {
             Control::Of(of_part) => {
                 of_part
@@ -1381,6 +1858,9 @@ pub trait Core: Sized {
     }}
 
     primitive! {fn compile_endcase(&mut self) {
+        if !self.check_space(mem::size_of::<isize>()) {
+            return;
+        }
         let idx = self.references().idx_drop;
         self.s_stack().push(idx as isize);
         self.compile_comma();
@@ -1433,6 +1913,9 @@ pub trait Core: Sized {
     ///   +------------------------------+
     ///
     primitive! {fn compile_while(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let here = self.compile_zero_branch(0);
         self.c_stack().push(Control::While(here));
     }}
@@ -1441,6 +1924,9 @@ pub trait Core: Sized {
     ///
     /// Continue execution at the location following `begin`.
     primitive! {fn compile_repeat(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let (begin_part, while_part) = match self.c_stack().pop2() {
             (Control::Begin(begin_part), Control::While(while_part)) => {
                 (begin_part, while_part)
@@ -1475,6 +1961,9 @@ pub trait Core: Sized {
     ///   +-------------+
     ///
     primitive! {fn compile_until(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let begin_part = match self.c_stack().pop() {
             Control::Begin(begin_part) => begin_part,
             _ => {
@@ -1503,6 +1992,9 @@ pub trait Core: Sized {
     ///   +------------+
     ///
     primitive! {fn compile_again(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let begin_part = match self.c_stack().pop() {
             Control::Begin(begin_part) => begin_part,
             _ => {
@@ -1637,6 +2129,9 @@ pub trait Core: Sized {
     /// Control::Do(here, here)
     ///
     primitive! {fn compile_do(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let idx = self.references().idx__do;
         self.s_stack().push(idx as isize);
         self.compile_comma();
@@ -1666,6 +2161,9 @@ pub trait Core: Sized {
     /// Control::Do(here, here)
     ///
     primitive! {fn compile_qdo(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let idx = self.references().idx__qdo;
         self.s_stack().push(idx as isize);
         self.compile_comma();
@@ -1710,6 +2208,9 @@ pub trait Core: Sized {
     /// Control::Do(do_part, _)
     ///
     primitive! {fn compile_loop(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let do_part = match self.c_stack().pop() {
             Control::Do(do_part,_) => do_part,
             _ => {
@@ -1739,6 +2240,9 @@ pub trait Core: Sized {
     /// the location given by do-sys and the next location for a transfer of
     /// control, to execute the words following `+LOOP`.
     primitive! {fn compile_plus_loop(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let do_part = match self.c_stack().pop() {
             Control::Do(do_part,_) => do_part,
             _ => {
@@ -1763,7 +2267,7 @@ pub trait Core: Sized {
 
     primitive! {fn activate(&mut self) {
         let i = (self.s_stack().pop() - 1) as usize;
-        if i < NUM_TASKS {
+        if i < self.task_count() {
             // Wake task `i`.
             self.set_awake(i, true);
             // Reset task `i` and Assign the code following ACTIVATE to task `i`
@@ -1898,8 +2402,9 @@ pub trait Core: Sized {
         let mut last_token = self.last_token().take().expect("token");
         last_token.clear();
         {
-
-            let source = &input_buffer[self.state().source_index..];
+            // The index may be past the end after skipping a delimiter.
+            let start = self.state().source_index.min(input_buffer.len());
+            let source = &input_buffer[start..];
             let mut cnt = source.len();
             let mut char_indices = source.char_indices();
             loop {
@@ -1922,7 +2427,7 @@ pub trait Core: Sized {
                     }
                 }
             }
-            self.state().source_index = self.state().source_index + cnt;
+            self.state().source_index = start + cnt;
         }
         self.set_last_token(last_token);
         self.set_input_buffer(input_buffer);
@@ -2018,8 +2523,10 @@ pub trait Core: Sized {
         // --+------+---------+-----------+--
         // Because B comes after A, the xt of A is valid during execution of B.
         let xt = self.s_stack().last() as usize;
-        let compilation_semantics = self.wordlist()[xt].compilation_semantics;
-        compilation_semantics(self);
+        if self.check_sandbox_xt(xt) {
+            let compilation_semantics = self.wordlist()[xt].compilation_semantics;
+            compilation_semantics(self);
+        }
     }}
 
     primitive! {fn compile_token(&mut self) {
@@ -2036,23 +2543,10 @@ pub trait Core: Sized {
                 }
             }
             None => {
-                let mut done = false;
                 self.set_error(None);
-                self.evaluate_integer(&last_token);
-                match self.last_error() {
-                    None => done = true,
-                    Some(_) => {
-                        self.set_error(None);
-                        self.evaluate_float(&last_token);
-                        if self.last_error().is_none() {
-                            done = true;
-                        }
-                    }
-                }
+                let number = self.evaluate_number(&last_token);
                 self.set_last_token(last_token);
-                if done {
-                    /* Do nothing. */
-                } else {
+                if !number {
                     self.abort_with(UNDEFINED_WORD);
                 }
             }
@@ -2071,23 +2565,10 @@ pub trait Core: Sized {
                 }
             }
             None => {
-                let mut done = false;
                 self.set_error(None);
-                self.evaluate_integer(&last_token);
-                match self.last_error() {
-                    None => done = true,
-                    Some(_) => {
-                        self.set_error(None);
-                        self.evaluate_float(&last_token);
-                        if self.last_error().is_none() {
-                            done = true;
-                        }
-                    }
-                }
+                let number = self.evaluate_number(&last_token);
                 self.set_last_token(last_token);
-                if done {
-                    /* Do nothing. */
-                } else {
+                if !number {
                     self.abort_with(UNDEFINED_WORD);
                 }
             }
@@ -2133,7 +2614,10 @@ pub trait Core: Sized {
     /// Store token in counted string at `c-addr`.`
     primitive! {fn store_token(&mut self) {
         let c_addr = self.s_stack().pop() as usize;
-        if self.data_space().start() <= c_addr {
+        let len = self.last_token().as_ref().map_or(0, |t| t.len().min(255)) + 1;
+        if self.data_space().is_read_only(c_addr, len) {
+            self.abort_with(WRITE_TO_A_READ_ONLY_LOCATION);
+        } else if self.data_space().start() <= c_addr {
             match self.last_token().take() {
                 Some(mut t) => {
                     self.data_space().put_cstr(&t, c_addr);
@@ -2157,12 +2641,20 @@ pub trait Core: Sized {
     }}
 
     fn evaluate_input(&mut self) {
+        // The instruction limit of a sandbox applies to the outermost
+        // evaluation.
+        if let Some(ref mut sandbox) = *self.sandbox() {
+            if sandbox.depth == 0 {
+                sandbox.executed = 0;
+            }
+            sandbox.depth += 1;
+        }
         loop {
             self.parse_word();
             match self.last_token().as_ref() {
                 Some(t) => {
                     if t.is_empty() {
-                        return;
+                        break;
                     }
                 }
                 None => {}
@@ -2184,6 +2676,9 @@ pub trait Core: Sized {
                 break;
             }
         }
+        if let Some(ref mut sandbox) = *self.sandbox() {
+            sandbox.depth = sandbox.depth.saturating_sub(1);
+        }
     }
 
     primitive! {fn base(&mut self) {
@@ -2191,52 +2686,59 @@ pub trait Core: Sized {
         self.s_stack().push(base_addr as isize);
     }}
 
-    fn evaluate_integer(&mut self, token: &str) {
-        let base_addr = self.data_space().system_variables().base_addr();
-        let default_base = unsafe { self.data_space().get_isize(base_addr) };
-        match parser::quoted_char(&token.as_bytes()) {
-            parser::IResult::Done(_bytes, c) => {
-                if self.state().is_compiling {
-                    self.s_stack().push(c as _);
-                    self.compile_integer();
-                } else {
-                    self.s_stack().push(c);
-                }
-                return;
+    /// Push the number `token`, or compile it in compilation state.
+    ///
+    /// Returns false if `token` is not a number. An exception raised when
+    /// compiling the number, such as `DICTIONARY_OVERFLOW`, is left in
+    /// `last_error`.
+    fn evaluate_number(&mut self, token: &str) -> bool {
+        if let Some(n) = self.parse_integer(token) {
+            self.s_stack().push(n);
+            if self.state().is_compiling {
+                self.compile_integer();
             }
-            parser::IResult::Err(_) => {
-                // Do nothing.
+            true
+        } else if let Some(f) = self.parse_float(token) {
+            self.f_stack().push(f);
+            if self.state().is_compiling {
+                self.compile_float();
+            } else {
+                self.f_stack().set_dim(0, Tag::known(Dimension::NONE));
             }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Integer `token` in the current BASE, or a character `'c'`.
+    fn parse_integer(&mut self, token: &str) -> Option<isize> {
+        let base_addr = self.data_space().system_variables().base_addr();
+        let default_base = unsafe { self.data_space().get_isize(base_addr) };
+        if let parser::IResult::Done(_bytes, c) = parser::quoted_char(&token.as_bytes()) {
+            return Some(c);
         }
         match parser::base(&token.as_bytes(), default_base) {
             parser::IResult::Done(bytes, base) => match parser::sign(&bytes) {
                 parser::IResult::Done(bytes, sign) => match parser::uint_in_base(&bytes, base) {
                     parser::IResult::Done(bytes, value) => {
                         if bytes.len() != 0 {
-                            self.set_error(Some(INTEGER_UNIDENTIFIED_FAULT));
+                            None
                         } else {
-                            if self.state().is_compiling {
-                                self.s_stack().push(sign.wrapping_mul(value));
-                                self.compile_integer();
-                            } else {
-                                self.s_stack().push(sign.wrapping_mul(value));
-                            }
+                            Some(sign.wrapping_mul(value))
                         }
                     }
-                    parser::IResult::Err(_e) => self.set_error(Some(INTEGER_UNIDENTIFIED_FAULT)),
+                    parser::IResult::Err(_e) => None,
                 },
-                parser::IResult::Err(_e) => {
-                    self.set_error(Some(INTEGER_UNIDENTIFIED_FAULT));
-                }
+                parser::IResult::Err(_e) => None,
             },
-            parser::IResult::Err(_e) => {
-                self.set_error(Some(INTEGER_UNIDENTIFIED_FAULT));
-            }
+            parser::IResult::Err(_e) => None,
         }
     }
 
-    /// Evaluate float.
-    fn evaluate_float(&mut self, token: &str) {
+    /// Float `token`, None if it is not a float or the floating-point
+    /// word set is not installed.
+    fn parse_float(&mut self, token: &str) -> Option<f64> {
         let significand_sign;
         let integer_part;
         let mut fraction_part = 0.0;
@@ -2251,8 +2753,7 @@ pub trait Core: Sized {
                 bytes = input;
             }
             parser::IResult::Err(_e) => {
-                self.set_error(Some(FLOATING_POINT_UNIDENTIFIED_FAULT));
-                return;
+                return None;
             }
         }
 
@@ -2263,8 +2764,7 @@ pub trait Core: Sized {
                 bytes = input;
             }
             parser::IResult::Err(_e) => {
-                self.set_error(Some(FLOATING_POINT_UNIDENTIFIED_FAULT));
-                return;
+                return None;
             }
         }
         if bytes.len() != len_before {
@@ -2274,8 +2774,7 @@ pub trait Core: Sized {
                     bytes = input;
                 }
                 parser::IResult::Err(_e) => {
-                    self.set_error(Some(FLOATING_POINT_UNIDENTIFIED_FAULT));
-                    return;
+                    return None;
                 }
             }
 
@@ -2289,8 +2788,7 @@ pub trait Core: Sized {
                                 bytes = input;
                             }
                             parser::IResult::Err(_e) => {
-                                self.set_error(Some(FLOATING_POINT_UNIDENTIFIED_FAULT));
-                                return;
+                                return None;
                             }
                         }
                         match parser::uint(bytes) {
@@ -2299,8 +2797,7 @@ pub trait Core: Sized {
                                 bytes = input;
                             }
                             parser::IResult::Err(_e) => {
-                                self.set_error(Some(FLOATING_POINT_UNIDENTIFIED_FAULT));
-                                return;
+                                return None;
                             }
                         }
                     } else {
@@ -2313,8 +2810,7 @@ pub trait Core: Sized {
                                             bytes = input;
                                         }
                                         parser::IResult::Err(_e) => {
-                                            self.set_error(Some(FLOATING_POINT_UNIDENTIFIED_FAULT));
-                                            return;
+                                            return None;
                                         }
                                     }
                                     match parser::uint(bytes) {
@@ -2323,22 +2819,19 @@ pub trait Core: Sized {
                                             bytes = input;
                                         }
                                         parser::IResult::Err(_e) => {
-                                            self.set_error(Some(FLOATING_POINT_UNIDENTIFIED_FAULT));
-                                            return;
+                                            return None;
                                         }
                                     }
                                 }
                             }
                             parser::IResult::Err(_e) => {
-                                self.set_error(Some(FLOATING_POINT_UNIDENTIFIED_FAULT));
-                                return;
+                                return None;
                             }
                         }
                     }
                 }
                 parser::IResult::Err(_e) => {
-                    self.set_error(Some(FLOATING_POINT_UNIDENTIFIED_FAULT));
-                    return;
+                    return None;
                 }
             }
 
@@ -2353,22 +2846,14 @@ pub trait Core: Sized {
             failed = true;
         }
 
-        if failed {
-            self.set_error(Some(FLOATING_POINT_UNIDENTIFIED_FAULT))
+        if failed || self.references().idx_flit == 0 {
+            None
         } else {
-            if self.references().idx_flit == 0 {
-                self.set_error(Some(FLOATING_POINT_UNIDENTIFIED_FAULT));
-            } else {
-                let value = (significand_sign as f64)
+            Some(
+                (significand_sign as f64)
                     * (integer_part as f64 + fraction_part)
-                    * ((10.0f64).powi((exponent_sign.wrapping_mul(exponent_part)) as i32) as f64);
-                if self.state().is_compiling {
-                    self.f_stack().push(value);
-                    self.compile_float();
-                } else {
-                    self.f_stack().push(value);
-                }
-            }
+                    * ((10.0f64).powi((exponent_sign.wrapping_mul(exponent_part)) as i32) as f64),
+            )
         }
     }
 
@@ -2402,9 +2887,43 @@ pub trait Core: Sized {
         word_type: WordType,
         action: primitive! {fn(&mut Self)},
         compilation_semantics: primitive! { fn(&mut Self) },
+    ) {
+        self.define_with_data(word_type, action, compilation_semantics, 0);
+    }
+
+    /// Define a word with `data_size` bytes of data following its name.
+    ///
+    /// Abort with `DICTIONARY_OVERFLOW` before the word is defined if there
+    /// is no space for them.
+    fn define_with_data(
+        &mut self,
+        word_type: WordType,
+        action: primitive! {fn(&mut Self)},
+        compilation_semantics: primitive! { fn(&mut Self) },
+        data_size: usize,
     ) {
         self.parse_word();
-        let mut last_token = self.last_token().take().expect("last token");
+        let last_token = self.last_token().take().expect("last token");
+        self.define_named_with_data(
+            last_token,
+            word_type,
+            action,
+            compilation_semantics,
+            data_size,
+        );
+    }
+
+    /// Define a word named `name` with `data_size` bytes of data following
+    /// its name, see `define_with_data`.
+    fn define_named_with_data(
+        &mut self,
+        name: String,
+        word_type: WordType,
+        action: primitive! {fn(&mut Self)},
+        compilation_semantics: primitive! { fn(&mut Self) },
+        data_size: usize,
+    ) {
+        let mut last_token = name;
         last_token.make_ascii_lowercase();
         if let Some(_) = self.find(&last_token) {
             match self.output_buffer().as_mut() {
@@ -2414,9 +2933,20 @@ pub trait Core: Sized {
                 None => {}
             }
         }
+        // Length of name, name and padding before data.
+        let size = last_token.len() + 2 * mem::size_of::<usize>() + data_size;
+        let definitions = match *self.sandbox() {
+            Some(ref sandbox) => sandbox.definitions_allowed(),
+            None => true,
+        };
         if last_token.is_empty() {
             self.set_last_token(last_token);
             self.abort_with(UNEXPECTED_END_OF_FILE);
+        } else if !definitions {
+            self.set_last_token(last_token);
+            self.abort_with(SANDBOX_DEFINITION);
+        } else if !self.check_space(size) {
+            self.set_last_token(last_token);
         } else {
             let nfa = self.data_space().compile_str(&last_token);
             self.data_space().align();
@@ -2454,6 +2984,9 @@ pub trait Core: Sized {
             compile_exit(self);
             let def = self.wordlist().last;
             self.wordlist_mut()[def].set_hidden(false);
+            let dfa = self.wordlist()[def].dfa();
+            let here = self.data_space().here();
+            self.data_space().protect(dfa, here);
         }
         self.left_bracket();
     }}
@@ -2464,7 +2997,12 @@ pub trait Core: Sized {
 
     primitive! {fn constant(&mut self) {
         let v = self.s_stack().pop();
-        self.define(WordType::Const, Core::p_const, Core::compile_const);
+        self.define_with_data(
+            WordType::Const,
+            Core::p_const,
+            Core::compile_const,
+            mem::size_of::<isize>(),
+        );
         if self.last_error().is_none() {
             self.data_space().compile_isize(v as isize);
         }
@@ -2497,10 +3035,28 @@ pub trait Core: Sized {
         dfa += mem::size_of::<usize>();
         let x = unsafe{ self.data_space().get_usize(dfa) };
         self.wordlist_mut().current =  x;
+        self.forget_closures(wp);
         self.data_space().truncate(nfa);
         self.wordlist_mut().truncate(wp);
     }}
 
+    /// Release the closures of the words from `wp` on, which are about to
+    /// be removed. Closures are added in the order of their words, so the
+    /// table is truncated at the first of them.
+    fn forget_closures(&mut self, wp: usize) {
+        let mut first = self.closures().len();
+        for w in wp..self.wordlist().len() {
+            if self.wordlist()[w].word_type() == WordType::Closure {
+                let dfa = self.wordlist()[w].dfa();
+                let i = unsafe { self.data_space().get_usize(dfa) };
+                if i < first {
+                    first = i;
+                }
+            }
+        }
+        self.closures().truncate(first);
+    }
+
     /// Example:
     /// ```text
     /// marker -work
@@ -2513,7 +3069,12 @@ pub trait Core: Sized {
     primitive! {fn marker(&mut self) {
         let x = self.wordlist().last;
         self.wordlist_mut().temp_buckets = self.wordlist().buckets;
-        self.define(WordType::Marker, Core::unmark, Core::compile_comma);
+        let search_order_len = self.wordlist().search_order_len;
+        let size = (BUCKET_SIZE * WORDLISTS + search_order_len + 4) * mem::size_of::<usize>();
+        self.define_with_data(WordType::Marker, Core::unmark, Core::compile_comma, size);
+        if self.last_error().is_some() {
+            return;
+        }
         self.data_space().compile_usize(x);
         for i in 0..BUCKET_SIZE * WORDLISTS {
             let x = self.wordlist().temp_buckets[i];
@@ -2564,6 +3125,9 @@ pub trait Core: Sized {
     ///   +---+----+
     ///
     primitive! {fn does(&mut self) {
+        if !self.check_space(2 * mem::size_of::<isize>()) {
+            return;
+        }
         let idx = self.references().idx__does;
         self.s_stack().push(idx as isize);
         self.compile_comma();
@@ -2587,6 +3151,9 @@ pub trait Core: Sized {
 
     /// Run time behavior of does>.
     primitive! {fn _does(&mut self) {
+        if !self.check_space(mem::size_of::<usize>()) {
+            return;
+        }
         let doer = self.state().instruction_pointer + mem::size_of::<isize>();
         self.data_space().compile_usize(doer);
         let def = self.wordlist().last;
@@ -2957,7 +3524,11 @@ pub trait Core: Sized {
         if self.data_space().start() < t &&
             t + mem::size_of::<isize>() <= self.data_space().limit()
         {
-            unsafe{ self.data_space().put_isize(n as isize, t as usize) };
+            if self.data_space().is_read_only(t, mem::size_of::<isize>()) {
+                self.abort_with(WRITE_TO_A_READ_ONLY_LOCATION);
+            } else {
+                unsafe{ self.data_space().put_isize(n as isize, t as usize) };
+            }
         } else {
             self.abort_with(INVALID_MEMORY_ADDRESS);
         }
@@ -2988,7 +3559,11 @@ pub trait Core: Sized {
         let (n, t) = self.s_stack().pop2();
         let t = t as usize;
         if self.data_space().start() < t && t  < self.data_space().limit() {
-            unsafe{ self.data_space().put_u8(n as u8, t as usize) };
+            if self.data_space().is_read_only(t, 1) {
+                self.abort_with(WRITE_TO_A_READ_ONLY_LOCATION);
+            } else {
+                unsafe{ self.data_space().put_u8(n as u8, t as usize) };
+            }
         } else {
             self.abort_with(INVALID_MEMORY_ADDRESS);
         }
@@ -3013,6 +3588,10 @@ pub trait Core: Sized {
                 && self.data_space().start() < addr2
                 && addr2 + u  <= self.data_space().limit()
             {
+                if self.data_space().is_read_only(addr2, u) {
+                    self.abort_with(WRITE_TO_A_READ_ONLY_LOCATION);
+                    return;
+                }
                 unsafe{
                     if addr1 < addr2 {
                         for p in (addr1..(addr1+u)).into_iter().zip(addr2..(addr2+u)).rev() {
@@ -3062,6 +3641,9 @@ pub trait Core: Sized {
     /// condition exists if xt is not for a word defined via CREATE.
     primitive! {fn to_body(&mut self) {
         let t = self.s_stack().pop() as usize;
+        if !self.check_sandbox_xt(t) {
+            return;
+        }
         if t < self.wordlist().len() {
             let dfa = self.wordlist()[t].dfa() as isize;
             self.s_stack().push(dfa);
@@ -3075,8 +3657,17 @@ pub trait Core: Sized {
     /// Remove `xt` from the stack and perform the semantics identified by it.
     /// Other stack effects are due to the word `EXECUTE`d.
     primitive! {fn execute(&mut self) {
-        let t = self.s_stack().pop();
-        self.execute_word(t as usize);
+        let t = self.s_stack().pop() as usize;
+        if !self.valid_xt(t) {
+            return;
+        }
+        if self.data_space().is_safe()
+            && (self.wordlist()[t].is_compile_only() || self.wordlist()[t].is_hidden())
+        {
+            self.abort_with(INVALID_EXECUTION_TOKEN);
+        } else if self.check_sandbox_xt(t) {
+            self.execute_word(t);
+        }
     }}
 
     /// Compilation: ( "<spaces>name" -- )
@@ -3111,8 +3702,19 @@ pub trait Core: Sized {
     primitive! {fn compile_comma(&mut self) {
         let compile_comma_vector = self.data_space().system_variables().compile_comma_vector();
         unsafe {
-            let compile_comma_vector: *const primitive!{fn (&mut Self)} = mem::transmute (compile_comma_vector);
-            (*compile_comma_vector)(self);
+            let compile_comma_vector: primitive!{fn (&mut Self)} =
+                mem::transmute(self.data_space().get_usize(compile_comma_vector));
+            compile_comma_vector(self);
+        }
+    }}
+
+    // COMPILE, ( xt -- )
+    //
+    // `compile_comma` checking that `xt` is allowed in the sandbox.
+    primitive! {fn p_compile_comma(&mut self) {
+        let xt = self.s_stack().last() as usize;
+        if self.valid_xt(xt) && self.check_sandbox_xt(xt) {
+            self.compile_comma();
         }
     }}
 
@@ -3124,6 +3726,15 @@ pub trait Core: Sized {
         self.s_stack().push(here);
     }}
 
+    // Run-time: ( -- u )
+    //
+    // `u` is the amount of space remaining in the region addressed by `here`,
+    // in address units.
+    primitive! {fn unused(&mut self) {
+        let unused = self.data_space().dictionary_limit() - self.data_space().here();
+        self.s_stack().push(unused as isize);
+    }}
+
     /// Run-time: ( n -- )
     ///
     /// If `n` is greater than zero, reserve n address units of data space. If `n`
@@ -3131,7 +3742,21 @@ pub trait Core: Sized {
     /// zero, leave the data-space pointer unchanged.
     primitive! {fn allot(&mut self) {
         let v = self.s_stack().pop();
-        self.data_space().allot(v);
+        if self.s_stack().underflow() {
+            // Leave it to check_stacks().
+        } else if v > 0 {
+            if self.check_space(v as usize) {
+                self.data_space().allot(v);
+            }
+        } else {
+            let here = self.data_space().here();
+            let pos = (here as isize).wrapping_add(v) as usize;
+            if pos < here && self.data_space().is_read_only(pos, here - pos) {
+                self.abort_with(WRITE_TO_A_READ_ONLY_LOCATION);
+            } else {
+                self.data_space().allot(v);
+            }
+        }
     }}
 
     /// Run-time: ( addr -- a-addr )
@@ -3174,7 +3799,9 @@ pub trait Core: Sized {
     /// data-space pointer is not aligned prior to execution of `,`.
     primitive! {fn comma(&mut self) {
         let v = self.s_stack().pop();
-        self.data_space().compile_isize(v as isize);
+        if self.check_space(mem::size_of::<isize>()) {
+            self.data_space().compile_isize(v as isize);
+        }
     }}
 
     primitive! {fn p_to_r(&mut self) {
@@ -3363,6 +3990,102 @@ pub trait Core: Sized {
         self.execute_word(h);
     }
 
+    /// Check that `n` more bytes fit into the dictionary. Abort with
+    /// `DICTIONARY_OVERFLOW` and return false if not.
+    fn check_space(&mut self, n: usize) -> bool {
+        let here = self.data_space().here();
+        let limit = self.data_space().dictionary_limit();
+        let sandbox_limit = match *self.sandbox() {
+            Some(ref sandbox) => sandbox.data_limit,
+            None => !0,
+        };
+        if n > limit - here {
+            self.abort_with(DICTIONARY_OVERFLOW);
+            false
+        } else if here + n > sandbox_limit {
+            self.abort_with(SANDBOX_DATA_LIMIT);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Install `sandbox` for the current task, or remove it with `None`.
+    ///
+    /// Data-space growth is counted from `here`, and words defined from now
+    /// on are always found.
+    fn set_sandbox(&mut self, sandbox: Option<Sandbox>) {
+        let here = self.data_space().here();
+        let words = self.wordlist().len();
+        let sandbox = sandbox.map(|mut sandbox| {
+            sandbox.set_base(here, words);
+            let mut allowed_xts = Vec::with_capacity(words);
+            for xt in 0..words {
+                let nfa = self.wordlist()[xt].nfa();
+                let wid = self.wordlist()[xt].wordlist;
+                let name = unsafe { self.data_space().get_str(nfa) };
+                allowed_xts.push(sandbox.allows(xt, name, wid));
+            }
+            sandbox.allowed_xts = allowed_xts;
+            sandbox
+        });
+        *self.sandbox() = sandbox;
+    }
+
+    /// Is `xt` an execution token of the word list? Abort with
+    /// `INVALID_EXECUTION_TOKEN` and return false if not.
+    fn valid_xt(&mut self, xt: usize) -> bool {
+        let valid = xt < self.wordlist().len();
+        if !valid {
+            self.abort_with(INVALID_EXECUTION_TOKEN);
+        }
+        valid
+    }
+
+    /// May the word `xt` be executed or compiled in the sandbox, if any?
+    /// Abort with `INVALID_EXECUTION_TOKEN` and return false if not.
+    fn check_sandbox_xt(&mut self, xt: usize) -> bool {
+        let allowed = match *self.sandbox() {
+            Some(ref sandbox) => sandbox.allows_xt(xt),
+            None => true,
+        };
+        if !allowed {
+            self.abort_with(INVALID_EXECUTION_TOKEN);
+        }
+        allowed
+    }
+
+    /// Turn safe mode on or off.
+    ///
+    /// In safe mode, `!`, `C!`, `F!`, `MOVE` and `ALLOT` cannot change
+    /// compiled code or the execution vectors in system variables, and
+    /// `EXECUTE` rejects compile-only and hidden words. Code of colon
+    /// definitions is write-protected at `;`. Definitions which exist when
+    /// safe mode is turned on are protected up to the next definition.
+    /// `EXECUTE`, `COMPILE,`, `>DFA`, `>NFA` and `>ACTION` abort with
+    /// `INVALID_EXECUTION_TOKEN` on an xt outside the word list, in safe
+    /// mode or not.
+    fn set_safe_mode(&mut self, on: bool) {
+        self.data_space().set_safe(on);
+        if on {
+            let len = self.wordlist().len();
+            for i in 1..len {
+                let (word_type, hidden, dfa) = {
+                    let w = &self.wordlist()[i];
+                    (w.word_type, w.is_hidden(), w.dfa())
+                };
+                if word_type == WordType::Nest && !hidden {
+                    let end = if i + 1 < len {
+                        self.wordlist()[i + 1].nfa()
+                    } else {
+                        self.data_space().here()
+                    };
+                    self.data_space().protect(dfa, end);
+                }
+            }
+        }
+    }
+
     /// ABORT the inner loop with an exception, reset VM and clears stacks.
     primitive! {fn abort(&mut self) {
         self.abort_with(ABORT);
@@ -3370,23 +4093,29 @@ pub trait Core: Sized {
 
     /// Data field address of `xt`. `>DFA ( xt -- dfa )`
     primitive! {fn to_dfa(&mut self) {
-        let xt = self.s_stack().pop();
-        let dfa = self.wordlist()[xt as usize].dfa();
-        self.s_stack().push(dfa as isize);
+        let xt = self.s_stack().pop() as usize;
+        if self.valid_xt(xt) {
+            let dfa = self.wordlist()[xt].dfa();
+            self.s_stack().push(dfa as isize);
+        }
     }}
 
     /// Name field address of `xt`. `>NFA ( xt -- nfa )`
     primitive! {fn to_nfa(&mut self) {
-        let xt = self.s_stack().pop();
-        let nfa = self.wordlist()[xt as usize].nfa();
-        self.s_stack().push(nfa as isize);
+        let xt = self.s_stack().pop() as usize;
+        if self.valid_xt(xt) {
+            let nfa = self.wordlist()[xt].nfa();
+            self.s_stack().push(nfa as isize);
+        }
     }}
 
     /// Interpretation semantics of `xt`. `>ACTION ( xt -- 'action )`
     primitive! {fn to_action(&mut self) {
-        let xt = self.s_stack().pop();
-        let action = self.wordlist()[xt as usize].action() as *mut u8 as usize;
-        self.s_stack().push(action as isize);
+        let xt = self.s_stack().pop() as usize;
+        if self.valid_xt(xt) {
+            let action = self.wordlist()[xt].action() as *mut u8 as usize;
+            self.s_stack().push(action as isize);
+        }
     }}
 
     /// Execution token with interpretation semantics of `action`. `ACTION> ( action -- xt )`
@@ -3439,14 +4168,15 @@ pub trait Core: Sized {
 
     /// Number of tasks
     primitive! {fn num_tasks(&mut self) {
-        self.s_stack().push(NUM_TASKS as _);
+        let n = self.task_count();
+        self.s_stack().push(n as _);
     }}
 
     /// Pause the current task and resume the next task which is awake.
     primitive! {fn pause(&mut self) {
         let mut i = self.current_task();
         loop {
-            i = (i + 1) % NUM_TASKS;
+            i = (i + 1) % self.task_count();
             if self.awake(i) {
                 self.set_current_task(i);
                 break;
@@ -3463,7 +4193,7 @@ pub trait Core: Sized {
     /// Suspend task `i`. `suspend ( i -- )`
     primitive! {fn suspend(&mut self) {
         let i = (self.s_stack().pop() - 1) as usize;
-        if i < NUM_TASKS {
+        if i < self.task_count() {
             self.set_awake(i as usize, false);
         } else {
             self.abort_with(INVALID_NUMERIC_ARGUMENT);
@@ -3473,7 +4203,7 @@ pub trait Core: Sized {
     /// Resume task `i`. `resume ( i -- )`
     primitive! {fn resume(&mut self) {
         let i = (self.s_stack().pop() - 1) as usize;
-        if i < NUM_TASKS {
+        if i < self.task_count() {
             self.set_awake(i as usize, true);
         } else {
             self.abort_with(INVALID_NUMERIC_ARGUMENT);
@@ -3566,14 +4296,21 @@ pub trait Core: Sized {
 
 #[cfg(test)]
 mod tests {
-    use super::{Core, Memory, WORDLISTS};
+    use super::{Core, Memory, RunStatus, Stack, DEFAULT_STACK_DEPTH, MAX_STACK_DEPTH, WORDLISTS};
     use exception::{
-        ABORT, CONTROL_STRUCTURE_MISMATCH, INTERPRETING_A_COMPILE_ONLY_WORD,
-        INVALID_EXECUTION_TOKEN, INVALID_MEMORY_ADDRESS, RESULT_OUT_OF_RANGE,
-        RETURN_STACK_UNDERFLOW, STACK_UNDERFLOW, UNDEFINED_WORD, UNEXPECTED_END_OF_FILE,
+        ABORT, CONTROL_STRUCTURE_MISMATCH, DICTIONARY_OVERFLOW, DIVISION_BY_ZERO,
+        FLOATING_POINT_STACK_OVERFLOW, INTERPRETING_A_COMPILE_ONLY_WORD, INVALID_EXECUTION_TOKEN,
+        INVALID_MEMORY_ADDRESS, RESULT_OUT_OF_RANGE, RETURN_STACK_UNDERFLOW,
+        SANDBOX_INSTRUCTION_LIMIT, STACK_OVERFLOW, STACK_UNDERFLOW, UNDEFINED_WORD,
+        UNEXPECTED_END_OF_FILE, WRITE_TO_A_READ_ONLY_LOCATION,
     };
-    use mock_vm::VM;
+    use mock_vm::{NewVM, VM};
+    use sandbox::Sandbox;
     use std::mem;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use vm::{Clock, VMBuilder};
 
     #[test]
     fn test_find() {
@@ -4785,6 +5522,159 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_dictionary_overflow() {
+        let vm = &mut VM::new();
+        vm.set_source("unused  here");
+        vm.evaluate_input();
+        let (unused, here) = vm.s_stack().pop2();
+        vm.s_stack().push(unused + 1);
+        vm.set_source("allot");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(DICTIONARY_OVERFLOW));
+        assert_eq!(vm.data_space().here(), here as usize);
+        assert_eq!(
+            here as usize + unused as usize,
+            vm.data_space().dictionary_limit()
+        );
+        vm.reset();
+        vm.set_source("unused 1 cells - allot  1 ,  unused");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().pop(), 0);
+        for source in &[
+            "2 ,",
+            "create x",
+            ": foo 1 2 + ;",
+            "3 constant three",
+            "1e fconstant one",
+            "marker -work",
+        ] {
+            vm.reset();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), Some(DICTIONARY_OVERFLOW));
+            assert_eq!(vm.data_space().here(), vm.data_space().dictionary_limit());
+        }
+        vm.reset();
+        vm.set_source("-8 allot : foo 1 2 + ;");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(DICTIONARY_OVERFLOW));
+        assert!(vm.find("foo").is_none());
+    }
+
+    #[test]
+    fn test_dictionary_overflow_in_number() {
+        for source in &[
+            ": foo [ unused 3 cells - allot ] 1 2 3 ;",
+            ": foo [ unused 2 cells - allot ] 1e ;",
+        ] {
+            let vm = &mut VM::new();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), Some(DICTIONARY_OVERFLOW), "{}", source);
+            assert!(vm.data_space().here() <= vm.data_space().dictionary_limit());
+        }
+    }
+
+    #[test]
+    fn test_add_closure_overflow() {
+        let vm = &mut VM::new();
+        vm.set_source("unused 16 - allot");
+        vm.evaluate_input();
+        vm.add_closure("answer", |vm: &mut VM| vm.s_stack().push(42));
+        assert_eq!(vm.last_error(), Some(DICTIONARY_OVERFLOW));
+        assert!(vm.find("answer").is_none());
+    }
+
+    #[test]
+    fn test_safe_mode() {
+        let vm = &mut VMBuilder::new().safe(true).build();
+        vm.set_source(": foo 1 2 + ;  variable x  5 x !  x @  16 base ! base @ decimal");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [5, 16]);
+        for source in &[
+            "0 ' foo >body !",
+            "0 ' foo >body c!",
+            "1e ' foo >body f!",
+            "x ' foo >body 1 cells move",
+            "' foo >body here - allot",
+        ] {
+            vm.reset();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), Some(WRITE_TO_A_READ_ONLY_LOCATION));
+        }
+        vm.reset();
+        let vector = vm.data_space().system_variables().compile_comma_vector();
+        vm.s_stack().push(0);
+        vm.s_stack().push(vector as isize);
+        vm.set_source("!");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(WRITE_TO_A_READ_ONLY_LOCATION));
+        vm.reset();
+        vm.set_source("' lit execute");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_EXECUTION_TOKEN));
+        vm.reset();
+        vm.set_source(": cc compile, ;");
+        vm.evaluate_input();
+        for source in &[
+            "100000 execute",
+            "-1 execute",
+            "100000 >dfa",
+            "-1 >nfa",
+            "100000 >action",
+            ": t [ 100000 cc ] ;",
+        ] {
+            vm.reset();
+            vm.s_stack().reset();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), Some(INVALID_EXECUTION_TOKEN), "{}", source);
+        }
+        vm.reset();
+        // Space of forgotten code is writable again.
+        vm.set_source("marker -m  : bar 1 ;  -m  create y 0 , 0 ,  7 y !  9 y cell+ !  y 2@  foo");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [9, 7, 3]);
+    }
+
+    #[test]
+    fn test_stack_depth() {
+        let vm = &mut VMBuilder::new()
+            .data_stack_depth(4)
+            .float_stack_depth(100)
+            .build();
+        assert_eq!(vm.s_stack().capacity(), 4);
+        assert_eq!(vm.r_stack().capacity(), DEFAULT_STACK_DEPTH);
+        vm.set_source("1 2 3 4");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        vm.set_source("5");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(STACK_OVERFLOW));
+        vm.reset();
+        vm.set_source("drop");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(STACK_UNDERFLOW));
+        vm.reset();
+        vm.set_source(": f 100 0 do 1e loop ; f");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.f_stack().len(), 100);
+        vm.set_source("1e");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(FLOATING_POINT_STACK_OVERFLOW));
+        assert_eq!(
+            Stack::<isize>::with_capacity(0, 1000).capacity(),
+            MAX_STACK_DEPTH
+        );
+        assert_eq!(Stack::<isize>::with_capacity(0, 0).capacity(), 1);
+    }
+
     #[test]
     fn test_to_r_r_fetch_r_from() {
         let vm = &mut VM::new();
@@ -5129,6 +6019,253 @@ mod tests {
         assert_eq!(vm.s_stack().as_slice(), [4, 8, 5, 10]);
     }
 
+    #[test]
+    fn test_run_for() {
+        let vm = &mut VM::new();
+        vm.set_source(": main 1 2 3 ;");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        let main = vm.find("main").expect("main");
+        vm.execute_word(main);
+        assert_eq!(vm.run_for(2), RunStatus::Yielded);
+        assert_eq!(vm.s_stack().as_slice(), [1, 2]);
+        assert_eq!(vm.run_for(1), RunStatus::Yielded);
+        assert_eq!(vm.s_stack().as_slice(), [1, 2, 3]);
+        assert_eq!(vm.run_for(10), RunStatus::Finished);
+        assert_eq!(vm.run_for(10), RunStatus::Finished);
+        // An error from before does not stop the next step.
+        vm.execute_word(main);
+        vm.set_error(Some(DIVISION_BY_ZERO));
+        assert_eq!(vm.step(), RunStatus::Yielded);
+        assert_eq!(vm.last_error(), None);
+    }
+
+    #[test]
+    fn test_run_for_error_and_pause() {
+        let vm = &mut VM::new();
+        vm.set_source(": main 1 0 / ;");
+        vm.evaluate_input();
+        let main = vm.find("main").expect("main");
+        vm.execute_word(main);
+        assert_eq!(vm.run_for(10), RunStatus::Error(DIVISION_BY_ZERO));
+
+        let vm = &mut VM::new();
+        vm.set_source(": main pause 1 ;");
+        vm.evaluate_input();
+        let main = vm.find("main").expect("main");
+        vm.set_awake(1, true);
+        vm.execute_word(main);
+        assert_eq!(vm.run_for(10), RunStatus::Paused);
+        assert_eq!(vm.current_task(), 1);
+    }
+
+    #[cfg(feature = "checked-stacks")]
+    #[test]
+    fn test_checked_stacks_correct_programs() {
+        // Programs using the stacks up to their capacity give the same
+        // results as without the feature.
+        for &(source, expected) in &[
+            ("1 2 3 4 5 6 7 8", &[1, 2, 3, 4, 5, 6, 7, 8][..]),
+            ("1 2 3 rot swap over", &[2, 1, 3, 1][..]),
+            (": t 5 0 do i loop + + + + ;  t", &[10][..]),
+            (": sq dup * ;  3 sq 4 sq +", &[25][..]),
+            ("1e 2e f+ 3e f* f>s", &[9][..]),
+            ("s\" abc\" nip", &[3][..]),
+            (": t 1 >r 2 r> + ;  t", &[3][..]),
+            (": t 1 2 2>r 2r@ 2r> rot + ;  t", &[1, 1, 4][..]),
+        ] {
+            let vm = &mut VMBuilder::new()
+                .data_stack_depth(8)
+                .float_stack_depth(8)
+                .build();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), None, "{}", source);
+            assert_eq!(vm.s_stack().as_slice(), expected, "{}", source);
+            assert_eq!(vm.f_stack().len(), 0, "{}", source);
+        }
+    }
+
+    #[cfg(feature = "checked-stacks")]
+    #[test]
+    fn test_checked_stacks_errors() {
+        use exception::FLOATING_POINT_STACK_UNDERFLOW;
+        // The error is reported by the step which overflows or underflows.
+        for &(source, steps, e) in &[
+            (": t 1 2 3 4 5 6 ;", 5, STACK_OVERFLOW),
+            (": t 1 drop drop 2 ;", 3, STACK_UNDERFLOW),
+            (": t 1 2 3 2dup ;", 4, STACK_OVERFLOW),
+            (": t 1e 2e 3e ;", 3, FLOATING_POINT_STACK_OVERFLOW),
+            (": t 1e fdrop fdrop 1e ;", 3, FLOATING_POINT_STACK_UNDERFLOW),
+        ] {
+            let vm = &mut VMBuilder::new()
+                .data_stack_depth(4)
+                .float_stack_depth(2)
+                .build();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), None, "{}", source);
+            let t = vm.find("t").expect("t");
+            vm.execute_word(t);
+            let mut n = 0;
+            let status = loop {
+                n += 1;
+                match vm.step() {
+                    RunStatus::Yielded => {}
+                    status => break status,
+                }
+            };
+            assert_eq!((n, status), (steps, RunStatus::Error(e)), "{}", source);
+            assert_eq!(vm.s_stack().len(), 0, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_run_for_in_sandbox() {
+        let vm = &mut VM::new();
+        vm.set_source(": main begin again ;");
+        vm.evaluate_input();
+        let main = vm.find("main").expect("main");
+        vm.set_sandbox(Some(Sandbox::new().max_instructions(100)));
+        vm.execute_word(main);
+        assert_eq!(
+            vm.run_for(100_000),
+            RunStatus::Error(SANDBOX_INSTRUCTION_LIMIT)
+        );
+        assert_eq!(vm.run_for(10), RunStatus::Finished);
+    }
+
+    #[test]
+    fn test_run_until() {
+        // A clock advancing 1 ns each time it is read.
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let clock = ticks.clone();
+        let vm = &mut VMBuilder::new()
+            .clock(Clock::Custom(Box::new(move || {
+                clock.fetch_add(1, Ordering::SeqCst) as u64
+            })))
+            .build();
+        vm.set_source(": main begin 1 drop again ;  : short 1 2 + ;");
+        vm.evaluate_input();
+        let short = vm.find("short").expect("short");
+        vm.execute_word(short);
+        let deadline = vm.system_time_ns() + 100;
+        assert_eq!(vm.run_until(deadline), RunStatus::Finished);
+        assert_eq!(vm.s_stack().pop(), 3);
+        assert!(ticks.load(Ordering::SeqCst) < deadline as usize);
+        let main = vm.find("main").expect("main");
+        vm.execute_word(main);
+        assert_eq!(vm.run_until(0), RunStatus::Yielded);
+        let start = ticks.load(Ordering::SeqCst);
+        let deadline = vm.system_time_ns() + 100;
+        assert_eq!(vm.run_until(deadline), RunStatus::Yielded);
+        // One read for the deadline, 99 steps and the read ending the run.
+        assert_eq!(ticks.load(Ordering::SeqCst), start + 101);
+        assert_eq!(vm.s_stack().as_slice(), []);
+        assert_eq!(vm.last_error(), None);
+    }
+
+    #[test]
+    fn test_run_until_manual_clock() {
+        let vm = &mut VM::new();
+        vm.set_source(": main 10 0 do i loop ;");
+        vm.evaluate_input();
+        let main = vm.find("main").expect("main");
+        vm.execute_word(main);
+        let now = vm.system_time_ns();
+        assert_eq!(vm.run_until(now), RunStatus::Yielded);
+        assert_eq!(vm.s_stack().len(), 0);
+        vm.advance();
+        let deadline = vm.system_time_ns();
+        vm.advance();
+        assert_eq!(vm.run_until(deadline), RunStatus::Yielded);
+        assert_eq!(vm.s_stack().len(), 0);
+        // The clock stands still, the program runs to its end.
+        let deadline = vm.system_time_ns() + 1;
+        assert_eq!(vm.run_until(deadline), RunStatus::Finished);
+        assert_eq!(vm.s_stack().len(), 10);
+    }
+
+    #[cfg(feature = "offset-addresses")]
+    #[test]
+    fn test_offset_addresses() {
+        use memory::OFFSET_BASE;
+        let vm1 = &mut VM::new();
+        let vm2 = &mut VM::new();
+        assert_eq!(vm1.data_space().start(), OFFSET_BASE);
+        for vm in [&mut *vm1, &mut *vm2].iter_mut() {
+            vm.set_source("variable x  42 x !  : s s\" abc\" ;  x s drop base");
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), None);
+        }
+        assert_eq!(vm1.s_stack().as_slice(), vm2.s_stack().as_slice());
+        let base = vm1.s_stack().pop() as usize;
+        assert_eq!(base, OFFSET_BASE + mem::size_of::<isize>());
+        let s = vm1.s_stack().pop() as usize;
+        assert_eq!(unsafe { vm1.data_space().str_from_raw_parts(s, 3) }, "abc");
+        let x = vm1.s_stack().pop() as usize;
+        assert_eq!(unsafe { vm1.data_space().get_isize(x) }, 42);
+    }
+
+    #[test]
+    fn test_add_closure() {
+        let vm = &mut VM::new();
+        let (tx, rx) = mpsc::channel();
+        let mut count = 0;
+        vm.add_closure("send", move |vm: &mut VM| {
+            count += 1;
+            let v = vm.s_stack().pop();
+            tx.send(v + count).unwrap();
+        });
+        vm.set_source("3 send  : main 5 send ;  main");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(rx.try_recv(), Ok(4));
+        assert_eq!(rx.try_recv(), Ok(7));
+        assert_eq!(vm.s_stack().as_slice(), []);
+    }
+
+    #[test]
+    fn test_marker_releases_closures() {
+        let vm = &mut VM::new();
+        let counter = Arc::new(AtomicUsize::new(0));
+        vm.set_source("marker -closures");
+        vm.evaluate_input();
+        let c = counter.clone();
+        vm.add_closure("count", move |_vm: &mut VM| {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+        vm.add_closure("count2", |_vm: &mut VM| {});
+        vm.set_source("count ' count");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.closures().len(), 2);
+        assert_eq!(Arc::strong_count(&counter), 2);
+        vm.set_source("-closures execute");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_EXECUTION_TOKEN));
+        assert_eq!(vm.closures().len(), 0);
+        assert_eq!(Arc::strong_count(&counter), 1);
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_add_closure_recursive() {
+        let vm = &mut VM::new();
+        vm.add_closure("again!", |vm: &mut VM| {
+            let xt = vm.find("again!").expect("again!");
+            vm.execute_word(xt);
+        });
+        vm.set_source("again!");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_EXECUTION_TOKEN));
+        vm.reset();
+        vm.set_source("1 2 +");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().pop(), 3);
+    }
+
     #[test]
     fn test_here_comma_compile_interpret() {
         let vm = &mut VM::new();
@@ -0,0 +1,602 @@
+//! Signal processing
+//!
+//! Filters and controllers for servo and sensor tasks, stepped once per
+//! cycle by native words which neither allocate nor parse. Each kind has a
+//! defining word, such as `BIQUAD`, which creates a named state block of
+//! floats in data space, and a step word, such as `BIQUAD-STEP`, which
+//! takes the address of the block and one input sample and returns one
+//! output sample:
+//!
+//! * `BIQUAD`, a second-order IIR filter in transposed direct form II,
+//! * `FIR`, an FIR filter with a circular delay line,
+//! * `MOVING-AVERAGE` of the last `u` samples,
+//! * `PID`, a PID controller with output clamping and conditional
+//!   integration against windup,
+//! * `RATE-LIMITER`, which limits the change of its output per step.
+//!
+//! The output of a filter or rate limiter is of the dimension of its
+//! input, see `units::Dimension`.
+//!
+//! ```
+//! use rtforth::core::Core;
+//! use rtforth::vm::VMBuilder;
+//!
+//! let mut vm = VMBuilder::new().build();
+//! vm.set_source("
+//!     4 moving-average avg
+//!     8e avg moving-average-step fdrop
+//!     4e avg moving-average-step
+//! ");
+//! vm.evaluate_input();
+//! assert_eq!(vm.f_stack().pop(), 3.0);
+//! ```
+
+use core::{Core, WordType};
+use exception::INVALID_NUMERIC_ARGUMENT;
+use linalg::LinearAlgebra;
+use memory::Memory;
+use std::mem;
+
+/// Floats of a biquad state block, see `Biquad::to_floats`.
+pub const BIQUAD_FLOATS: usize = 7;
+
+/// Floats of a PID state block, see `Pid::to_floats`.
+pub const PID_FLOATS: usize = 8;
+
+/// Floats of a rate limiter state block, see `RateLimiter::to_floats`.
+pub const RATE_LIMITER_FLOATS: usize = 3;
+
+/// Second-order IIR filter with transfer function
+/// `(b0 + b1 z^-1 + b2 z^-2) / (1 + a1 z^-1 + a2 z^-2)`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Biquad {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+    s1: f64,
+    s2: f64,
+}
+
+impl Biquad {
+    /// Filter of the coefficients at rest.
+    pub fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Biquad {
+        Biquad {
+            b0,
+            b1,
+            b2,
+            a1,
+            a2,
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    /// Filter input `x`, returning the output.
+    pub fn step(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.s1;
+        self.s1 = self.b1 * x - self.a1 * y + self.s2;
+        self.s2 = self.b2 * x - self.a2 * y;
+        y
+    }
+
+    /// The filter as floats, `b0 b1 b2 a1 a2` and the state.
+    pub fn to_floats(&self) -> [f64; BIQUAD_FLOATS] {
+        [
+            self.b0, self.b1, self.b2, self.a1, self.a2, self.s1, self.s2,
+        ]
+    }
+
+    /// Filter of `floats` written by `to_floats`.
+    pub fn from_floats(floats: &[f64; BIQUAD_FLOATS]) -> Biquad {
+        Biquad {
+            b0: floats[0],
+            b1: floats[1],
+            b2: floats[2],
+            a1: floats[3],
+            a2: floats[4],
+            s1: floats[5],
+            s2: floats[6],
+        }
+    }
+}
+
+/// PID controller with period `dt` and output between `min` and `max`.
+///
+/// The integral is not changed in a step whose output is clamped if the
+/// error would drive it further into saturation.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pid {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    pub dt: f64,
+    pub min: f64,
+    pub max: f64,
+    integral: f64,
+    previous: f64,
+}
+
+impl Pid {
+    /// Controller at rest. Returns `None` unless `dt` is positive and
+    /// `min` not greater than `max`.
+    pub fn new(kp: f64, ki: f64, kd: f64, dt: f64, min: f64, max: f64) -> Option<Pid> {
+        if dt > 0.0 && min <= max {
+            Some(Pid {
+                kp,
+                ki,
+                kd,
+                dt,
+                min,
+                max,
+                integral: 0.0,
+                previous: 0.0,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Control `error`, returning the output.
+    pub fn step(&mut self, error: f64) -> f64 {
+        let integral = self.integral + self.ki * error * self.dt;
+        let derivative = self.kd * (error - self.previous) / self.dt;
+        let u = self.kp * error + integral + derivative;
+        self.previous = error;
+        if u > self.max {
+            if error < 0.0 {
+                self.integral = integral;
+            }
+            self.max
+        } else if u < self.min {
+            if error > 0.0 {
+                self.integral = integral;
+            }
+            self.min
+        } else {
+            self.integral = integral;
+            u
+        }
+    }
+
+    /// The controller as floats, `kp ki kd dt min max` and the state.
+    pub fn to_floats(&self) -> [f64; PID_FLOATS] {
+        [
+            self.kp,
+            self.ki,
+            self.kd,
+            self.dt,
+            self.min,
+            self.max,
+            self.integral,
+            self.previous,
+        ]
+    }
+
+    /// Controller of `floats` written by `to_floats`.
+    pub fn from_floats(floats: &[f64; PID_FLOATS]) -> Pid {
+        Pid {
+            kp: floats[0],
+            ki: floats[1],
+            kd: floats[2],
+            dt: floats[3],
+            min: floats[4],
+            max: floats[5],
+            integral: floats[6],
+            previous: floats[7],
+        }
+    }
+}
+
+/// Rate limiter changing its output by at most `max_step` per step. The
+/// first output is the first input.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RateLimiter {
+    pub max_step: f64,
+    last: f64,
+    started: bool,
+}
+
+impl RateLimiter {
+    /// Limiter of `rate` per second stepped every `dt` seconds. Returns
+    /// `None` if `rate` is negative or `dt` not positive.
+    pub fn new(rate: f64, dt: f64) -> Option<RateLimiter> {
+        if rate >= 0.0 && dt > 0.0 {
+            Some(RateLimiter {
+                max_step: rate * dt,
+                last: 0.0,
+                started: false,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Limit input `x`, returning the output.
+    pub fn step(&mut self, x: f64) -> f64 {
+        let last = self.last;
+        let y = if !self.started {
+            x
+        } else if x - last > self.max_step {
+            last + self.max_step
+        } else if last - x > self.max_step {
+            last - self.max_step
+        } else {
+            x
+        };
+        self.last = y;
+        self.started = true;
+        y
+    }
+
+    /// The limiter as floats, `max_step`, the last output and 1 if it
+    /// has been stepped, 0 otherwise.
+    pub fn to_floats(&self) -> [f64; RATE_LIMITER_FLOATS] {
+        let started = if self.started { 1.0 } else { 0.0 };
+        [self.max_step, self.last, started]
+    }
+
+    /// Limiter of `floats` written by `to_floats`.
+    pub fn from_floats(floats: &[f64; RATE_LIMITER_FLOATS]) -> RateLimiter {
+        RateLimiter {
+            max_step: floats[0],
+            last: floats[1],
+            started: floats[2] != 0.0,
+        }
+    }
+}
+
+pub trait Dsp: LinearAlgebra {
+    fn add_dsp(&mut self) {
+        self.add_primitive("biquad", Dsp::biquad);
+        self.add_primitive("biquad-step", Dsp::biquad_step);
+        self.add_primitive("fir", Dsp::fir);
+        self.add_primitive("fir-step", Dsp::fir_step);
+        self.add_primitive("moving-average", Dsp::moving_average);
+        self.add_primitive("moving-average-step", Dsp::moving_average_step);
+        self.add_primitive("pid", Dsp::pid);
+        self.add_primitive("pid-step", Dsp::pid_step);
+        self.add_primitive("rate-limiter", Dsp::rate_limiter);
+        self.add_primitive("rate-limiter-step", Dsp::rate_limiter_step);
+    }
+
+    /// Define the next word of the input as a state block of `len` floats,
+    /// initialized to zero, and return its float-aligned address. The word
+    /// returns the address when executed, as a word defined by `CREATE`.
+    ///
+    /// Aborts and returns `None` if the block does not fit.
+    fn create_block(&mut self, len: usize) -> Option<usize> {
+        let bytes = match len.checked_add(1) {
+            Some(n) => n.checked_mul(mem::size_of::<f64>()),
+            None => None,
+        };
+        let bytes = match bytes {
+            Some(bytes) => bytes,
+            None => {
+                self.abort_with(INVALID_NUMERIC_ARGUMENT);
+                return None;
+            }
+        };
+        self.define_with_data(WordType::Var, Core::p_var, Core::compile_var, bytes);
+        if self.last_error().is_some() {
+            return None;
+        }
+        self.data_space().align_f64();
+        let addr = self.data_space().here();
+        for _ in 0..len {
+            self.data_space().compile_f64(0.0);
+        }
+        Some(addr)
+    }
+
+    /// Load the state block at `addr` into `floats` and return its
+    /// float-aligned address. Aborts and returns `None` if it is not in
+    /// data space.
+    fn load_block(&mut self, addr: isize, floats: &mut [f64]) -> Option<usize> {
+        let addr = self.float_array(addr, floats.len() as isize, true)?;
+        for (i, x) in floats.iter_mut().enumerate() {
+            *x = self.float_at(addr, i);
+        }
+        Some(addr)
+    }
+
+    /// Address and length `u` of the circular buffer block at `addr`,
+    /// whose first float is `u`, followed by `header` floats and `taps * u`
+    /// floats. Aborts and returns `None` if the block is invalid.
+    fn circular_block(
+        &mut self,
+        addr: isize,
+        header: usize,
+        taps: usize,
+    ) -> Option<(usize, usize)> {
+        let addr = self.float_array(addr, 1 + header as isize, true)?;
+        let u = self.float_at(addr, 0);
+        let limit = self.data_space().limit() as f64;
+        if !(1.0..=limit).contains(&u) || u.fract() != 0.0 {
+            self.abort_with(INVALID_NUMERIC_ARGUMENT);
+            return None;
+        }
+        let u = u as usize;
+        let addr = self.float_array(addr as isize, (1 + header + taps * u) as isize, true)?;
+        Some((addr, u))
+    }
+
+    // BIQUAD ( "<spaces>name" -- ) ( F: r-b0 r-b1 r-b2 r-a1 r-a2 -- )
+    // Create a biquad filter name ( -- f-addr ) with the coefficients.
+    primitive! {fn biquad(&mut self) {
+        let (a1, a2) = self.f_stack().pop2();
+        let (b0, b1, b2) = self.f_stack().pop3();
+        let biquad = Biquad::new(b0, b1, b2, a1, a2);
+        if let Some(addr) = self.create_block(BIQUAD_FLOATS) {
+            self.store_floats(addr, &biquad.to_floats());
+        }
+    }}
+
+    // BIQUAD-STEP ( f-addr -- ) ( F: r1 -- r2 )
+    // r2 is the output of the biquad filter at f-addr for input r1.
+    primitive! {fn biquad_step(&mut self) {
+        let addr = self.s_stack().pop();
+        let tag = self.f_stack().dim(0);
+        let x = self.f_stack().pop();
+        let mut floats = [0.0; BIQUAD_FLOATS];
+        if let Some(addr) = self.load_block(addr, &mut floats) {
+            let mut biquad = Biquad::from_floats(&floats);
+            let y = biquad.step(x);
+            self.store_floats(addr, &biquad.to_floats());
+            self.push_tagged_result(y, false, Some(tag));
+        }
+    }}
+
+    // FIR ( f-addr u "<spaces>name" -- )
+    // Create an FIR filter name ( -- f-addr ) with the u coefficients at
+    // f-addr, the first one for the latest input.
+    primitive! {fn fir(&mut self) {
+        let (src, u) = self.s_stack().pop2();
+        if u <= 0 {
+            self.abort_with(INVALID_NUMERIC_ARGUMENT);
+            return;
+        }
+        let src = match self.float_array(src, u, false) {
+            Some(src) => src,
+            None => return,
+        };
+        let u = u as usize;
+        let len = u.checked_mul(2).and_then(|n| n.checked_add(2));
+        if let Some(addr) = self.create_block(len.unwrap_or(!0)) {
+            self.store_floats(addr, &[u as f64]);
+            for i in 0..u {
+                let c = self.float_at(src, i);
+                self.store_floats(addr + (2 + i) * mem::size_of::<f64>(), &[c]);
+            }
+        }
+    }}
+
+    // FIR-STEP ( f-addr -- ) ( F: r1 -- r2 )
+    // r2 is the output of the FIR filter at f-addr for input r1.
+    primitive! {fn fir_step(&mut self) {
+        let addr = self.s_stack().pop();
+        let tag = self.f_stack().dim(0);
+        let x = self.f_stack().pop();
+        if let Some((addr, u)) = self.circular_block(addr, 1, 2) {
+            let i = self.float_at(addr, 1) as usize % u;
+            let delay = 2 + u;
+            self.store_floats(addr + (delay + i) * mem::size_of::<f64>(), &[x]);
+            let mut y = 0.0;
+            for k in 0..u {
+                let c = self.float_at(addr, 2 + k);
+                y += c * self.float_at(addr, delay + (i + u - k) % u);
+            }
+            self.store_floats(addr + mem::size_of::<f64>(), &[((i + 1) % u) as f64]);
+            self.push_tagged_result(y, false, Some(tag));
+        }
+    }}
+
+    // MOVING-AVERAGE ( u "<spaces>name" -- )
+    // Create a moving average name ( -- f-addr ) of u samples, initially
+    // all zero.
+    primitive! {fn moving_average(&mut self) {
+        let u = self.s_stack().pop();
+        if u <= 0 {
+            self.abort_with(INVALID_NUMERIC_ARGUMENT);
+            return;
+        }
+        let len = (u as usize).checked_add(3);
+        if let Some(addr) = self.create_block(len.unwrap_or(!0)) {
+            self.store_floats(addr, &[u as f64]);
+        }
+    }}
+
+    // MOVING-AVERAGE-STEP ( f-addr -- ) ( F: r1 -- r2 )
+    // r2 is the average of input r1 and the inputs before it of the moving
+    // average at f-addr.
+    //
+    // The sum is updated by each input and summed again once every u
+    // steps against rounding errors.
+    primitive! {fn moving_average_step(&mut self) {
+        let addr = self.s_stack().pop();
+        let tag = self.f_stack().dim(0);
+        let x = self.f_stack().pop();
+        if let Some((addr, u)) = self.circular_block(addr, 2, 1) {
+            let size = mem::size_of::<f64>();
+            let i = self.float_at(addr, 1) as usize % u;
+            let old = self.float_at(addr, 3 + i);
+            self.store_floats(addr + (3 + i) * size, &[x]);
+            let next = (i + 1) % u;
+            let sum = if next == 0 {
+                (0..u).map(|k| self.float_at(addr, 3 + k)).sum()
+            } else {
+                self.float_at(addr, 2) + x - old
+            };
+            self.store_floats(addr + size, &[next as f64, sum]);
+            self.push_tagged_result(sum / u as f64, false, Some(tag));
+        }
+    }}
+
+    // PID ( "<spaces>name" -- ) ( F: r-kp r-ki r-kd r-dt r-min r-max -- )
+    // Create a PID controller name ( -- f-addr ) with gains r-kp, r-ki
+    // and r-kd, period r-dt and output between r-min and r-max.
+    primitive! {fn pid(&mut self) {
+        let (dt, min, max) = self.f_stack().pop3();
+        let (kp, ki, kd) = self.f_stack().pop3();
+        match Pid::new(kp, ki, kd, dt, min, max) {
+            Some(pid) => {
+                if let Some(addr) = self.create_block(PID_FLOATS) {
+                    self.store_floats(addr, &pid.to_floats());
+                }
+            }
+            None => self.abort_with(INVALID_NUMERIC_ARGUMENT),
+        }
+    }}
+
+    // PID-STEP ( f-addr -- ) ( F: r-error -- r-output )
+    // Step the PID controller at f-addr with r-error.
+    primitive! {fn pid_step(&mut self) {
+        let addr = self.s_stack().pop();
+        let error = self.f_stack().pop();
+        let mut floats = [0.0; PID_FLOATS];
+        if let Some(addr) = self.load_block(addr, &mut floats) {
+            let mut pid = Pid::from_floats(&floats);
+            let u = pid.step(error);
+            self.store_floats(addr, &pid.to_floats());
+            self.push_float_result(u, false);
+        }
+    }}
+
+    // RATE-LIMITER ( "<spaces>name" -- ) ( F: r-rate r-dt -- )
+    // Create a rate limiter name ( -- f-addr ) changing by at most r-rate
+    // per second when stepped every r-dt seconds.
+    primitive! {fn rate_limiter(&mut self) {
+        let (rate, dt) = self.f_stack().pop2();
+        match RateLimiter::new(rate, dt) {
+            Some(limiter) => {
+                if let Some(addr) = self.create_block(RATE_LIMITER_FLOATS) {
+                    self.store_floats(addr, &limiter.to_floats());
+                }
+            }
+            None => self.abort_with(INVALID_NUMERIC_ARGUMENT),
+        }
+    }}
+
+    // RATE-LIMITER-STEP ( f-addr -- ) ( F: r1 -- r2 )
+    // r2 is the output of the rate limiter at f-addr for input r1.
+    primitive! {fn rate_limiter_step(&mut self) {
+        let addr = self.s_stack().pop();
+        let tag = self.f_stack().dim(0);
+        let x = self.f_stack().pop();
+        let mut floats = [0.0; RATE_LIMITER_FLOATS];
+        if let Some(addr) = self.load_block(addr, &mut floats) {
+            let mut limiter = RateLimiter::from_floats(&floats);
+            let y = limiter.step(x);
+            self.store_floats(addr, &limiter.to_floats());
+            self.push_tagged_result(y, false, Some(tag));
+        }
+    }}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exception::{INVALID_MEMORY_ADDRESS, INVALID_NUMERIC_ARGUMENT};
+    use vm::VMBuilder;
+
+    #[test]
+    fn test_biquad() {
+        let mut biquad = Biquad::new(0.5, 0.5, 0.0, -0.5, 0.0);
+        let (mut x1, mut y1) = (0.0, 0.0);
+        for &x in &[1.0, 0.0, 2.0, -3.0, 0.5, 0.0, 0.0] {
+            let y = 0.5 * x + 0.5 * x1 + 0.5 * y1;
+            assert_ulps_eq!(biquad.step(x), y);
+            x1 = x;
+            y1 = y;
+        }
+        assert_eq!(Biquad::from_floats(&biquad.to_floats()), biquad);
+    }
+
+    #[test]
+    fn test_pid() {
+        let mut pid = Pid::new(1.0, 1.0, 0.1, 0.1, -2.0, 2.0).unwrap();
+        assert_ulps_eq!(pid.step(0.5), 0.5 + 0.05 + 0.5);
+        assert_ulps_eq!(pid.step(0.5), 0.5 + 0.1);
+        // Saturated, the integral does not wind up.
+        for _ in 0..100 {
+            assert_eq!(pid.step(10.0), 2.0);
+        }
+        assert_eq!(pid.step(-0.5), -2.0);
+        assert_ulps_eq!(pid.step(-0.5), -0.5 + 0.05);
+        assert_eq!(Pid::from_floats(&pid.to_floats()), pid);
+        assert!(Pid::new(1.0, 0.0, 0.0, 0.0, -1.0, 1.0).is_none());
+        assert!(Pid::new(1.0, 0.0, 0.0, 0.1, 1.0, -1.0).is_none());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(10.0, 0.1).unwrap();
+        let outputs: Vec<f64> = [5.0, 10.0, 10.0, 3.0, 5.5]
+            .iter()
+            .map(|&x| limiter.step(x))
+            .collect();
+        assert_eq!(outputs, [5.0, 6.0, 7.0, 6.0, 5.5]);
+        assert_eq!(RateLimiter::from_floats(&limiter.to_floats()), limiter);
+        assert!(RateLimiter::new(-1.0, 0.1).is_none());
+    }
+
+    #[test]
+    fn test_dsp_words() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(
+            "create taps  0.5e f, 0.25e f, 0.25e f,
+             taps 3 fir smooth
+             2 moving-average avg
+             0.5e 0.5e 0e -0.5e 0e biquad lp
+             1e 0e 0e 1e -2e 2e pid loop
+             10e 0.1e rate-limiter ramp
+             4e smooth fir-step  8e smooth fir-step
+             0e smooth fir-step  0e smooth fir-step
+             1e avg moving-average-step  3e avg moving-average-step
+             5e avg moving-average-step
+             1e lp biquad-step  0e lp biquad-step
+             3e loop pid-step  -1e loop pid-step
+             5e ramp rate-limiter-step  10e ramp rate-limiter-step",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(
+            vm.f_stack().as_slice(),
+            [2.0, 5.0, 3.0, 2.0, 0.5, 2.0, 4.0, 0.5, 0.75, 2.0, -1.0, 5.0, 6.0]
+        );
+
+        vm.set_source("0 moving-average empty");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_NUMERIC_ARGUMENT));
+        vm.set_error(None);
+        vm.set_source("1e 0e 0e 0e -1e 1e pid stuck");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_NUMERIC_ARGUMENT));
+        vm.set_error(None);
+        vm.set_source("-1 0 fir bad");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_NUMERIC_ARGUMENT));
+        vm.set_error(None);
+        vm.set_source("0e -1 biquad-step");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_MEMORY_ADDRESS));
+        vm.set_error(None);
+        vm.set_source("create nothing 0e f,  0e nothing fir-step");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_NUMERIC_ARGUMENT));
+    }
+
+    #[cfg(feature = "checked-units")]
+    #[test]
+    fn test_filter_dimensions() {
+        use exception::ARGUMENT_TYPE_MISMATCH;
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source("4 moving-average avg  1e mm avg moving-average-step  1e mm f+");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        vm.set_source("1e mm avg moving-average-step  1e f+");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(ARGUMENT_TYPE_MISMATCH));
+    }
+}
@@ -0,0 +1,394 @@
+//! Calling Forth words from Rust
+//!
+//! ```
+//! use rtforth::core::Core;
+//! use rtforth::embed::Embed;
+//! use rtforth::vm::VM;
+//!
+//! let mut vm = VM::new();
+//! vm.set_source(": scale ( n -- n*2 ) ( F: r -- r*2 ) 2 * 2e f* ;");
+//! vm.evaluate_input();
+//! let (n, r): (isize, f64) = vm.call("scale", (3, 1.5)).unwrap();
+//! assert_eq!(n, 6);
+//! assert_eq!(r, 3.0);
+//! ```
+
+use core::Core;
+use exception::{
+    ForthError, DICTIONARY_OVERFLOW, FLOATING_POINT_STACK_OVERFLOW, INVALID_MEMORY_ADDRESS,
+    STACK_OVERFLOW,
+};
+use memory::Memory;
+use std::mem;
+use Result;
+use TRUE;
+
+/// Values which can be passed to a Forth word
+pub trait ToForth {
+    /// Number of cells pushed on the data stack.
+    const CELLS: usize;
+    /// Number of floats pushed on the floating-point stack.
+    const FLOATS: usize;
+    /// Push the value to the stacks of `vm`.
+    fn push<V: Core>(self, vm: &mut V) -> Result;
+}
+
+/// Values which can be returned from a Forth word
+pub trait FromForth: Sized {
+    /// Number of cells popped from the data stack.
+    const CELLS: usize;
+    /// Number of floats popped from the floating-point stack.
+    const FLOATS: usize;
+    /// Pop the value from the stacks of `vm`.
+    fn pop<V: Core>(vm: &mut V) -> Result<Self>;
+}
+
+impl ToForth for isize {
+    const CELLS: usize = 1;
+    const FLOATS: usize = 0;
+    fn push<V: Core>(self, vm: &mut V) -> Result {
+        vm.s_stack().push(self);
+        Ok(())
+    }
+}
+
+impl FromForth for isize {
+    const CELLS: usize = 1;
+    const FLOATS: usize = 0;
+    fn pop<V: Core>(vm: &mut V) -> Result<Self> {
+        Ok(vm.s_stack().pop())
+    }
+}
+
+impl ToForth for f64 {
+    const CELLS: usize = 0;
+    const FLOATS: usize = 1;
+    fn push<V: Core>(self, vm: &mut V) -> Result {
+        vm.f_stack().push(self);
+        Ok(())
+    }
+}
+
+impl FromForth for f64 {
+    const CELLS: usize = 0;
+    const FLOATS: usize = 1;
+    fn pop<V: Core>(vm: &mut V) -> Result<Self> {
+        Ok(vm.f_stack().pop())
+    }
+}
+
+/// `true` is passed as Forth TRUE (-1).
+impl ToForth for bool {
+    const CELLS: usize = 1;
+    const FLOATS: usize = 0;
+    fn push<V: Core>(self, vm: &mut V) -> Result {
+        vm.s_stack().push(if self { TRUE } else { 0 });
+        Ok(())
+    }
+}
+
+/// Any non-zero flag is `true`.
+impl FromForth for bool {
+    const CELLS: usize = 1;
+    const FLOATS: usize = 0;
+    fn pop<V: Core>(vm: &mut V) -> Result<Self> {
+        Ok(vm.s_stack().pop() != 0)
+    }
+}
+
+/// The string is copied into the data space and passed as `( c-addr u )`.
+/// The space is released after the call if the word does not allot.
+impl ToForth for &str {
+    const CELLS: usize = 2;
+    const FLOATS: usize = 0;
+    fn push<V: Core>(self, vm: &mut V) -> Result {
+        let len = self.len();
+        let here = vm.data_space().here();
+        if here + len + mem::size_of::<usize>() > vm.data_space().dictionary_limit() {
+            return Err(ForthError::from(DICTIONARY_OVERFLOW));
+        }
+        let addr = vm.data_space().compile_str(self) + mem::size_of::<usize>();
+        vm.s_stack().push2(addr as isize, len as isize);
+        Ok(())
+    }
+}
+
+impl ToForth for String {
+    const CELLS: usize = 2;
+    const FLOATS: usize = 0;
+    fn push<V: Core>(self, vm: &mut V) -> Result {
+        self.as_str().push(vm)
+    }
+}
+
+/// A string `( c-addr u )` in the data space is copied into a `String`.
+impl FromForth for String {
+    const CELLS: usize = 2;
+    const FLOATS: usize = 0;
+    fn pop<V: Core>(vm: &mut V) -> Result<Self> {
+        let (addr, len) = vm.s_stack().pop2();
+        let (addr, len) = (addr as usize, len as usize);
+        if addr < vm.data_space().start()
+            || len > vm.data_space().limit()
+            || addr > vm.data_space().limit() - len
+        {
+            return Err(ForthError::from(INVALID_MEMORY_ADDRESS));
+        }
+        let bytes = unsafe { vm.data_space().buffer_from_raw_parts(addr, len) };
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+impl ToForth for () {
+    const CELLS: usize = 0;
+    const FLOATS: usize = 0;
+    fn push<V: Core>(self, _vm: &mut V) -> Result {
+        Ok(())
+    }
+}
+
+impl FromForth for () {
+    const CELLS: usize = 0;
+    const FLOATS: usize = 0;
+    fn pop<V: Core>(_vm: &mut V) -> Result<Self> {
+        Ok(())
+    }
+}
+
+// Tuples are pushed from left to right, so that the last element is on
+// the top of its stack.
+macro_rules! tuple_impls {
+    ($($name:ident)+) => {
+        impl<$($name: ToForth),+> ToForth for ($($name,)+) {
+            const CELLS: usize = 0 $(+ $name::CELLS)+;
+            const FLOATS: usize = 0 $(+ $name::FLOATS)+;
+            #[allow(non_snake_case)]
+            fn push<V: Core>(self, vm: &mut V) -> Result {
+                let ($($name,)+) = self;
+                $($name.push(vm)?;)+
+                Ok(())
+            }
+        }
+
+        impl<$($name: FromForth),+> FromForth for ($($name,)+) {
+            const CELLS: usize = 0 $(+ $name::CELLS)+;
+            const FLOATS: usize = 0 $(+ $name::FLOATS)+;
+            #[allow(non_snake_case)]
+            fn pop<V: Core>(vm: &mut V) -> Result<Self> {
+                tuple_impls!(@pop vm; []; $($name)+);
+                Ok(($($name,)+))
+            }
+        }
+    };
+    // Pop in reverse order.
+    (@pop $vm:ident; [$($rev:ident)*]; $first:ident $($rest:ident)*) => {
+        tuple_impls!(@pop $vm; [$first $($rev)*]; $($rest)*);
+    };
+    (@pop $vm:ident; [$($rev:ident)*];) => {
+        $(let $rev = $rev::pop($vm)?;)*
+    };
+}
+
+tuple_impls! { A }
+tuple_impls! { A B }
+tuple_impls! { A B C }
+tuple_impls! { A B C D }
+tuple_impls! { A B C D E }
+tuple_impls! { A B C D E F }
+
+/// Typed calls of Forth words
+pub trait Embed: Core {
+    /// Call the word `name` with `args` and return its results.
+    ///
+    /// The word is expected to consume all `args` and leave exactly the
+    /// results of type `R` on the stacks. Otherwise the stacks are
+    /// restored to their contents before the call and an error is
+    /// returned. An exception raised by the word is returned as
+    /// `ForthError` and cleared from the VM, and the stacks cleared by the
+    /// exception are restored as well.
+    fn call<A: ToForth, R: FromForth>(&mut self, name: &str, args: A) -> Result<R> {
+        let xt = match self.find(name) {
+            Some(xt) => xt,
+            None => return Err(ForthError::UndefinedWord(name.to_string())),
+        };
+        let here = self.data_space().here();
+        let s_depth = self.s_stack().len() as usize;
+        let f_depth = self.f_stack().len() as usize;
+        let s_saved = self.s_stack().inner;
+        let f_saved = self.f_stack().inner;
+        let restore = |vm: &mut Self| {
+            vm.s_stack().restore(&s_saved[..s_depth]);
+            vm.f_stack().restore(&f_saved[..f_depth]);
+        };
+        if let Err(e) = args.push(self) {
+            restore(self);
+            self.data_space().truncate(here);
+            return Err(e);
+        }
+        if self.s_stack().overflow() {
+            restore(self);
+            self.data_space().truncate(here);
+            return Err(ForthError::from(STACK_OVERFLOW));
+        }
+        if self.f_stack().overflow() {
+            restore(self);
+            self.data_space().truncate(here);
+            return Err(ForthError::from(FLOATING_POINT_STACK_OVERFLOW));
+        }
+        let args_end = self.data_space().here();
+        let ip = self.state().instruction_pointer;
+        self.state().instruction_pointer = 0;
+        self.execute_word(xt);
+        self.run();
+        self.check_stacks();
+        self.state().instruction_pointer = ip;
+        if self.data_space().here() == args_end {
+            self.data_space().truncate(here);
+        }
+        if let Some(e) = self.last_error() {
+            self.set_error(None);
+            restore(self);
+            return Err(ForthError::from(e));
+        }
+        let s_expected = s_depth + R::CELLS;
+        let f_expected = f_depth + R::FLOATS;
+        let s_found = self.s_stack().len() as usize;
+        let f_found = self.f_stack().len() as usize;
+        if s_found != s_expected {
+            restore(self);
+            return Err(ForthError::StackDepth {
+                expected: s_expected,
+                found: s_found,
+            });
+        }
+        if f_found != f_expected {
+            restore(self);
+            return Err(ForthError::FloatStackDepth {
+                expected: f_expected,
+                found: f_found,
+            });
+        }
+        R::pop(self)
+    }
+}
+
+impl<T: Core> Embed for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::Embed;
+    use core::Core;
+    use exception::{
+        ForthError, DIVISION_BY_ZERO, FLOATING_POINT_STACK_UNDERFLOW, STACK_OVERFLOW,
+        UNDEFINED_WORD, UNEXPECTED_STACK_DEPTH,
+    };
+    use memory::Memory;
+    use mock_vm::{NewVM, VM};
+    use Result;
+
+    #[test]
+    fn test_call_values() {
+        let vm = &mut VM::new();
+        vm.set_source(": sum3 + + ;  : f2* 2e f* ;  : within10 0 10 within ;");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.call::<_, isize>("sum3", (1, 2, 3)).unwrap(), 6);
+        assert_eq!(vm.call::<_, f64>("f2*", 1.25).unwrap(), 2.5);
+        assert!(vm.call::<_, bool>("within10", 5).unwrap());
+        assert!(!vm.call::<_, bool>("within10", 15).unwrap());
+        assert_eq!(
+            vm.call::<_, (isize, isize)>("swap", (1, 2)).unwrap(),
+            (2, 1)
+        );
+        assert_eq!(vm.s_stack().len(), 0);
+        assert_eq!(vm.f_stack().len(), 0);
+    }
+
+    #[test]
+    fn test_call_strings() {
+        let vm = &mut VM::new();
+        let here = vm.data_space().here();
+        assert_eq!(vm.call::<_, isize>("nip", "hello").unwrap(), 5);
+        assert_eq!(vm.call::<_, String>("noop", "hello").unwrap(), "hello");
+        assert_eq!(vm.data_space().here(), here);
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let vm = &mut VM::new();
+        match vm.call::<_, ()>("no-such-word", ()) {
+            Err(ForthError::UndefinedWord(ref name)) if name == "no-such-word" => {}
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(
+            vm.call::<_, isize>("/", (1, 0)).unwrap_err().code(),
+            DIVISION_BY_ZERO
+        );
+        assert_eq!(vm.last_error(), None);
+        vm.s_stack().push(7);
+        match vm.call::<_, isize>("dup", 1) {
+            Err(ForthError::StackDepth {
+                expected: 2,
+                found: 3,
+            }) => {}
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(vm.s_stack().as_slice(), [7]);
+        assert_eq!(
+            vm.call::<_, ()>("fdrop", ()).unwrap_err().code(),
+            FLOATING_POINT_STACK_UNDERFLOW
+        );
+    }
+
+    #[test]
+    fn test_call_error_keeps_stacks() {
+        let vm = &mut VM::new();
+        vm.set_source(": fail 1 2 1e abort ;");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        vm.s_stack().push2(7, 8);
+        vm.f_stack().push(1.5);
+        assert!(vm.call::<_, isize>("fail", 3).is_err());
+        assert_eq!(
+            vm.call::<_, isize>("/", (1, 0)).unwrap_err().code(),
+            DIVISION_BY_ZERO
+        );
+        assert!(vm.call::<_, ()>("2drop", ()).is_err());
+        assert_eq!(vm.s_stack().as_slice(), [7, 8]);
+        assert_eq!(vm.f_stack().as_slice(), [1.5]);
+    }
+
+    #[test]
+    fn test_call_stack_overflow() {
+        let vm = &mut VM::new();
+        let cap = vm.s_stack().capacity();
+        for i in 0..cap - 1 {
+            vm.s_stack().push(i as isize);
+        }
+        assert_eq!(
+            vm.call::<_, isize>("+", (1, 2)).unwrap_err().code(),
+            STACK_OVERFLOW
+        );
+        assert_eq!(vm.s_stack().len() as usize, cap - 1);
+        assert!(!vm.s_stack().overflow());
+        assert_eq!(vm.call::<_, isize>("dup", ()).unwrap(), cap as isize - 2);
+    }
+
+    #[test]
+    fn test_call_in_forth_result() {
+        fn scaled(vm: &mut VM, name: &str) -> Result<isize> {
+            let n: isize = vm.call(name, 3)?;
+            Ok(n * 10)
+        }
+        let vm = &mut VM::new();
+        assert_eq!(scaled(vm, "1+").unwrap(), 40);
+        let e = scaled(vm, "no-such-word").unwrap_err();
+        assert_eq!(e.code(), UNDEFINED_WORD);
+        assert_eq!(format!("{}", e), "Undefined word no-such-word");
+        vm.s_stack().push(1);
+        assert_eq!(
+            scaled(vm, "drop").unwrap_err().code(),
+            UNEXPECTED_STACK_DEPTH
+        );
+    }
+}
@@ -1,3 +1,7 @@
+use std::error;
+use std::fmt;
+use std::io;
+
 /// Exceptions assigned by Forth standard
 
 pub const ABORT: isize = -1;
@@ -32,9 +36,9 @@ pub const COMPILER_NESTING: isize = -29;
 pub const OBSOLESCENT_FEATURE: isize = -30;
 pub const TO_BODY_USED_ON_NON_CREATED_DEFINITION: isize = -31;
 pub const INVALID_NAME_ARGUMENT: isize = -32;
-pub const BLOCK_READ_EXCEPTION: isize = -33; /* Not used by rtForth */
-pub const BLOCK_WRITE_EXCEPTION: isize = -34; /* Not used by rtForth */
-pub const INVALID_BLOCK_NUMBER: isize = -35; /* Not used by rtForth */
+pub const BLOCK_READ_EXCEPTION: isize = -33;
+pub const BLOCK_WRITE_EXCEPTION: isize = -34;
+pub const INVALID_BLOCK_NUMBER: isize = -35;
 pub const INVALID_FILE_POSITION: isize = -36;
 pub const FILE_IO_EXCEPTION: isize = -37;
 pub const NON_EXISTENT_FILE: isize = -38;
@@ -58,6 +62,9 @@ pub const FLOATING_POINT_UNIDENTIFIED_FAULT: isize = -55;
 pub const QUIT: isize = -56;
 pub const EXCEPTION_IN_SENDING_OR_RECEIVING_A_CHARACTER: isize = -57;
 pub const BRACKET_IF_ELSE_OR_THEN_EXCEPTION: isize = -58;
+pub const ALLOCATE_EXCEPTION: isize = -59;
+pub const FREE_EXCEPTION: isize = -60;
+pub const RESIZE_EXCEPTION: isize = -61;
 
 /// rtForth system exceptions (-511..-256)
 
@@ -66,6 +73,11 @@ pub const INVALID_EXECUTION_TOKEN: isize = -257;
 pub const INTEGER_UNIDENTIFIED_FAULT: isize = -258;
 pub const INCOMPATIBLE_THREADED_WORD: isize = -259;
 pub const INTERPRET_ONLY: isize = -260;
+pub const SANDBOX_INSTRUCTION_LIMIT: isize = -261;
+pub const SANDBOX_DATA_LIMIT: isize = -262;
+pub const SANDBOX_DEFINITION: isize = -263;
+pub const INVALID_GCODE: isize = -264;
+pub const UNEXPECTED_STACK_DEPTH: isize = -265;
 
 /// Description of the exception
 pub fn description(e: isize) -> &'static str {
@@ -130,11 +142,151 @@ pub fn description(e: isize) -> &'static str {
             "Exception in sending or receiving a character"
         }
         BRACKET_IF_ELSE_OR_THEN_EXCEPTION => "[IF],[ELSE],[THEN] exception",
+        ALLOCATE_EXCEPTION => "ALLOCATE exception",
+        FREE_EXCEPTION => "FREE exception",
+        RESIZE_EXCEPTION => "RESIZE exception",
         UNSUPPORTED_BASE_FOR_INTEGER_CONVERSION => "Unsupported base for integer conversion",
         INVALID_EXECUTION_TOKEN => "Invalid execution token",
         INTEGER_UNIDENTIFIED_FAULT => "Integer unidentified fault",
         INCOMPATIBLE_THREADED_WORD => "Incompatible threaded word",
         INTERPRET_ONLY => "Interpret only",
+        SANDBOX_INSTRUCTION_LIMIT => "Sandbox instruction limit exceeded",
+        SANDBOX_DATA_LIMIT => "Sandbox data space limit exceeded",
+        SANDBOX_DEFINITION => "Definition not allowed in sandbox",
+        INVALID_GCODE => "Invalid G-code",
+        UNEXPECTED_STACK_DEPTH => "Unexpected stack depth",
         _ => "Unknown exception",
     }
 }
+
+/// Error of the Rust API
+///
+/// Converts from and to the numeric exception codes seen by Forth code.
+#[derive(Debug)]
+pub enum ForthError {
+    /// Exception assigned by Forth standard (-255..-1)
+    Standard(isize),
+    /// rtForth system exception (-511..-256)
+    System(isize),
+    /// Any other code, given to THROW by user programs
+    User(isize),
+    /// I/O error on file `path`
+    Io { path: String, error: io::Error },
+    /// No word with this name is found.
+    UndefinedWord(String),
+    /// The data stack depth after a call from Rust is not the expected one.
+    StackDepth { expected: usize, found: usize },
+    /// The floating-point stack depth after a call from Rust is not the
+    /// expected one.
+    FloatStackDepth { expected: usize, found: usize },
+}
+
+impl ForthError {
+    /// Exception code seen by Forth code.
+    pub fn code(&self) -> isize {
+        match *self {
+            ForthError::Standard(e) | ForthError::System(e) | ForthError::User(e) => e,
+            ForthError::Io { ref error, .. } => {
+                if error.kind() == io::ErrorKind::NotFound {
+                    NON_EXISTENT_FILE
+                } else {
+                    FILE_IO_EXCEPTION
+                }
+            }
+            ForthError::UndefinedWord(_) => UNDEFINED_WORD,
+            ForthError::StackDepth { .. } | ForthError::FloatStackDepth { .. } => {
+                UNEXPECTED_STACK_DEPTH
+            }
+        }
+    }
+
+    /// I/O error `error` on file `path`.
+    pub fn io(path: &str, error: io::Error) -> ForthError {
+        ForthError::Io {
+            path: path.to_string(),
+            error,
+        }
+    }
+}
+
+impl From<isize> for ForthError {
+    fn from(e: isize) -> ForthError {
+        if (-255..=-1).contains(&e) {
+            ForthError::Standard(e)
+        } else if (-511..=-256).contains(&e) {
+            ForthError::System(e)
+        } else {
+            ForthError::User(e)
+        }
+    }
+}
+
+impl From<ForthError> for isize {
+    fn from(e: ForthError) -> isize {
+        e.code()
+    }
+}
+
+impl fmt::Display for ForthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ForthError::Standard(e) | ForthError::System(e) => {
+                write!(f, "{} ({})", description(e), e)
+            }
+            ForthError::User(e) => write!(f, "User exception ({})", e),
+            ForthError::Io {
+                ref path,
+                ref error,
+            } => write!(f, "{}: {}", path, error),
+            ForthError::UndefinedWord(ref name) => write!(f, "Undefined word {}", name),
+            ForthError::StackDepth { expected, found } => write!(
+                f,
+                "Data stack depth {} after call, expected {}",
+                found, expected
+            ),
+            ForthError::FloatStackDepth { expected, found } => write!(
+                f,
+                "Floating-point stack depth {} after call, expected {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl error::Error for ForthError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ForthError::Io { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_forth_error_codes() {
+        match ForthError::from(DIVISION_BY_ZERO) {
+            ForthError::Standard(DIVISION_BY_ZERO) => {}
+            e => panic!("{:?}", e),
+        }
+        match ForthError::from(INVALID_EXECUTION_TOKEN) {
+            ForthError::System(INVALID_EXECUTION_TOKEN) => {}
+            e => panic!("{:?}", e),
+        }
+        match ForthError::from(1) {
+            ForthError::User(1) => {}
+            e => panic!("{:?}", e),
+        }
+        assert_eq!(isize::from(ForthError::from(-4096)), -4096);
+        let e = ForthError::io("a.fs", io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(e.code(), NON_EXISTENT_FILE);
+        assert_eq!(
+            format!("{}", ForthError::from(DIVISION_BY_ZERO)),
+            "Division by zero (-10)"
+        );
+    }
+}
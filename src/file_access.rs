@@ -1,13 +1,205 @@
 use exception::{
-    FILE_IO_EXCEPTION, INVALID_MEMORY_ADDRESS, INVALID_NUMERIC_ARGUMENT, RESULT_OUT_OF_RANGE,
+    ForthError, FILE_IO_EXCEPTION, INVALID_MEMORY_ADDRESS, INVALID_NUMERIC_ARGUMENT,
+    RESULT_OUT_OF_RANGE, WRITE_TO_A_READ_ONLY_LOCATION,
 };
-use std::fs::{self, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use Core;
 use Memory;
+use {FALSE, TRUE};
 
 const PATH_NAME_MAX_LEN: usize = 256;
 
+enum Buffer {
+    Reader(BufReader<File>),
+    Writer(BufWriter<File>),
+}
+
+/// Move the file of `r` back to the first byte not yet consumed and
+/// discard the read-ahead. Returns the position.
+fn discard_read_ahead(r: &mut BufReader<File>) -> io::Result<u64> {
+    let n = r.buffer().len();
+    let pos = r.get_mut().seek(SeekFrom::Current(-(n as i64)))?;
+    r.consume(n);
+    Ok(pos)
+}
+
+/// File opened by OPEN-FILE or CREATE-FILE
+///
+/// Reads and writes are buffered. The buffer is flushed when switching
+/// between reading and writing, so that both share one file position.
+pub struct FileHandle {
+    // None only while switching buffers.
+    inner: Option<Buffer>,
+}
+
+impl FileHandle {
+    pub fn new(file: File) -> FileHandle {
+        FileHandle {
+            inner: Some(Buffer::Reader(BufReader::new(file))),
+        }
+    }
+
+    /// Unbuffered file positioned at the current position of the handle.
+    pub fn into_file(mut self) -> io::Result<File> {
+        self.take_file()
+    }
+
+    // Flush the buffer and take the file out of it. The buffer is kept if
+    // flushing fails.
+    fn take_file(&mut self) -> io::Result<File> {
+        match self.inner.take().expect("file buffer") {
+            Buffer::Reader(mut r) => match discard_read_ahead(&mut r) {
+                Ok(_) => Ok(r.into_inner()),
+                Err(e) => {
+                    self.inner = Some(Buffer::Reader(r));
+                    Err(e)
+                }
+            },
+            Buffer::Writer(w) => match w.into_inner() {
+                Ok(f) => Ok(f),
+                Err(e) => {
+                    let kind = e.error().kind();
+                    self.inner = Some(Buffer::Writer(e.into_inner()));
+                    Err(io::Error::from(kind))
+                }
+            },
+        }
+    }
+
+    fn reader(&mut self) -> io::Result<&mut BufReader<File>> {
+        if let Some(Buffer::Writer(_)) = self.inner {
+            let f = self.take_file()?;
+            self.inner = Some(Buffer::Reader(BufReader::new(f)));
+        }
+        match self.inner {
+            Some(Buffer::Reader(ref mut r)) => Ok(r),
+            _ => unreachable!(),
+        }
+    }
+
+    fn writer(&mut self) -> io::Result<&mut BufWriter<File>> {
+        if let Some(Buffer::Reader(_)) = self.inner {
+            let f = self.take_file()?;
+            self.inner = Some(Buffer::Writer(BufWriter::new(f)));
+        }
+        match self.inner {
+            Some(Buffer::Writer(ref mut w)) => Ok(w),
+            _ => unreachable!(),
+        }
+    }
+
+    // Apply `f` to the unbuffered file.
+    fn with_file<T, F: FnOnce(&mut File) -> io::Result<T>>(&mut self, f: F) -> io::Result<T> {
+        let mut file = self.take_file()?;
+        let result = f(&mut file);
+        self.inner = Some(Buffer::Reader(BufReader::new(file)));
+        result
+    }
+
+    /// Read into `buf` until it is full or the end of file is reached.
+    /// Returns the number of bytes read.
+    pub fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let r = self.reader()?;
+        let mut n = 0;
+        while n < buf.len() {
+            match r.read(&mut buf[n..]) {
+                Ok(0) => break,
+                Ok(m) => n += m,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(n)
+    }
+
+    /// Read the next line into `buf`, without the line terminator LF or
+    /// CR LF. Returns the length of the line and false at the end of file.
+    ///
+    /// If the line is longer than `buf`, `buf.len()` bytes are read and
+    /// the rest of the line is left for the next read.
+    pub fn read_line(&mut self, buf: &mut [u8]) -> io::Result<(usize, bool)> {
+        let r = self.reader()?;
+        if buf.is_empty() {
+            // Nothing is read, but the flag still tells if there is more.
+            return Ok((0, !r.fill_buf()?.is_empty()));
+        }
+        let mut n = 0;
+        let mut found = false;
+        while n < buf.len() {
+            let (used, end) = {
+                let available = r.fill_buf()?;
+                if available.is_empty() {
+                    break;
+                }
+                found = true;
+                let len = available.len().min(buf.len() - n);
+                match available[..len].iter().position(|&c| c == b'\n') {
+                    Some(i) => {
+                        buf[n..n + i].copy_from_slice(&available[..i]);
+                        n += i;
+                        (i + 1, true)
+                    }
+                    None => {
+                        buf[n..n + len].copy_from_slice(&available[..len]);
+                        n += len;
+                        (len, false)
+                    }
+                }
+            };
+            r.consume(used);
+            if end {
+                if n > 0 && buf[n - 1] == b'\r' {
+                    n -= 1;
+                }
+                break;
+            }
+        }
+        Ok((n, found))
+    }
+
+    /// Write all of `buf`.
+    pub fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.writer()?.write_all(buf)
+    }
+
+    /// Write `buf` followed by a line terminator.
+    pub fn write_line(&mut self, buf: &[u8]) -> io::Result<()> {
+        let w = self.writer()?;
+        w.write_all(buf)?;
+        w.write_all(b"\n")
+    }
+
+    /// Write buffered data to the storage device.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.with_file(|f| f.sync_all())
+    }
+
+    /// Size of the file in bytes, including buffered data.
+    pub fn size(&mut self) -> io::Result<u64> {
+        self.with_file(|f| f.metadata().map(|m| m.len()))
+    }
+
+    /// Truncate or extend the file to `size` bytes.
+    pub fn set_size(&mut self, size: u64) -> io::Result<()> {
+        self.with_file(|f| f.set_len(size))
+    }
+
+    /// Current position.
+    pub fn position(&mut self) -> io::Result<u64> {
+        discard_read_ahead(self.reader()?)
+    }
+
+    /// Move to position `pos`.
+    pub fn seek(&mut self, pos: u64) -> io::Result<()> {
+        match self.inner {
+            Some(Buffer::Reader(ref mut r)) => r.seek(SeekFrom::Start(pos)).map(|_| ()),
+            Some(Buffer::Writer(ref mut w)) => w.seek(SeekFrom::Start(pos)).map(|_| ()),
+            None => unreachable!(),
+        }
+    }
+}
+
 pub trait FileAccess: Core {
     fn add_file_access(&mut self) {
         self.add_primitive("file-size", FileAccess::file_size);
@@ -20,6 +212,23 @@ pub trait FileAccess: Core {
         self.add_primitive("write-file", FileAccess::write_file);
         self.add_primitive("resize-file", FileAccess::resize_file);
         self.add_primitive("reposition-file", FileAccess::reposition_file);
+        self.add_primitive("read-line", FileAccess::read_line);
+        self.add_primitive("write-line", FileAccess::write_line);
+        self.add_primitive("flush-file", FileAccess::flush_file);
+        self.add_primitive("file-status", FileAccess::file_status);
+        self.add_primitive("rename-file", FileAccess::rename_file);
+    }
+
+    /// Path name in the character string specified by `caddr` and `u`.
+    fn path_name(&mut self, caddr: usize, u: usize) -> Option<String> {
+        if u <= PATH_NAME_MAX_LEN
+            && self.data_space().start() <= caddr
+            && caddr + u <= self.data_space().limit()
+        {
+            Some(unsafe { self.data_space().str_from_raw_parts(caddr, u) }.to_string())
+        } else {
+            None
+        }
     }
 
     /// ( fileid -- ud ior )
@@ -42,11 +251,10 @@ pub trait FileAccess: Core {
         }
         let fileid = fileid as usize - 1;
         if fileid < self.files().len() {
-            let ud = match &self.files()[fileid] {
-                &Some(ref f) => {
-                    match f.metadata() {
-                        Ok(m) => {
-                            let ud = m.len();
+            let ud = match self.files_mut()[fileid] {
+                Some(ref mut f) => {
+                    match f.size() {
+                        Ok(ud) => {
                             if ud <= isize::max_value() as u64 {
                                 Ok(ud)
                             } else {
@@ -58,7 +266,7 @@ pub trait FileAccess: Core {
                         }
                     }
                 }
-                &None => {
+                None => {
                     Err(INVALID_NUMERIC_ARGUMENT)
                 }
             };
@@ -96,7 +304,7 @@ pub trait FileAccess: Core {
         if fileid < self.files().len() {
             let ud = match &mut self.files_mut()[fileid] {
                 &mut Some(ref mut f) => {
-                    match f.seek(SeekFrom::Current(0)) {
+                    match f.position() {
                         Ok(ud) => {
                             if ud <= isize::max_value() as u64 {
                                 Ok(ud)
@@ -209,13 +417,13 @@ pub trait FileAccess: Core {
                 });
                 match position {
                     Some(p) => {
-                        self.files_mut()[p] = Some(file);
+                        self.files_mut()[p] = Some(FileHandle::new(file));
                         self.s_stack().push2(p as isize + 1, 0);
                     }
                     None => {
                         let fileid = self.files().len() as isize;
                         self.s_stack().push2(fileid + 1, 0);
-                        self.files_mut().push(Some(file));
+                        self.files_mut().push(Some(FileHandle::new(file)));
                     }
                 }
             }
@@ -307,13 +515,13 @@ pub trait FileAccess: Core {
                 });
                 match position {
                     Some(p) => {
-                        self.files_mut()[p] = Some(file);
+                        self.files_mut()[p] = Some(FileHandle::new(file));
                         self.s_stack().push2(p as isize + 1, 0);
                     }
                     None => {
                         let fileid = self.files().len() as isize;
                         self.s_stack().push2(fileid + 1, 0);
-                        self.files_mut().push(Some(file));
+                        self.files_mut().push(Some(FileHandle::new(file)));
                     }
                 }
             }
@@ -361,9 +569,11 @@ pub trait FileAccess: Core {
         } else {
             let mut file = self.files_mut()[fileid].take().unwrap();
             let result = {
-                if self.data_space().start() <= caddr && caddr + u1 <= self.data_space().limit() {
-                    let mut buf = unsafe{ self.data_space().buffer_from_raw_parts_mut(caddr, u1) };
-                    file.read(&mut buf).or(Err(FILE_IO_EXCEPTION as _))
+                if self.data_space().is_read_only(caddr, u1) {
+                    Err(WRITE_TO_A_READ_ONLY_LOCATION as _)
+                } else if self.data_space().start() <= caddr && caddr + u1 <= self.data_space().limit() {
+                    let buf = unsafe{ self.data_space().buffer_from_raw_parts_mut(caddr, u1) };
+                    file.read(buf).or(Err(FILE_IO_EXCEPTION as _))
                 } else {
                     Err(INVALID_MEMORY_ADDRESS as _)
                 }
@@ -405,7 +615,7 @@ pub trait FileAccess: Core {
                     let result = {
                         if self.data_space().start() <= caddr && caddr + u <= self.data_space().limit() {
                             let buf = unsafe{ self.data_space().buffer_from_raw_parts(caddr as _, u as _) };
-                            f.write_all(buf).or(Err(FILE_IO_EXCEPTION))
+                            f.write(buf).or(Err(FILE_IO_EXCEPTION))
                         } else {
                             Err(INVALID_MEMORY_ADDRESS)
                         }
@@ -458,8 +668,8 @@ pub trait FileAccess: Core {
             self.s_stack().push(INVALID_NUMERIC_ARGUMENT as _);
         } else {
             match self.files_mut()[fileid].take() {
-                Some(f) => {
-                    match f.set_len(ud_lower as u64) {
+                Some(mut f) => {
+                    match f.set_size(ud_lower as u64) {
                         Ok(_) => {
                             self.s_stack().push(0);
                         }
@@ -506,7 +716,7 @@ pub trait FileAccess: Core {
         } else {
             match self.files_mut()[fileid].take() {
                 Some(mut f) => {
-                    match f.seek(SeekFrom::Start(ud_lower as u64)) {
+                    match f.seek(ud_lower as u64) {
                         Ok(_) => {
                             self.s_stack().push(0);
                         }
@@ -522,4 +732,295 @@ pub trait FileAccess: Core {
             }
         }
     }}
+
+    /// ( c-addr u1 fileid -- u2 flag ior )
+    ///
+    /// Read the next line from the file identified by fileid into the
+    /// memory at c-addr. At most u1 characters are read. The line
+    /// terminator is not stored.
+    ///
+    /// If the operation succeeded, flag is true and ior is zero. u2 is the
+    /// number of characters read. If u2 equals u1, the end of the line may
+    /// not have been reached yet. If the operation is initiated at the end
+    /// of file, flag is false and u2 is zero.
+    primitive! {fn read_line(&mut self) {
+        let (caddr, u1, fileid) = self.s_stack().pop3();
+        let caddr = caddr as usize;
+        let u1 = u1 as usize;
+        if fileid <= 0 || fileid as usize > self.files().len() {
+            self.s_stack().push3(0, FALSE, INVALID_NUMERIC_ARGUMENT);
+            return;
+        }
+        let fileid = fileid as usize - 1;
+        match self.files_mut()[fileid].take() {
+            Some(mut f) => {
+                let result = {
+                    if self.data_space().is_read_only(caddr, u1) {
+                        Err(WRITE_TO_A_READ_ONLY_LOCATION)
+                    } else if self.data_space().start() <= caddr && caddr + u1 <= self.data_space().limit() {
+                        let buf = unsafe{ self.data_space().buffer_from_raw_parts_mut(caddr, u1) };
+                        f.read_line(buf).or(Err(FILE_IO_EXCEPTION))
+                    } else {
+                        Err(INVALID_MEMORY_ADDRESS)
+                    }
+                };
+                match result {
+                    Ok((u2, not_eof)) => {
+                        self.s_stack().push3(u2 as isize, if not_eof { TRUE } else { FALSE }, 0);
+                    }
+                    Err(e) => {
+                        self.s_stack().push3(0, FALSE, e);
+                    }
+                }
+                self.files_mut()[fileid] = Some(f);
+            }
+            None => {
+                self.s_stack().push3(0, FALSE, INVALID_NUMERIC_ARGUMENT);
+            }
+        }
+    }}
+
+    /// ( c-addr u fileid -- ior )
+    ///
+    /// Write u characters from c-addr followed by a line terminator to the
+    /// file identified by fileid starting at its current position.
+    primitive! {fn write_line(&mut self) {
+        let (caddr, u, fileid) = self.s_stack().pop3();
+        let caddr = caddr as usize;
+        let u = u as usize;
+        if fileid <= 0 || fileid as usize > self.files().len() {
+            self.s_stack().push(INVALID_NUMERIC_ARGUMENT);
+            return;
+        }
+        let fileid = fileid as usize - 1;
+        match self.files_mut()[fileid].take() {
+            Some(mut f) => {
+                let result = {
+                    if self.data_space().start() <= caddr && caddr + u <= self.data_space().limit() {
+                        let buf = unsafe{ self.data_space().buffer_from_raw_parts(caddr, u) };
+                        f.write_line(buf).or(Err(FILE_IO_EXCEPTION))
+                    } else {
+                        Err(INVALID_MEMORY_ADDRESS)
+                    }
+                };
+                match result {
+                    Ok(_) => self.s_stack().push(0),
+                    Err(e) => self.s_stack().push(e),
+                }
+                self.files_mut()[fileid] = Some(f);
+            }
+            None => {
+                self.s_stack().push(INVALID_NUMERIC_ARGUMENT);
+            }
+        }
+    }}
+
+    /// ( fileid -- ior )
+    ///
+    /// Write buffered data of the file identified by fileid to the storage
+    /// device.
+    primitive! {fn flush_file(&mut self) {
+        let fileid = self.s_stack().pop();
+        if fileid <= 0 || fileid as usize > self.files().len() {
+            self.s_stack().push(INVALID_NUMERIC_ARGUMENT);
+            return;
+        }
+        let result = match self.files_mut()[fileid as usize - 1] {
+            Some(ref mut f) => {
+                match f.flush() {
+                    Ok(_) => 0,
+                    Err(_) => FILE_IO_EXCEPTION,
+                }
+            }
+            None => INVALID_NUMERIC_ARGUMENT,
+        };
+        self.s_stack().push(result);
+    }}
+
+    /// ( c-addr u -- x ior )
+    ///
+    /// Status of the file named in the character string specified by c-addr
+    /// u. If the file exists, ior is zero and x is its size in characters.
+    /// Otherwise ior is the I/O result code, NON_EXISTENT_FILE if there is
+    /// no such file.
+    primitive! {fn file_status(&mut self) {
+        let (caddr, u) = self.s_stack().pop2();
+        match self.path_name(caddr as usize, u as usize) {
+            Some(path_name) => {
+                match fs::metadata(&path_name) {
+                    Ok(m) => {
+                        let x = m.len().min(isize::max_value() as u64) as isize;
+                        self.s_stack().push2(x, 0);
+                    }
+                    Err(e) => {
+                        let ior = ForthError::io(&path_name, e).code();
+                        self.s_stack().push2(0, ior);
+                    }
+                }
+            }
+            None => {
+                self.s_stack().push2(0, INVALID_NUMERIC_ARGUMENT);
+            }
+        }
+    }}
+
+    /// ( c-addr1 u1 c-addr2 u2 -- ior )
+    ///
+    /// Rename the file named by the character string c-addr1 u1 to the name
+    /// in the character string c-addr2 u2.
+    primitive! {fn rename_file(&mut self) {
+        let (caddr2, u2) = self.s_stack().pop2();
+        let (caddr1, u1) = self.s_stack().pop2();
+        let from = self.path_name(caddr1 as usize, u1 as usize);
+        let to = self.path_name(caddr2 as usize, u2 as usize);
+        let ior = match (from, to) {
+            (Some(from), Some(to)) => {
+                match fs::rename(&from, &to) {
+                    Ok(_) => 0,
+                    Err(e) => ForthError::io(&from, e).code(),
+                }
+            }
+            _ => INVALID_NUMERIC_ARGUMENT,
+        };
+        self.s_stack().push(ior);
+    }}
+}
+
+#[cfg(test)]
+mod tests {
+    use core::Core;
+    use exception::{NON_EXISTENT_FILE, PARSED_STRING_OVERFLOW};
+    use memory::Memory;
+    use std::env;
+    use std::fs;
+    use vm::VMBuilder;
+
+    fn file_path(name: &str) -> String {
+        env::temp_dir()
+            .join(format!("rtforth-{}-{}.txt", name, ::std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_read_write_line() {
+        let path = file_path("lines");
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(&format!(
+            "variable fid  s\" {}\" r/w create-file drop fid !
+             s\" hello\" fid @ write-line  s\" abcdef\" fid @ write-line
+             0 0 fid @ reposition-file  fid @ file-size 2drop",
+            path
+        ));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [0, 0, 0, 13]);
+        vm.s_stack().reset();
+        vm.set_source(
+            "pad 80 fid @ read-line  pad 3 fid @ read-line  pad 3 fid @ read-line
+             pad 80 fid @ read-line  pad 80 fid @ read-line",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(
+            vm.s_stack().as_slice(),
+            [5, -1, 0, 3, -1, 0, 3, -1, 0, 0, -1, 0, 0, 0, 0]
+        );
+        vm.s_stack().reset();
+        vm.set_source("fid @ file-position 2drop  pad c@  fid @ close-file");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [13, b'd' as isize, 0]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_line_crlf() {
+        let path = file_path("crlf");
+        fs::write(&path, b"one\r\ntwo").unwrap();
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(&format!(
+            "variable fid  s\" {}\" r/o open-file drop fid !
+             pad 0 fid @ read-line
+             pad 80 fid @ read-line  pad 80 fid @ read-line  pad c@
+             pad 0 fid @ read-line",
+            path
+        ));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(
+            vm.s_stack().as_slice(),
+            [0, -1, 0, 3, -1, 0, 3, -1, 0, b't' as isize, 0, 0, 0]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_status_rename_flush() {
+        let from = file_path("rename-from");
+        let to = file_path("rename-to");
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(&format!(
+            "variable fid  s\" {}\" w/o create-file drop fid !
+             s\" abc\" fid @ write-file  fid @ flush-file",
+            from
+        ));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [0, 0]);
+        assert_eq!(fs::read(&from).unwrap(), b"abc");
+        vm.s_stack().reset();
+        vm.set_source(&format!(
+            "fid @ close-file  s\" {0}\" s\" {1}\" rename-file
+             s\" {0}\" file-status nip  s\" {1}\" file-status",
+            from, to
+        ));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [0, 0, NON_EXISTENT_FILE, 3, 0]);
+        fs::remove_file(&to).unwrap();
+    }
+
+    #[test]
+    fn test_interpret_s_quote() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source("s\" abc\" s\" de\"");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        let (a2, u2) = vm.s_stack().pop2();
+        let (a1, u1) = vm.s_stack().pop2();
+        assert_ne!(a1, a2);
+        unsafe {
+            assert_eq!(vm.data_space().str_from_raw_parts(a1 as _, u1 as _), "abc");
+            assert_eq!(vm.data_space().str_from_raw_parts(a2 as _, u2 as _), "de");
+        }
+        let long = "x".repeat(257);
+        vm.set_source(&format!("s\" {}\"", long));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(PARSED_STRING_OVERFLOW));
+        vm.set_error(None);
+        vm.set_source(&format!("s\" {}\" nip", &long[1..]));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().pop(), 256);
+    }
+
+    #[test]
+    fn test_require_and_include_file() {
+        let path = file_path("require");
+        fs::write(&path, b"1 +\n").unwrap();
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(&format!(
+            "0 s\" {0}\" required  s\" {0}\" required  require {0}  s\" {0}\" included",
+            path
+        ));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [2]);
+        vm.set_source(&format!("s\" {}\" r/o open-file drop include-file", path));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [3]);
+        fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,626 @@
+//! Fixed-point word set
+//!
+//! Q-format fixed-point numbers are cells on the data stack with a number
+//! of fraction bits, `Fixed::fraction_bits`, 16 by default, so that 1.5 is
+//! 98304 in Q16. Addition, subtraction and comparison are those of
+//! integers. The other words use only integer arithmetic and round to
+//! nearest, ties away from zero, so that their results are bit-exact on
+//! every target. Sine and cosine are computed with CORDIC in Q61, so they
+//! are exact to about 2^-58. Results out of range abort with
+//! `RESULT_OUT_OF_RANGE`.
+//!
+//! ```
+//! use rtforth::core::Core;
+//! use rtforth::vm::VMBuilder;
+//!
+//! let mut vm = VMBuilder::new().build();
+//! vm.set_source("3 s>q 2 s>q q/  2 s>q qsqrt");
+//! vm.evaluate_input();
+//! assert_eq!(vm.s_stack().as_slice(), [98304, 92682]);
+//! ```
+
+use exception::{DIVISION_BY_ZERO, INVALID_NUMERIC_ARGUMENT, RESULT_OUT_OF_RANGE};
+use memory::Memory;
+use std::mem;
+use units::Units;
+
+/// Fraction bits after start-up.
+pub const DEFAULT_FRACTION_BITS: isize = 16;
+
+/// Largest fraction bits, leaving a sign bit and an integer bit.
+pub const MAX_FRACTION_BITS: u32 = mem::size_of::<isize>() as u32 * 8 - 2;
+
+// Fraction bits of CORDIC.
+const Q: u32 = 61;
+
+// atan(2^-i) in Q61. For larger i, atan(2^-i) rounds to 2^(61-i).
+const ATAN: [i64; 21] = [
+    1811004864519280711,
+    1069098597953152948,
+    564882337777596249,
+    286743094836456889,
+    143927976672616092,
+    72034151524184357,
+    36025865417378411,
+    18014032019027246,
+    9007153442175927,
+    4503593900760542,
+    2251799097857775,
+    1125899817364151,
+    562949942236502,
+    281474975312555,
+    140737488180565,
+    70368744155819,
+    35184372086101,
+    17592186044075,
+    8796093022165,
+    4398046511099,
+    2199023255551,
+];
+
+// Reciprocal of the CORDIC gain in Q61.
+const CORDIC_SCALE: i64 = 1400229935014726477;
+
+// Pi, pi/2 and 2*pi in Q61.
+const PI: i128 = 7244019458077122842;
+const HALF_PI: i128 = 3622009729038561421;
+const TWO_PI: i128 = 14488038916154245685;
+
+// `x` if it fits in a cell.
+fn narrow(x: i128) -> Option<isize> {
+    let n = x as isize;
+    if n as i128 == x {
+        Some(n)
+    } else {
+        None
+    }
+}
+
+// `x` divided by two to the power of `n`, rounded to nearest, ties away
+// from zero.
+fn round_shift(x: i128, n: u32) -> i128 {
+    if n == 0 {
+        x
+    } else {
+        let half = 1i128 << (n - 1);
+        if x < 0 {
+            -((half - x) >> n)
+        } else {
+            (x + half) >> n
+        }
+    }
+}
+
+// `x` in Q`from` converted to Q`to`.
+fn rescale(x: i128, from: u32, to: u32) -> i128 {
+    if from >= to {
+        round_shift(x, from - to)
+    } else {
+        x << (to - from)
+    }
+}
+
+// Square root of `n` rounded down.
+fn isqrt(n: u128) -> u128 {
+    let mut x = n;
+    let mut r = 0u128;
+    let mut bit = 1u128 << 126;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if x >= r + bit {
+            x -= r + bit;
+            r = (r >> 1) + bit;
+        } else {
+            r >>= 1;
+        }
+        bit >>= 2;
+    }
+    r
+}
+
+/// Integer `n` as a Q number, `None` if out of range.
+pub fn q_from_int(n: isize, bits: u32) -> Option<isize> {
+    narrow((n as i128) << bits)
+}
+
+/// Q number `a` rounded to an integer.
+pub fn q_to_int(a: isize, bits: u32) -> isize {
+    round_shift(a as i128, bits) as isize
+}
+
+/// Float `r` as a Q number, `None` if it is not finite or out of range.
+pub fn q_from_f64(r: f64, bits: u32) -> Option<isize> {
+    let x = (r * (1u64 << bits) as f64).round();
+    let limit = (1u64 << (mem::size_of::<isize>() * 8 - 1)) as f64;
+    if x.is_finite() && -limit <= x && x < limit {
+        Some(x as isize)
+    } else {
+        None
+    }
+}
+
+/// Q number `a` as a float.
+pub fn q_to_f64(a: isize, bits: u32) -> f64 {
+    a as f64 / (1u64 << bits) as f64
+}
+
+// Finite float `r` as `(m, e)` with `r` exactly `m * 2^e`, |m| < 2^53.
+fn decompose(r: f64) -> (i128, i32) {
+    let bits = r.to_bits();
+    let exp = ((bits >> 52) & 0x7ff) as i32;
+    let frac = (bits & ((1 << 52) - 1)) as i128;
+    let (m, e) = if exp == 0 {
+        (frac, -1074)
+    } else {
+        (frac | 1 << 52, exp - 1075)
+    };
+    (if r < 0.0 { -m } else { m }, e)
+}
+
+// `n / d` rounded to nearest, ties away from zero. `d` is not zero.
+fn round_div(n: i128, d: i128) -> i128 {
+    let q = n / d;
+    let r = n % d;
+    if 2 * r.abs() < d.abs() {
+        q
+    } else if (n < 0) == (d < 0) {
+        q + 1
+    } else {
+        q - 1
+    }
+}
+
+/// Q number `a` times float `r`, rounded once, `None` if `r` is not
+/// finite or the product is out of range.
+pub fn q_scale(a: isize, r: f64) -> Option<isize> {
+    if !r.is_finite() {
+        return None;
+    }
+    let (m, e) = decompose(r);
+    // |x| < 2^116.
+    let x = a as i128 * m;
+    if x == 0 {
+        Some(0)
+    } else if e >= 0 {
+        if e >= 64 {
+            None
+        } else {
+            x.checked_mul(1 << e).and_then(narrow)
+        }
+    } else if e < -120 {
+        Some(0)
+    } else {
+        narrow(round_shift(x, -e as u32))
+    }
+}
+
+/// Q number `a` divided by float `r`, rounded once, `None` if `r` is zero
+/// or not finite or the quotient is out of range.
+pub fn q_unscale(a: isize, r: f64) -> Option<isize> {
+    if !r.is_finite() || r == 0.0 {
+        return None;
+    }
+    let (m, e) = decompose(r);
+    if a == 0 {
+        Some(0)
+    } else if e <= 0 {
+        // The quotient is out of range if a * 2^-e overflows, |m| < 2^53.
+        if e < -126 {
+            return None;
+        }
+        (a as i128)
+            .checked_mul(1 << -e)
+            .and_then(|n| narrow(round_div(n, m)))
+    } else if e >= 64 {
+        // |a / r| < 2^63 / 2^116.
+        Some(0)
+    } else {
+        narrow(round_div(a as i128, m << e))
+    }
+}
+
+/// Product of Q numbers `a` and `b`, `None` if out of range.
+pub fn q_mul(a: isize, b: isize, bits: u32) -> Option<isize> {
+    narrow(round_shift(a as i128 * b as i128, bits))
+}
+
+/// Quotient of Q numbers `a` and `b`, `None` if `b` is zero or the
+/// quotient is out of range.
+pub fn q_div(a: isize, b: isize, bits: u32) -> Option<isize> {
+    if b == 0 {
+        return None;
+    }
+    narrow(round_div((a as i128) << bits, b as i128))
+}
+
+/// Square root of Q number `a`, `None` if `a` is negative.
+pub fn q_sqrt(a: isize, bits: u32) -> Option<isize> {
+    if a < 0 {
+        return None;
+    }
+    let n = (a as u128) << bits;
+    let r = isqrt(n);
+    let r = if n - r * r > r { r + 1 } else { r };
+    narrow(r as i128)
+}
+
+/// Sine and cosine of angle `a` in radians, all Q numbers.
+pub fn q_sin_cos(a: isize, bits: u32) -> (isize, isize) {
+    // Reduce the angle to -pi/2..=pi/2, negating the cosine if needed.
+    let mut z = rescale(a as i128, bits, Q).rem_euclid(TWO_PI);
+    if z > PI {
+        z -= TWO_PI;
+    }
+    let mut cos_sign = 1;
+    if z > HALF_PI {
+        z = PI - z;
+        cos_sign = -1;
+    } else if z < -HALF_PI {
+        z = -PI - z;
+        cos_sign = -1;
+    }
+    let mut x = CORDIC_SCALE;
+    let mut y = 0i64;
+    let mut z = z as i64;
+    for i in 0..=Q {
+        let t = if (i as usize) < ATAN.len() {
+            ATAN[i as usize]
+        } else {
+            1i64 << (Q - i)
+        };
+        let (dx, dy) = (y >> i, x >> i);
+        if z >= 0 {
+            x -= dx;
+            y += dy;
+            z -= t;
+        } else {
+            x += dx;
+            y -= dy;
+            z += t;
+        }
+    }
+    (
+        rescale(y as i128, Q, bits) as isize,
+        cos_sign * rescale(x as i128, Q, bits) as isize,
+    )
+}
+
+pub trait Fixed: Units {
+    fn add_fixed(&mut self) {
+        self.add_primitive("q-bits", Fixed::q_bits);
+        self.add_primitive("set-q-bits", Fixed::set_q_bits);
+        self.add_primitive("s>q", Fixed::s_to_q);
+        self.add_primitive("q>s", Fixed::q_to_s);
+        self.add_primitive("f>q", Fixed::f_to_q);
+        self.add_primitive("q>f", Fixed::q_to_f);
+        self.add_primitive("unit>q", Fixed::unit_to_q);
+        self.add_primitive("q-unit", Fixed::q_unit);
+        self.add_primitive("q>unit", Fixed::q_to_unit);
+        self.add_primitive("q*", Fixed::q_star);
+        self.add_primitive("q/", Fixed::q_slash);
+        self.add_primitive("qsqrt", Fixed::qsqrt);
+        self.add_primitive("qsin", Fixed::qsin);
+        self.add_primitive("qcos", Fixed::qcos);
+        self.add_primitive("qsincos", Fixed::qsincos);
+    }
+
+    /// Fraction bits of Q numbers, limited to `MAX_FRACTION_BITS`.
+    fn fraction_bits(&mut self) -> u32 {
+        let addr = self.data_space().system_variables().fraction_bits_addr();
+        let bits = unsafe { self.data_space().get_isize(addr) };
+        if bits < 0 {
+            0
+        } else if bits > MAX_FRACTION_BITS as isize {
+            MAX_FRACTION_BITS
+        } else {
+            bits as u32
+        }
+    }
+
+    /// Set fraction bits of Q numbers to `bits`.
+    fn set_fraction_bits(&mut self, bits: u32) {
+        let addr = self.data_space().system_variables().fraction_bits_addr();
+        unsafe { self.data_space().put_isize(bits as isize, addr) };
+    }
+
+    /// Push `result`, or abort with `RESULT_OUT_OF_RANGE` if it is `None`.
+    fn push_q_result(&mut self, result: Option<isize>) {
+        match result {
+            Some(q) => self.s_stack().push(q),
+            None => self.abort_with(RESULT_OUT_OF_RANGE),
+        }
+    }
+
+    // Q-BITS ( -- u )
+    // Fraction bits of Q numbers.
+    primitive! {fn q_bits(&mut self) {
+        let bits = self.fraction_bits();
+        self.s_stack().push(bits as isize);
+    }}
+
+    // SET-Q-BITS ( u -- )
+    // Set fraction bits of Q numbers to u, at most `MAX_FRACTION_BITS`.
+    primitive! {fn set_q_bits(&mut self) {
+        let u = self.s_stack().pop();
+        if u < 0 || u > MAX_FRACTION_BITS as isize {
+            self.abort_with(INVALID_NUMERIC_ARGUMENT);
+        } else {
+            self.set_fraction_bits(u as u32);
+        }
+    }}
+
+    // S>Q ( n -- q )
+    primitive! {fn s_to_q(&mut self) {
+        let n = self.s_stack().pop();
+        let bits = self.fraction_bits();
+        self.push_q_result(q_from_int(n, bits));
+    }}
+
+    // Q>S ( q -- n )
+    // Round q to the nearest integer.
+    primitive! {fn q_to_s(&mut self) {
+        let q = self.s_stack().pop();
+        let bits = self.fraction_bits();
+        self.s_stack().push(q_to_int(q, bits));
+    }}
+
+    // F>Q ( -- q ) ( F: r -- )
+    primitive! {fn f_to_q(&mut self) {
+        let r = self.f_stack().pop();
+        let bits = self.fraction_bits();
+        self.push_q_result(q_from_f64(r, bits));
+    }}
+
+    // Q>F ( q -- ) ( F: -- r )
+    primitive! {fn q_to_f(&mut self) {
+        let q = self.s_stack().pop();
+        let bits = self.fraction_bits();
+        self.f_stack().push(q_to_f64(q, bits));
+    }}
+
+    // UNIT>Q ( "<spaces>name" -- q )
+    // Scale factor of unit word `name` to its SI unit, so that
+    // `unit>q mm q*` converts millimeters to meters. The factor is rounded
+    // first, Q-UNIT is more accurate.
+    primitive! {fn unit_to_q(&mut self) {
+        if let Some(unit) = self.parse_unit() {
+            let bits = self.fraction_bits();
+            self.push_q_result(q_from_f64(unit.factor, bits));
+        }
+    }}
+
+    // Q-UNIT ( q1 "<spaces>name" -- q2 )
+    // Convert q1 in unit word `name` to q2 in its SI unit, as in
+    // `10 s>q q-unit mm`. q1 is scaled by the factor of the unit in one
+    // rounded step, the offset of a unit such as DEGC is added rounded.
+    primitive! {fn q_unit(&mut self) {
+        let a = self.s_stack().pop();
+        if let Some(unit) = self.parse_unit() {
+            let bits = self.fraction_bits();
+            let offset = if unit.offset == 0.0 { Some(0) } else { q_from_f64(unit.offset, bits) };
+            let result = match (q_scale(a, unit.factor), offset) {
+                (Some(x), Some(y)) => x.checked_add(y),
+                _ => None,
+            };
+            self.push_q_result(result);
+        }
+    }}
+
+    // Q>UNIT ( q1 "<spaces>name" -- q2 )
+    // Convert q1 in the SI unit of unit word `name` to q2 in the unit, the
+    // reverse of Q-UNIT, as in `q>unit mm`. The rounded offset is
+    // subtracted first, then q1 is divided by the factor in one rounded
+    // step.
+    primitive! {fn q_to_unit(&mut self) {
+        let a = self.s_stack().pop();
+        if let Some(unit) = self.parse_unit() {
+            let bits = self.fraction_bits();
+            let offset = if unit.offset == 0.0 { Some(0) } else { q_from_f64(unit.offset, bits) };
+            let result = offset
+                .and_then(|y| a.checked_sub(y))
+                .and_then(|x| q_unscale(x, unit.factor));
+            self.push_q_result(result);
+        }
+    }}
+
+    // Q* ( q1 q2 -- q3 )
+    primitive! {fn q_star(&mut self) {
+        let (a, b) = self.s_stack().pop2();
+        let bits = self.fraction_bits();
+        self.push_q_result(q_mul(a, b, bits));
+    }}
+
+    // Q/ ( q1 q2 -- q3 )
+    primitive! {fn q_slash(&mut self) {
+        let (a, b) = self.s_stack().pop2();
+        if b == 0 {
+            self.abort_with(DIVISION_BY_ZERO);
+        } else {
+            let bits = self.fraction_bits();
+            self.push_q_result(q_div(a, b, bits));
+        }
+    }}
+
+    // QSQRT ( q1 -- q2 )
+    primitive! {fn qsqrt(&mut self) {
+        let a = self.s_stack().pop();
+        let bits = self.fraction_bits();
+        match q_sqrt(a, bits) {
+            Some(q) => self.s_stack().push(q),
+            None => self.abort_with(INVALID_NUMERIC_ARGUMENT),
+        }
+    }}
+
+    // QSIN ( q1 -- q2 )
+    // q2 is the sine of angle q1 in radians.
+    primitive! {fn qsin(&mut self) {
+        let a = self.s_stack().pop();
+        let bits = self.fraction_bits();
+        self.s_stack().push(q_sin_cos(a, bits).0);
+    }}
+
+    // QCOS ( q1 -- q2 )
+    primitive! {fn qcos(&mut self) {
+        let a = self.s_stack().pop();
+        let bits = self.fraction_bits();
+        self.s_stack().push(q_sin_cos(a, bits).1);
+    }}
+
+    // QSINCOS ( q1 -- q2 q3 )
+    // q2 is the sine and q3 the cosine of angle q1 in radians.
+    primitive! {fn qsincos(&mut self) {
+        let a = self.s_stack().pop();
+        let bits = self.fraction_bits();
+        let (s, c) = q_sin_cos(a, bits);
+        self.s_stack().push2(s, c);
+    }}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Core;
+    use exception::{
+        DIVISION_BY_ZERO, INVALID_NUMERIC_ARGUMENT, RESULT_OUT_OF_RANGE, UNDEFINED_WORD,
+    };
+    use vm::VMBuilder;
+
+    #[test]
+    fn test_rounding() {
+        // 0.5 * 0.25 in Q2 is 0.125, a tie rounded away from zero.
+        assert_eq!(q_mul(2, 1, 2), Some(1));
+        assert_eq!(q_mul(-2, 1, 2), Some(-1));
+        assert_eq!(q_mul(3 << 16, 1 << 15, 16), Some(3 << 15));
+        assert_eq!(q_div(1 << 16, 3 << 16, 16), Some(21845));
+        assert_eq!(q_div(2 << 16, 3 << 16, 16), Some(43691));
+        assert_eq!(q_div(-2 << 16, 3 << 16, 16), Some(-43691));
+        assert_eq!(q_div(2 << 16, -3 << 16, 16), Some(-43691));
+        assert_eq!(q_div(1, 0, 16), None);
+        assert_eq!(q_to_int(3 << 15, 16), 2);
+        assert_eq!(q_to_int(-3 << 15, 16), -2);
+        assert_eq!(q_to_int((5 << 14) - 1, 16), 1);
+        assert_eq!(q_from_f64(1.5, 16), Some(98304));
+        assert_eq!(q_from_f64(-1.0 / 65536.0 / 2.0, 16), Some(-1));
+        assert_eq!(q_from_f64(1e30, 16), None);
+        assert_eq!(q_to_f64(-98304, 16), -1.5);
+        assert_eq!(q_sqrt(2 << 16, 16), Some(92682));
+        assert_eq!(q_sqrt(1 << 16, 16), Some(1 << 16));
+        assert_eq!(q_sqrt(-1, 16), None);
+        assert_eq!(q_from_int(1 << 20, MAX_FRACTION_BITS), None);
+        assert_eq!(q_scale(3, 0.5), Some(2));
+        assert_eq!(q_scale(-3, 0.5), Some(-2));
+        assert_eq!(q_scale(5, -4.0), Some(-20));
+        assert_eq!(q_scale(1, 1e-300), Some(0));
+        assert_eq!(q_scale(1 << 20, 1e300), None);
+        assert_eq!(q_scale(1, f64::NAN), None);
+        assert_eq!(q_unscale(3, 2.0), Some(2));
+        assert_eq!(q_unscale(-3, 2.0), Some(-2));
+        assert_eq!(q_unscale(5, -0.25), Some(-20));
+        assert_eq!(q_unscale(66, 0.001), Some(66000));
+        assert_eq!(q_unscale(1, 1e300), Some(0));
+        assert_eq!(q_unscale(1 << 20, 1e-300), None);
+        assert_eq!(q_unscale(1, 0.0), None);
+        assert_eq!(q_unscale(1, f64::INFINITY), None);
+    }
+
+    #[test]
+    fn test_sin_cos() {
+        for &bits in &[0, 8, 16, 24, 30] {
+            let one = (1 << bits) as f64;
+            for i in -200..200 {
+                let r = i as f64 * 0.05;
+                let a = q_from_f64(r, bits).unwrap();
+                let (s, c) = q_sin_cos(a, bits);
+                let angle = q_to_f64(a, bits);
+                assert!(
+                    (s as f64 - angle.sin() * one).abs() <= 1.0,
+                    "sin {} {}",
+                    r,
+                    bits
+                );
+                assert!(
+                    (c as f64 - angle.cos() * one).abs() <= 1.0,
+                    "cos {} {}",
+                    r,
+                    bits
+                );
+            }
+        }
+        assert_eq!(q_sin_cos(0, 16), (0, 1 << 16));
+        let bits = MAX_FRACTION_BITS;
+        let one = (1u64 << bits) as f64;
+        let (s, c) = q_sin_cos(q_from_f64(1.0, bits).unwrap(), bits);
+        assert!((s as f64 / one - 1f64.sin()).abs() < 1e-15);
+        assert!((c as f64 / one - 1f64.cos()).abs() < 1e-15);
+    }
+
+    #[test]
+    fn test_q_unit() {
+        let vm = &mut VMBuilder::new().build();
+        // 1000 mm is about 1.007 meter with the factor 66/65536 of UNIT>Q.
+        vm.set_source("1000 s>q q-unit mm  1000 s>q unit>q mm q*  25 s>q q-unit degc");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [65536, 66000, 19539558]);
+        for n in -2000..2000 {
+            vm.s_stack().reset();
+            vm.s_stack().push(n * 7);
+            vm.set_source("q-unit mm");
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), None);
+            let exact = (n * 7) as f64 * 0.001;
+            assert!((vm.s_stack().pop() as f64 - exact).abs() <= 0.5, "{}", n);
+        }
+        vm.s_stack().reset();
+        vm.set_source("65536 q>unit mm  19539558 q>unit degc");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [65536000, 25 << 16]);
+        for n in -2000..2000 {
+            vm.s_stack().reset();
+            vm.s_stack().push(n * 7);
+            vm.set_source("q>unit inch");
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), None);
+            let exact = (n * 7) as f64 / 0.0254;
+            assert!((vm.s_stack().pop() as f64 - exact).abs() <= 0.5, "{}", n);
+        }
+        vm.set_source("1 q-unit nosuchunit");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(UNDEFINED_WORD));
+        vm.set_error(None);
+        vm.set_source("1000000000 s>q q>unit usec");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(RESULT_OUT_OF_RANGE));
+    }
+
+    #[test]
+    fn test_fixed_words() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source("q-bits  1 s>q 3 s>q q/ 3 s>q q* q>s  1.5e f>q q>f  unit>q mm");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [16, 1, 66]);
+        assert_eq!(vm.f_stack().as_slice(), [1.5]);
+        vm.set_source("8 set-q-bits q-bits  pi f>q qsincos");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [16, 1, 66, 8, 0, -256]);
+
+        for &(source, error) in &[
+            ("1 0 q/", DIVISION_BY_ZERO),
+            ("-1 qsqrt", INVALID_NUMERIC_ARGUMENT),
+            ("-1 set-q-bits", INVALID_NUMERIC_ARGUMENT),
+            ("1e30 f>q", RESULT_OUT_OF_RANGE),
+            ("-1 1 rshift s>q", RESULT_OUT_OF_RANGE),
+        ] {
+            vm.set_error(None);
+            vm.s_stack().reset();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), Some(error), "{}", source);
+        }
+    }
+}
@@ -1,10 +1,116 @@
 use core::{Core, WordType};
-use exception::INVALID_MEMORY_ADDRESS;
+use exception::{
+    ARGUMENT_TYPE_MISMATCH, FLOATING_POINT_DIVIDED_BY_ZERO, FLOATING_POINT_INVALID_ARGUMENT,
+    FLOATING_POINT_RESULT_OUT_OF_RANGE, INVALID_MEMORY_ADDRESS, WRITE_TO_A_READ_ONLY_LOCATION,
+};
 use memory::{DataSpace, Memory};
 use std::f64::consts::PI;
 use std::mem;
+use units::{unitless, Dimension, DimensionTag, Tag};
 use {FALSE, TRUE};
 
+/// Significant digits displayed by F., FE. and FS. after start-up.
+pub const DEFAULT_PRECISION: isize = 7;
+
+/// Largest PRECISION, the significant digits of an f64.
+pub const MAX_PRECISION: isize = 17;
+
+/// Float status flag of an invalid operation, one with a NaN result.
+pub const FLOAT_INVALID: isize = 1;
+
+/// Float status flag of a division by zero, an infinite result of finite
+/// arguments at a pole, such as `1e 0e f/` or `0e fln`.
+pub const FLOAT_DIVIDE_BY_ZERO: isize = 2;
+
+/// Float status flag of an overflow, any other infinite result.
+pub const FLOAT_OVERFLOW: isize = 4;
+
+/// Float status flag of result `r`. `pole` is true if the arguments are at
+/// a pole of the operation.
+pub fn float_status_of(r: f64, pole: bool) -> isize {
+    if r.is_nan() {
+        FLOAT_INVALID
+    } else if r.is_infinite() {
+        if pole {
+            FLOAT_DIVIDE_BY_ZERO
+        } else {
+            FLOAT_OVERFLOW
+        }
+    } else {
+        0
+    }
+}
+
+/// Decimal digits of `|r|` rounded to `u` significant digits, and exponent
+/// `n` such that `|r|` is about `0.ddd` times ten to the power of `n`. `r`
+/// should be finite.
+pub fn significant_digits(r: f64, u: usize) -> (String, isize) {
+    let s = format!("{:.*e}", u.max(1) - 1, r.abs());
+    let e = s.find('e').expect("exponent");
+    let exponent: isize = s[e + 1..].parse().expect("exponent");
+    let mut digits: String = s[..e].chars().filter(|&c| c != '.').collect();
+    digits.truncate(u);
+    (digits, exponent + 1)
+}
+
+/// Convert string `s` to a float as >FLOAT does.
+///
+/// `s` is a significand, a sign and digits with an optional decimal point,
+/// optionally followed by an exponent, one of `E`, `e`, `D` or `d`, an
+/// optional sign and digits. A string of blanks is zero.
+pub fn to_float(s: &str) -> Option<f64> {
+    let s = s.trim_end_matches(' ');
+    if s.is_empty() {
+        return Some(0.0);
+    }
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    if bytes[i] == b'+' || bytes[i] == b'-' {
+        i += 1;
+    }
+    let mut digits = 0;
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+        i += 1;
+        digits += 1;
+    }
+    if i < bytes.len() && bytes[i] == b'.' {
+        i += 1;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+            digits += 1;
+        }
+    }
+    if digits == 0 {
+        return None;
+    }
+    let mut text = String::from(&s[..i]);
+    if i < bytes.len() {
+        match bytes[i] {
+            b'E' | b'e' | b'D' | b'd' => i += 1,
+            b'+' | b'-' => {}
+            _ => return None,
+        }
+        text.push('e');
+        if i < bytes.len() && (bytes[i] == b'+' || bytes[i] == b'-') {
+            text.push(bytes[i] as char);
+            i += 1;
+        }
+        let start = i;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        if i < bytes.len() {
+            return None;
+        }
+        if start == i {
+            text.push('0');
+        } else {
+            text.push_str(&s[start..]);
+        }
+    }
+    text.parse().ok()
+}
+
 pub trait Float: Core {
     fn add_float(&mut self) {
         self.add_primitive("fconstant", Float::fconstant);
@@ -55,6 +161,126 @@ pub trait Float: Core {
         self.add_primitive("fround", Float::fround);
         self.add_primitive("fceil", Float::fceil);
         self.add_primitive("fnegate", Float::fnegate);
+        self.add_primitive("fdepth", Float::fdepth);
+        self.add_primitive("ftrunc", Float::ftrunc);
+        self.add_primitive("fsinh", Float::fsinh);
+        self.add_primitive("fcosh", Float::fcosh);
+        self.add_primitive("ftanh", Float::ftanh);
+        self.add_primitive("fasinh", Float::fasinh);
+        self.add_primitive("facosh", Float::facosh);
+        self.add_primitive("fatanh", Float::fatanh);
+        self.add_primitive(">float", Float::to_float);
+        self.add_primitive("represent", Float::represent);
+        self.add_primitive("precision", Float::precision);
+        self.add_primitive("set-precision", Float::set_precision);
+        self.add_primitive("f.", Float::fdot);
+        self.add_primitive("fe.", Float::fedot);
+        self.add_primitive("fs.", Float::fsdot);
+        self.add_primitive("sf@", Float::sffetch);
+        self.add_primitive("sf!", Float::sfstore);
+        self.add_primitive("sfloat+", Float::sfloat_plus);
+        self.add_primitive("sfloats", Float::sfloats);
+        self.add_primitive("sfaligned", Float::sfaligned);
+        self.add_primitive("sfalign", Float::sfalign);
+        // Floats are f64, so double floats are floats.
+        self.add_primitive("df@", Float::ffetch);
+        self.add_primitive("df!", Float::fstore);
+        self.add_primitive("dfloat+", Float::float_plus);
+        self.add_primitive("dfloats", Float::floats);
+        self.add_primitive("dfaligned", Float::faligned);
+        self.add_primitive("dfalign", Float::falign);
+        self.add_primitive("float-status", Float::p_float_status);
+        self.add_primitive("clear-float-status", Float::clear_float_status);
+        self.add_primitive("strict-float", Float::p_strict_float);
+        self.add_primitive("set-strict-float", Float::p_set_strict_float);
+    }
+
+    /// Sticky float status, the `FLOAT_INVALID`, `FLOAT_DIVIDE_BY_ZERO` and
+    /// `FLOAT_OVERFLOW` flags raised since it was last cleared.
+    fn float_status(&mut self) -> isize {
+        let addr = self.data_space().system_variables().float_status_addr();
+        unsafe { self.data_space().get_isize(addr) }
+    }
+
+    /// Is strict float mode on? In strict mode, a float primitive raising a
+    /// float status flag aborts with `FLOATING_POINT_INVALID_ARGUMENT`,
+    /// `FLOATING_POINT_DIVIDED_BY_ZERO` or
+    /// `FLOATING_POINT_RESULT_OUT_OF_RANGE` instead of returning a NaN or
+    /// an infinity.
+    fn is_strict_float(&mut self) -> bool {
+        let addr = self.data_space().system_variables().strict_float_addr();
+        unsafe { self.data_space().get_isize(addr) != 0 }
+    }
+
+    /// Turn strict float mode on or off.
+    fn set_strict_float(&mut self, on: bool) {
+        let addr = self.data_space().system_variables().strict_float_addr();
+        unsafe {
+            self.data_space()
+                .put_isize(if on { TRUE } else { FALSE }, addr)
+        };
+    }
+
+    /// Raise float status flag `status` if it is not zero. Returns true if
+    /// aborted because of strict float mode.
+    fn raise_float_status(&mut self, status: isize) -> bool {
+        if status == 0 {
+            return false;
+        }
+        let addr = self.data_space().system_variables().float_status_addr();
+        unsafe {
+            let old = self.data_space().get_isize(addr);
+            self.data_space().put_isize(old | status, addr);
+        }
+        if self.is_strict_float() {
+            self.abort_with(match status {
+                FLOAT_DIVIDE_BY_ZERO => FLOATING_POINT_DIVIDED_BY_ZERO,
+                FLOAT_OVERFLOW => FLOATING_POINT_RESULT_OUT_OF_RANGE,
+                _ => FLOATING_POINT_INVALID_ARGUMENT,
+            });
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Push `r`, the result of a float operation, raising its float status,
+    /// see `float_status_of`.
+    fn push_float_result(&mut self, r: f64, pole: bool) {
+        if !self.raise_float_status(float_status_of(r, pole)) {
+            self.f_stack().push(r);
+        }
+    }
+
+    /// Push `r` of dimension `tag` as `push_float_result` does. `tag` is
+    /// `None` if the dimensions of the arguments mismatch, see
+    /// `units::DimensionTag`.
+    fn push_tagged_result(&mut self, r: f64, pole: bool, tag: Option<Tag>) {
+        match tag {
+            Some(tag) => {
+                if !self.raise_float_status(float_status_of(r, pole)) {
+                    self.f_stack().push(r);
+                    self.f_stack().set_dim(0, tag);
+                }
+            }
+            None => self.abort_with(ARGUMENT_TYPE_MISMATCH),
+        }
+    }
+
+    /// Check that the floats at `depth` and `depth + 1` are of the same
+    /// dimension. Aborts with `ARGUMENT_TYPE_MISMATCH` if not.
+    fn check_same_dims(&mut self, depth: u8) -> bool {
+        let tag = Tag::sum(self.f_stack().dim(depth + 1), self.f_stack().dim(depth));
+        if tag.is_none() {
+            self.abort_with(ARGUMENT_TYPE_MISMATCH);
+        }
+        tag.is_some()
+    }
+
+    /// Push dimensionless result `r` of an argument tagged `tag`, see
+    /// `units::unitless`.
+    fn push_unitless_result(&mut self, r: f64, pole: bool, tag: Tag, d: Dimension) {
+        self.push_tagged_result(r, pole, unitless(tag, d));
     }
 
     // Defining words
@@ -68,9 +294,16 @@ pub trait Float: Core {
 
     primitive! {fn fconstant(&mut self) {
         let v = self.f_stack().pop();
-        self.define(WordType::Fconst, Float::p_fconst, Core::compile_fconst);
-        self.data_space().align_f64();
-        self.data_space().compile_f64(v);
+        self.define_with_data(
+            WordType::Fconst,
+            Float::p_fconst,
+            Core::compile_fconst,
+            2 * mem::size_of::<f64>(),
+        );
+        if self.last_error().is_none() {
+            self.data_space().align_f64();
+            self.data_space().compile_f64(v);
+        }
     }}
 
     /// Run-time: ( a-addr1 -- a-addr2 )
@@ -107,6 +340,7 @@ pub trait Float: Core {
 
     primitive! {fn pi(&mut self) {
         self.f_stack().push(PI);
+        self.f_stack().set_dim(0, Tag::known(Dimension::NONE));
     }}
 
     // Floating point primitives
@@ -133,121 +367,164 @@ pub trait Float: Core {
         if self.data_space().start() <= t &&
             t < self.data_space().limit()
         {
-            unsafe{ self.data_space().put_f64(n, t) };
+            if self.data_space().is_read_only(t, mem::size_of::<f64>()) {
+                self.abort_with(WRITE_TO_A_READ_ONLY_LOCATION);
+            } else {
+                unsafe{ self.data_space().put_f64(n, t) };
+            }
         } else {
             self.abort_with(INVALID_MEMORY_ADDRESS);
         }
     }}
 
     primitive! {fn fabs(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
         self.f_stack().push(t.abs());
+        self.f_stack().set_dim(0, tag);
     }}
 
     primitive! {fn fsin(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
-        self.f_stack().push(t.sin());
+        self.push_unitless_result(t.sin(), false, tag, Dimension::ANGLE);
     }}
 
     primitive! {fn fcos(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
-        self.f_stack().push(t.cos());
+        self.push_unitless_result(t.cos(), false, tag, Dimension::ANGLE);
     }}
 
     primitive! {fn ftan(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
-        self.f_stack().push(t.tan());
+        self.push_unitless_result(t.tan(), false, tag, Dimension::ANGLE);
     }}
 
     primitive! {fn fsincos(&mut self) {
+        let tag = match unitless(self.f_stack().dim(0), Dimension::ANGLE) {
+            Some(tag) => tag,
+            None => {
+                self.abort_with(ARGUMENT_TYPE_MISMATCH);
+                return;
+            }
+        };
         let t = self.f_stack().pop();
         let (s, c) = t.sin_cos();
-        self.f_stack().push2(s, c);
+        if !self.raise_float_status(float_status_of(s, false)) {
+            self.f_stack().push2(s, c);
+            self.f_stack().set_dim(1, tag);
+            self.f_stack().set_dim(0, tag);
+        }
     }}
 
     primitive! {fn fasin(&mut self) {
+        let tag = Tag::check(self.f_stack().dim(0), Dimension::NONE)
+            .map(|_| Tag::known(Dimension::ANGLE));
         let t = self.f_stack().pop();
-        self.f_stack().push(t.asin());
+        self.push_tagged_result(t.asin(), false, tag);
     }}
 
     primitive! {fn facos(&mut self) {
+        let tag = Tag::check(self.f_stack().dim(0), Dimension::NONE)
+            .map(|_| Tag::known(Dimension::ANGLE));
         let t = self.f_stack().pop();
-        self.f_stack().push(t.acos());
+        self.push_tagged_result(t.acos(), false, tag);
     }}
 
     primitive! {fn fatan(&mut self) {
+        let tag = Tag::check(self.f_stack().dim(0), Dimension::NONE)
+            .map(|_| Tag::known(Dimension::ANGLE));
         let t = self.f_stack().pop();
-        self.f_stack().push(t.atan());
+        self.push_tagged_result(t.atan(), false, tag);
     }}
 
     primitive! {fn fatan2(&mut self) {
+        let tag = Tag::sum(self.f_stack().dim(1), self.f_stack().dim(0))
+            .map(|_| Tag::known(Dimension::ANGLE));
         let t = self.f_stack().pop();
         let n = self.f_stack().pop();
-        self.f_stack().push(n.atan2(t));
+        self.push_tagged_result(n.atan2(t), false, tag);
     }}
 
     // ( F: r1 -- r2 )
     // r2 is the base-ten logarithm of r1. An ambiguous condition exists if r1 is less than or equal to zero.
     primitive! {fn flog(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
-        self.f_stack().push(t.log10());
+        self.push_unitless_result(t.log10(), t == 0.0, tag, Dimension::NONE);
     }}
 
     // ( F: r1 -- r2 )
     // r2 is the natural logarithm of r1. An ambiguous condition exists if r1 is less than or equal to zero.
     primitive! {fn fln(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
-        self.f_stack().push(t.ln());
+        self.push_unitless_result(t.ln(), t == 0.0, tag, Dimension::NONE);
     }}
 
     // ( F: r1 -- r2 )
     // r2 is the natural logarithm of the quantity r1 plus one. An ambiguous condition exists if r1 is less than or equal to negative one.
     primitive! {fn flnp1(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
-        self.f_stack().push(t.ln_1p());
+        self.push_unitless_result(t.ln_1p(), t == -1.0, tag, Dimension::NONE);
     }}
 
     // ( F: r1 -- r2 )
     // Raise ten to the power r1, giving r2.
     primitive! {fn falog(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
-        self.f_stack().push((10.0f64).powf(t));
+        self.push_unitless_result((10.0f64).powf(t), false, tag, Dimension::NONE);
     }}
 
     // FEXP ( F: r1 -- r2 )
     // Raise Euler's number e to the power r1, giving r2.
     primitive! {fn fexp(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
-        self.f_stack().push(t.exp());
+        self.push_unitless_result(t.exp(), false, tag, Dimension::NONE);
     }}
 
     // FEXPM1 ( F: r1 -- r2 )
     // Raise Euler's number e to the power r1 and subtract one, giving r2.
     primitive! {fn fexpm1(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
-        self.f_stack().push(t.exp_m1());
+        self.push_unitless_result(t.exp_m1(), false, tag, Dimension::NONE);
     }}
 
     primitive! {fn fsqrt(&mut self) {
+        let tag = Tag::root(self.f_stack().dim(0));
         let t = self.f_stack().pop();
-        self.f_stack().push(t.sqrt());
+        self.push_tagged_result(t.sqrt(), false, tag);
     }}
 
     primitive! {fn fswap(&mut self) {
+        let (n_tag, t_tag) = (self.f_stack().dim(1), self.f_stack().dim(0));
         let t = self.f_stack().pop();
         let n = self.f_stack().pop();
         self.f_stack().push2(t, n);
+        self.f_stack().set_dim(1, t_tag);
+        self.f_stack().set_dim(0, n_tag);
     }}
 
     primitive! {fn fnip(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
         let _ = self.f_stack().pop();
         self.f_stack().push(t);
+        self.f_stack().set_dim(0, tag);
     }}
 
     primitive! {fn fdup(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
         self.f_stack().push2(t, t);
+        self.f_stack().set_dim(1, tag);
+        self.f_stack().set_dim(0, tag);
     }}
 
     primitive! {fn fdrop(&mut self) {
@@ -255,16 +532,26 @@ pub trait Float: Core {
     }}
 
     primitive! {fn frot(&mut self) {
+        let tag1 = self.f_stack().dim(2);
+        let tag2 = self.f_stack().dim(1);
+        let tag3 = self.f_stack().dim(0);
         let x3 = self.f_stack().pop();
         let x2 = self.f_stack().pop();
         let x1 = self.f_stack().pop();
         self.f_stack().push3(x2, x3, x1);
+        self.f_stack().set_dim(2, tag2);
+        self.f_stack().set_dim(1, tag3);
+        self.f_stack().set_dim(0, tag1);
     }}
 
     primitive! {fn fover(&mut self) {
+        let (n_tag, t_tag) = (self.f_stack().dim(1), self.f_stack().dim(0));
         let t = self.f_stack().pop();
         let n = self.f_stack().pop();
         self.f_stack().push3(n, t, n);
+        self.f_stack().set_dim(2, n_tag);
+        self.f_stack().set_dim(1, t_tag);
+        self.f_stack().set_dim(0, n_tag);
     }}
 
     /// Place a copy of the nth floating point stack entry on top of the floating point stack. `fpick ( n -- ) ( F: ... -- x )`
@@ -274,50 +561,73 @@ pub trait Float: Core {
         let t = self.s_stack().pop() as u8;
         let len = self.f_stack().len;
         let x = self.f_stack()[len.wrapping_sub(t.wrapping_add(1))];
+        let tag = self.f_stack().dim(t);
         self.f_stack().push(x);
+        self.f_stack().set_dim(0, tag);
     }}
 
     primitive! {fn s_to_f(&mut self) {
         let t = self.s_stack().pop();
         self.f_stack().push(t as f64);
+        self.f_stack().set_dim(0, Tag::known(Dimension::NONE));
     }}
 
     primitive! {fn f_to_s(&mut self) {
         let t = self.f_stack().pop();
-        self.s_stack().push(t as isize);
+        // 2 to the power of the bits of isize minus one.
+        let limit = (1usize << (mem::size_of::<isize>() * 8 - 1)) as f64;
+        let status = if t.is_nan() {
+            FLOAT_INVALID
+        } else if t.trunc() < -limit || t.trunc() >= limit {
+            FLOAT_OVERFLOW
+        } else {
+            0
+        };
+        if !self.raise_float_status(status) {
+            self.s_stack().push(t as isize);
+        }
     }}
 
     primitive! {fn fplus(&mut self) {
+        let (n_tag, t_tag) = (self.f_stack().dim(1), self.f_stack().dim(0));
         let t = self.f_stack().pop();
         let n = self.f_stack().pop();
-        self.f_stack().push(n + t);
+        self.push_tagged_result(n + t, false, Tag::sum(n_tag, t_tag));
     }}
 
     primitive! {fn fminus(&mut self) {
+        let (n_tag, t_tag) = (self.f_stack().dim(1), self.f_stack().dim(0));
         let t = self.f_stack().pop();
         let n = self.f_stack().pop();
-        self.f_stack().push(n - t);
+        self.push_tagged_result(n - t, false, Tag::sum(n_tag, t_tag));
     }}
 
     primitive! {fn fstar(&mut self) {
+        let (n_tag, t_tag) = (self.f_stack().dim(1), self.f_stack().dim(0));
         let t = self.f_stack().pop();
         let n = self.f_stack().pop();
-        self.f_stack().push(n * t);
+        self.push_tagged_result(n * t, false, Some(Tag::product(n_tag, t_tag)));
     }}
 
     primitive! {fn fslash(&mut self) {
+        let (n_tag, t_tag) = (self.f_stack().dim(1), self.f_stack().dim(0));
         let t = self.f_stack().pop();
         let n = self.f_stack().pop();
-        self.f_stack().push(n / t);
+        self.push_tagged_result(n / t, t == 0.0, Some(Tag::quotient(n_tag, t_tag)));
     }}
 
     primitive! {fn fpowf(&mut self) {
+        let tag = Tag::sum(self.f_stack().dim(1), self.f_stack().dim(0))
+            .and_then(|tag| Tag::check(tag, Dimension::NONE));
         let t = self.f_stack().pop();
         let n = self.f_stack().pop();
-        self.f_stack().push(n.powf(t));
+        self.push_tagged_result(n.powf(t), n == 0.0 && t < 0.0, tag);
     }}
 
     primitive! {fn fproximate(&mut self) {
+        if !self.check_same_dims(1) {
+            return;
+        }
         let (x1, x2, x3) = self.f_stack().pop3();
         if x3 > 0.0 {
             self.s_stack()
@@ -345,48 +655,353 @@ pub trait Float: Core {
     }}
 
     primitive! {fn f_less_than(&mut self) {
+        if !self.check_same_dims(0) {
+            return;
+        }
         let t = self.f_stack().pop();
         let n = self.f_stack().pop();
         self.s_stack().push(if n < t { TRUE } else { FALSE });
     }}
 
     primitive! {fn fmin(&mut self) {
+        let tag = Tag::sum(self.f_stack().dim(1), self.f_stack().dim(0));
         let (n, t) = self.f_stack().pop2();
-        self.f_stack().push(t.min(n));
+        self.push_tagged_result(t.min(n), false, tag);
     }}
 
     primitive! {fn fmax(&mut self) {
+        let tag = Tag::sum(self.f_stack().dim(1), self.f_stack().dim(0));
         let (n, t) = self.f_stack().pop2();
-        self.f_stack().push(t.max(n));
+        self.push_tagged_result(t.max(n), false, tag);
     }}
 
     primitive! {fn fround(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
         self.f_stack().push(t.round());
+        self.f_stack().set_dim(0, tag);
     }}
 
     primitive! {fn floor(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
         self.f_stack().push(t.floor());
+        self.f_stack().set_dim(0, tag);
     }}
 
     primitive! {fn fceil(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
         self.f_stack().push(t.ceil());
+        self.f_stack().set_dim(0, tag);
     }}
 
     primitive! {fn fnegate(&mut self) {
+        let tag = self.f_stack().dim(0);
         let t = self.f_stack().pop();
         self.f_stack().push(-t);
+        self.f_stack().set_dim(0, tag);
+    }}
+
+    // FDEPTH ( -- +n )
+    // +n is the number of values contained on the floating-point stack.
+    primitive! {fn fdepth(&mut self) {
+        let len = self.f_stack().len;
+        self.s_stack().push(len as isize);
+    }}
+
+    // FTRUNC ( F: r1 -- r2 )
+    // Round r1 to an integral value using the "round towards zero" rule,
+    // giving r2.
+    primitive! {fn ftrunc(&mut self) {
+        let tag = self.f_stack().dim(0);
+        let t = self.f_stack().pop();
+        self.f_stack().push(t.trunc());
+        self.f_stack().set_dim(0, tag);
+    }}
+
+    primitive! {fn fsinh(&mut self) {
+        let tag = self.f_stack().dim(0);
+        let t = self.f_stack().pop();
+        self.push_unitless_result(t.sinh(), false, tag, Dimension::NONE);
+    }}
+
+    primitive! {fn fcosh(&mut self) {
+        let tag = self.f_stack().dim(0);
+        let t = self.f_stack().pop();
+        self.push_unitless_result(t.cosh(), false, tag, Dimension::NONE);
+    }}
+
+    primitive! {fn ftanh(&mut self) {
+        let tag = self.f_stack().dim(0);
+        let t = self.f_stack().pop();
+        self.push_unitless_result(t.tanh(), false, tag, Dimension::NONE);
+    }}
+
+    primitive! {fn fasinh(&mut self) {
+        let tag = self.f_stack().dim(0);
+        let t = self.f_stack().pop();
+        self.push_unitless_result(t.asinh(), false, tag, Dimension::NONE);
+    }}
+
+    primitive! {fn facosh(&mut self) {
+        let tag = self.f_stack().dim(0);
+        let t = self.f_stack().pop();
+        self.push_unitless_result(t.acosh(), false, tag, Dimension::NONE);
+    }}
+
+    primitive! {fn fatanh(&mut self) {
+        let tag = self.f_stack().dim(0);
+        let t = self.f_stack().pop();
+        self.push_unitless_result(t.atanh(), t.abs() == 1.0, tag, Dimension::NONE);
     }}
+
+    // Single floats
+
+    // SF@ ( sf-addr -- ) ( F: -- r )
+    // Fetch the f32 stored at sf-addr.
+    primitive! {fn sffetch(&mut self) {
+        let t = DataSpace::aligned_f32(self.s_stack().pop() as usize);
+        if self.data_space().start() <= t &&
+            t < self.data_space().limit()
+        {
+            let value = unsafe{ self.data_space().get_f32(t) };
+            self.f_stack().push(f64::from(value));
+        } else {
+            self.abort_with(INVALID_MEMORY_ADDRESS);
+        }
+    }}
+
+    // SF! ( sf-addr -- ) ( F: r -- )
+    // Store r as f32 at sf-addr, rounding to the nearest f32.
+    primitive! {fn sfstore(&mut self) {
+        let t = DataSpace::aligned_f32(self.s_stack().pop() as usize);
+        let n = self.f_stack().pop();
+        if self.data_space().start() <= t &&
+            t < self.data_space().limit()
+        {
+            if self.data_space().is_read_only(t, mem::size_of::<f32>()) {
+                self.abort_with(WRITE_TO_A_READ_ONLY_LOCATION);
+            } else {
+                unsafe{ self.data_space().put_f32(n as f32, t) };
+            }
+        } else {
+            self.abort_with(INVALID_MEMORY_ADDRESS);
+        }
+    }}
+
+    primitive! {fn sfloat_plus(&mut self) {
+        let v = self.s_stack().pop();
+        self.s_stack().push(v + mem::size_of::<f32>() as isize);
+    }}
+
+    primitive! {fn sfloats(&mut self) {
+        let v = self.s_stack().pop();
+        self.s_stack().push(v * mem::size_of::<f32>() as isize);
+    }}
+
+    primitive! {fn sfaligned(&mut self) {
+        let pos = self.s_stack().pop();
+        let pos = DataSpace::aligned_f32(pos as usize);
+        self.s_stack().push(pos as isize);
+    }}
+
+    primitive! {fn sfalign(&mut self) {
+        self.data_space().align_f32();
+    }}
+
+    // Conversion and display
+
+    // >FLOAT ( c-addr u -- true | false ) ( F: -- r | )
+    // Convert the string c-addr u to a float, see `to_float`.
+    primitive! {fn to_float(&mut self) {
+        let (caddr, u) = self.s_stack().pop2();
+        let (caddr, u) = (caddr as usize, u as usize);
+        if !(self.data_space().start() <= caddr && caddr + u <= self.data_space().limit()) {
+            self.abort_with(INVALID_MEMORY_ADDRESS);
+            return;
+        }
+        let r = to_float(unsafe{ self.data_space().str_from_raw_parts(caddr, u) });
+        match r {
+            Some(r) => {
+                self.f_stack().push(r);
+                self.f_stack().set_dim(0, Tag::known(Dimension::NONE));
+                self.s_stack().push(TRUE);
+            }
+            None => self.s_stack().push(FALSE),
+        }
+    }}
+
+    // REPRESENT ( c-addr u -- n flag1 flag2 ) ( F: r -- )
+    // Store the u most significant digits of r at c-addr, see
+    // `significant_digits`. flag1 is true if r is negative, flag2 is false
+    // if r is infinite or NaN, in which case its name is stored instead.
+    primitive! {fn represent(&mut self) {
+        let r = self.f_stack().pop();
+        let (caddr, u) = self.s_stack().pop2();
+        let (caddr, u) = (caddr as usize, u as usize);
+        if !(self.data_space().start() <= caddr && caddr + u <= self.data_space().limit()) {
+            self.abort_with(INVALID_MEMORY_ADDRESS);
+            return;
+        }
+        if self.data_space().is_read_only(caddr, u) {
+            self.abort_with(WRITE_TO_A_READ_ONLY_LOCATION);
+            return;
+        }
+        let (digits, n, valid) = if r.is_finite() {
+            let (digits, n) = significant_digits(r, u);
+            (digits, n, true)
+        } else {
+            (format!("{:<1$}", format!("{}", r.abs()), u), 0, false)
+        };
+        let buf = unsafe{ self.data_space().buffer_from_raw_parts_mut(caddr, u) };
+        for (b, d) in buf.iter_mut().zip(digits.bytes()) {
+            *b = d;
+        }
+        let negative = r.is_sign_negative() && !r.is_nan();
+        self.s_stack().push3(
+            n,
+            if negative { TRUE } else { FALSE },
+            if valid { TRUE } else { FALSE },
+        );
+    }}
+
+    // PRECISION ( -- u )
+    // Number of significant digits displayed by F., FE. and FS.
+    primitive! {fn precision(&mut self) {
+        let precision = self.float_precision();
+        self.s_stack().push(precision as isize);
+    }}
+
+    // SET-PRECISION ( u -- )
+    // Set PRECISION to u. It is limited to between 1 and `MAX_PRECISION`
+    // when used.
+    primitive! {fn set_precision(&mut self) {
+        let u = self.s_stack().pop();
+        let addr = self.data_space().system_variables().precision_addr();
+        unsafe{ self.data_space().put_isize(u, addr) };
+    }}
+
+    // F. ( F: r -- )
+    // Display r in fixed-point notation with PRECISION significant digits,
+    // followed by a space.
+    primitive! {fn fdot(&mut self) {
+        let r = self.f_stack().pop();
+        let precision = self.float_precision();
+        let text = if r.is_finite() {
+            let (_, n) = significant_digits(r, precision);
+            let decimals = precision as isize - n;
+            format!("{:.*}", if decimals > 0 { decimals as usize } else { 0 }, r)
+        } else {
+            format!("{}", r)
+        };
+        self.push_float_text(&text);
+    }}
+
+    // FE. ( F: r -- )
+    // Display r in engineering notation, with an exponent which is a
+    // multiple of three, and PRECISION significant digits, followed by a
+    // space.
+    primitive! {fn fedot(&mut self) {
+        let r = self.f_stack().pop();
+        let precision = self.float_precision();
+        let text = if r.is_finite() {
+            let (mut digits, n) = significant_digits(r, precision);
+            let exponent = n - 1 - (n - 1).rem_euclid(3);
+            let integer_digits = (n - exponent) as usize;
+            while digits.len() < integer_digits {
+                digits.push('0');
+            }
+            format!(
+                "{}{}.{}E{}",
+                if r.is_sign_negative() { "-" } else { "" },
+                &digits[..integer_digits],
+                &digits[integer_digits..],
+                exponent
+            )
+        } else {
+            format!("{}", r)
+        };
+        self.push_float_text(&text);
+    }}
+
+    // FS. ( F: r -- )
+    // Display r in scientific notation with PRECISION significant digits,
+    // followed by a space.
+    primitive! {fn fsdot(&mut self) {
+        let r = self.f_stack().pop();
+        let precision = self.float_precision();
+        let text = if r.is_finite() {
+            format!("{:.*e}", precision - 1, r).replace('e', "E")
+        } else {
+            format!("{}", r)
+        };
+        self.push_float_text(&text);
+    }}
+
+    // Float status
+
+    // FLOAT-STATUS ( -- u )
+    // Sticky float status, see `Float::float_status`.
+    primitive! {fn p_float_status(&mut self) {
+        let status = self.float_status();
+        self.s_stack().push(status);
+    }}
+
+    // CLEAR-FLOAT-STATUS ( -- )
+    primitive! {fn clear_float_status(&mut self) {
+        let addr = self.data_space().system_variables().float_status_addr();
+        unsafe{ self.data_space().put_isize(0, addr) };
+    }}
+
+    // STRICT-FLOAT ( -- flag )
+    // Is strict float mode on? See `Float::is_strict_float`.
+    primitive! {fn p_strict_float(&mut self) {
+        let on = self.is_strict_float();
+        self.s_stack().push(if on { TRUE } else { FALSE });
+    }}
+
+    // SET-STRICT-FLOAT ( flag -- )
+    // Turn strict float mode on if flag is true, off otherwise.
+    primitive! {fn p_set_strict_float(&mut self) {
+        let flag = self.s_stack().pop();
+        self.set_strict_float(flag != 0);
+    }}
+
+    /// Significant digits displayed by F., FE. and FS.
+    fn float_precision(&mut self) -> usize {
+        let addr = self.data_space().system_variables().precision_addr();
+        let precision = unsafe { self.data_space().get_isize(addr) };
+        if precision < 1 {
+            1
+        } else if precision > MAX_PRECISION {
+            MAX_PRECISION as usize
+        } else {
+            precision as usize
+        }
+    }
+
+    /// Put `text` followed by a space into the output buffer.
+    fn push_float_text(&mut self, text: &str) {
+        if let Some(mut buf) = self.output_buffer().take() {
+            buf.push_str(text);
+            buf.push(' ');
+            self.set_output_buffer(buf);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Float;
+    use super::{to_float, Float, FLOAT_DIVIDE_BY_ZERO, FLOAT_INVALID, FLOAT_OVERFLOW};
     use core::Core;
-    use exception::UNDEFINED_WORD;
-    use mock_vm::VM;
+    use exception::{
+        FLOATING_POINT_DIVIDED_BY_ZERO, FLOATING_POINT_INVALID_ARGUMENT,
+        FLOATING_POINT_RESULT_OUT_OF_RANGE, UNDEFINED_WORD,
+    };
+    use memory::Memory;
+    use mock_vm::{NewVM, VM};
+    use vm::VMBuilder;
 
     #[test]
     fn test_ans_forth_float() {
@@ -877,4 +1492,186 @@ mod tests {
         assert_eq!(vm.last_error(), None);
         assert_eq!(vm.f_stack().as_slice(), [1.0, 2.0]);
     }
+
+    #[test]
+    fn test_fdepth_ftrunc_hyperbolic() {
+        let vm = &mut VM::new();
+        vm.set_source("fdepth 1.5e -2.5e ftrunc fswap ftrunc fdepth");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [0, 2]);
+        assert_eq!(vm.f_stack().as_slice(), [-2.0, 1.0]);
+        vm.f_stack().reset();
+        vm.set_source("0.5e fsinh fasinh 0.5e fcosh facosh 0.5e ftanh fatanh");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        for &r in vm.f_stack().as_slice() {
+            assert_ulps_eq!(r, 0.5, max_ulps = 4);
+        }
+    }
+
+    #[test]
+    fn test_single_and_double_floats() {
+        let vm = &mut VM::new();
+        vm.set_source(
+            "0 sffield: x.a  dffield: x.b  constant /x  create x /x allot
+             1.1e x x.a sf!  1.1e x x.b df!  x x.a sf@  x x.b df@
+             /x  3 sfloats  3 dfloats  1 sfaligned  1 dfaligned",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [16, 12, 24, 4, 8]);
+        assert_eq!(vm.f_stack().as_slice(), [f64::from(1.1f32), 1.1]);
+    }
+
+    #[test]
+    fn test_to_float() {
+        assert_eq!(to_float("1E"), Some(1.0));
+        assert_eq!(to_float("-1.5e+2"), Some(-150.0));
+        assert_eq!(to_float("+.5D-1"), Some(0.05));
+        assert_eq!(to_float("25"), Some(25.0));
+        assert_eq!(to_float("2e-"), Some(2.0));
+        assert_eq!(to_float("   "), Some(0.0));
+        assert_eq!(to_float("1.5-3"), Some(0.0015));
+        assert_eq!(to_float("e3"), None);
+        assert_eq!(to_float("."), None);
+        assert_eq!(to_float("1x"), None);
+        assert_eq!(to_float("inf"), None);
+        let vm = &mut VM::new();
+        vm.set_source("s\" 1.25e1\" >float  s\" x\" >float");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [-1, 0]);
+        assert_eq!(vm.f_stack().as_slice(), [12.5]);
+    }
+
+    #[test]
+    fn test_represent() {
+        let vm = &mut VM::new();
+        vm.set_source("-12.346e here 4 represent  here 4");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        let (addr, u) = vm.s_stack().pop2();
+        assert_eq!(vm.s_stack().as_slice(), [2, -1, -1]);
+        assert_eq!(
+            unsafe { vm.data_space().str_from_raw_parts(addr as _, u as _) },
+            "1235"
+        );
+        vm.s_stack().reset();
+        vm.set_source("0.000999e here 2 represent  1e 0e f/ here 5 represent");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [-2, 0, -1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_precision_and_display() {
+        let vm = &mut VM::new();
+        vm.set_source("precision  3 set-precision precision  0 set-precision precision");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [7, 3, 1]);
+        vm.s_stack().reset();
+        vm.output_buffer().as_mut().unwrap().clear();
+        vm.set_source(
+            "4 set-precision  3.14159e f.  1234.56e f.  0.00123e f.  -2e f.
+             12346e fe.  0.5e fe.  -1e fe.  1234.56e fs.  -0.00123e fs.",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(
+            vm.output_buffer().clone().unwrap(),
+            "3.142 1235 0.001230 -2.000 12.35E3 500.0E-3 -1.000E0 1.235E3 -1.230E-3 "
+        );
+    }
+
+    #[test]
+    fn test_fvalue_and_value() {
+        let vm = &mut VM::new();
+        vm.set_source(
+            "1.5e fvalue r  7 value n  r n
+             2.5e to r  8 to n  r n
+             : set ( F: r -- ) ( n -- )   to n  to r ;  -1e 9 set  r n",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [7, 8, 9]);
+        assert_eq!(vm.f_stack().as_slice(), [1.5, 2.5, -1.0]);
+    }
+
+    #[test]
+    fn test_float_status() {
+        let vm = &mut VM::new();
+        vm.set_source("float-status strict-float  -1e fsqrt  1e 0e f/  1e308 10e f*  float-status");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [0, 0, 7]);
+        assert_eq!(vm.f_stack().len(), 3);
+        assert!(vm.f_stack().as_slice()[0].is_nan());
+        assert!(vm.f_stack().as_slice()[1..].iter().all(|r| r.is_infinite()));
+        vm.s_stack().reset();
+        vm.set_source("clear-float-status float-status  0e fln fdrop float-status");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [0, 2]);
+    }
+
+    #[test]
+    fn test_strict_float() {
+        let vm = &mut VMBuilder::new().strict_float(true).build();
+        assert!(vm.is_strict_float());
+        for &(source, e, status) in [
+            ("-1e fsqrt", FLOATING_POINT_INVALID_ARGUMENT, FLOAT_INVALID),
+            ("0e 0e f/", FLOATING_POINT_INVALID_ARGUMENT, FLOAT_INVALID),
+            ("2e fasin", FLOATING_POINT_INVALID_ARGUMENT, FLOAT_INVALID),
+            (
+                "1e 0e f/",
+                FLOATING_POINT_DIVIDED_BY_ZERO,
+                FLOAT_DIVIDE_BY_ZERO,
+            ),
+            (
+                "0e fln",
+                FLOATING_POINT_DIVIDED_BY_ZERO,
+                FLOAT_DIVIDE_BY_ZERO,
+            ),
+            (
+                "1e fatanh",
+                FLOATING_POINT_DIVIDED_BY_ZERO,
+                FLOAT_DIVIDE_BY_ZERO,
+            ),
+            (
+                "1e308 10e f*",
+                FLOATING_POINT_RESULT_OUT_OF_RANGE,
+                FLOAT_OVERFLOW,
+            ),
+            (
+                "1000e fexp",
+                FLOATING_POINT_RESULT_OUT_OF_RANGE,
+                FLOAT_OVERFLOW,
+            ),
+            (
+                "1e30 f>s",
+                FLOATING_POINT_RESULT_OUT_OF_RANGE,
+                FLOAT_OVERFLOW,
+            ),
+        ]
+        .iter()
+        {
+            vm.set_source("clear-float-status");
+            vm.evaluate_input();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), Some(e), "{}", source);
+            assert_eq!(vm.float_status(), status, "{}", source);
+            assert_eq!(vm.f_stack().len(), 0);
+        }
+        vm.set_error(None);
+        vm.set_source(
+            "2e fsqrt fdrop  1e15 f>s drop  false set-strict-float  -1e fsqrt strict-float",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [0]);
+        assert!(vm.f_stack().pop().is_nan());
+    }
 }
@@ -0,0 +1,634 @@
+//! G-code front-end
+//!
+//! Translates blocks of RS274/NGC G-code into Forth source calling
+//! user-defined words, so that a G-code program can be loaded with
+//! `INCLUDE` like a Forth file. Input sources whose path ends in `.ngc`,
+//! `.nc` or `.gcode` are translated line by line as they are loaded, see
+//! `HasLoader::load_line`.
+//!
+//! The words of a block are translated into calls in this order:
+//!
+//! | G-code         | Forth word                       |
+//! |----------------|----------------------------------|
+//! | `F`            | `F ( F: feed -- )`               |
+//! | `S`            | `S ( F: speed -- )`              |
+//! | `T`            | `T ( n -- )`                     |
+//! | `M3`, `M8`...  | `M3`, `M8`... `( -- )`           |
+//! | `G17` to `G19` | `G17` to `G19 ( -- )`            |
+//! | `G0`, `G1`     | `G0`, `G1 ( F: x y z -- )`       |
+//! | `G2`, `G3`     | `G2`, `G3 ( F: x y z cx cy cz -- )` |
+//! | `M0`, `M2`...  | `M0`, `M2`... `( -- )`           |
+//!
+//! Stop codes M0, M1, M2, M30 and M60 are called after the motion of the
+//! block, the other M-codes before it.
+//!
+//! Lengths, feeds and speeds are converted by the unit words `MM` or
+//! `INCH`, `MM/MIN` or `INCH/MIN` and `RPM`, so the words get them in SI
+//! units. Units (G20, G21) and distance mode (G90, G91) are resolved by the
+//! translator: motion words get the absolute end point and, for arcs, the
+//! absolute centre, also of an arc given by its radius `R`. The motion mode
+//! is modal, a block with axis words only repeats the last motion. An
+//! invalid block leaves the modal state unchanged.
+//!
+//! Comments in parentheses and after `;` are skipped. Parameters are set
+//! with `#n=value` and read with `#n`, values may be expressions with
+//! `+ - * /` in brackets. As in RS274/NGC, parameters set in a block take
+//! effect after the block.
+//!
+//! ```
+//! use rtforth::gcode::Translator;
+//!
+//! let mut gcode = Translator::new();
+//! assert_eq!(
+//!     gcode.translate("G91 G1 X10 F[2*#1+100] (feed)").unwrap(),
+//!     "100e mm/min f 10e mm 0e mm 0e mm g1"
+//! );
+//! assert_eq!(gcode.translate("Y-5").unwrap(), "10e mm -5e mm 0e mm g1");
+//! ```
+
+use exception::{ForthError, INVALID_GCODE};
+use std::fmt::Write;
+use Result;
+
+/// Number of numbered parameters, `#1` to `#5399`.
+pub const PARAMETERS: usize = 5400;
+
+/// Plane of arcs, selected by G17, G18 or G19.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Plane {
+    Xy,
+    Zx,
+    Yz,
+}
+
+impl Plane {
+    /// Indices of the first and second axis of the plane.
+    fn axes(self) -> (usize, usize) {
+        match self {
+            Plane::Xy => (0, 1),
+            Plane::Zx => (2, 0),
+            Plane::Yz => (1, 2),
+        }
+    }
+}
+
+/// Modal state of a G-code program, which translates its blocks.
+pub struct Translator {
+    motion: Option<usize>,
+    plane: Plane,
+    inch: bool,
+    incremental: bool,
+    position: [f64; 3],
+    parameters: Vec<f64>,
+}
+
+impl Default for Translator {
+    fn default() -> Translator {
+        Translator::new()
+    }
+}
+
+impl Translator {
+    /// Program at the origin, in millimeters, absolute distance mode and
+    /// XY plane, with no motion mode and all parameters 0.
+    pub fn new() -> Translator {
+        Translator {
+            motion: None,
+            plane: Plane::Xy,
+            inch: false,
+            incremental: false,
+            position: [0.0; 3],
+            parameters: vec![0.0; PARAMETERS],
+        }
+    }
+
+    /// Position in program units after the last motion.
+    pub fn position(&self) -> [f64; 3] {
+        self.position
+    }
+
+    pub fn plane(&self) -> Plane {
+        self.plane
+    }
+
+    /// Value of parameter `#n`.
+    pub fn parameter(&self, n: usize) -> f64 {
+        self.parameters[n]
+    }
+
+    /// Translate `block` into Forth source.
+    ///
+    /// Fails with `INVALID_GCODE` on a syntax error, an unsupported word,
+    /// two G-codes of the same modal group or an arc which cannot be
+    /// drawn.
+    pub fn translate(&mut self, block: &str) -> Result<String> {
+        let chars = strip(block)?;
+        let block = Parser {
+            chars: &chars,
+            pos: 0,
+            parameters: &self.parameters,
+        }
+        .block()?;
+        self.execute(&block)
+    }
+
+    fn execute(&mut self, block: &Block) -> Result<String> {
+        let (mut motion, mut plane, mut units, mut distance) = (None, None, None, None);
+        for &g in &block.g {
+            let code = integer(g)?;
+            let group = match code {
+                0..=3 => &mut motion,
+                17..=19 => &mut plane,
+                20 | 21 => &mut units,
+                90 | 91 => &mut distance,
+                _ => return Err(invalid()),
+            };
+            if group.is_some() {
+                return Err(invalid());
+            }
+            *group = Some(code);
+        }
+        // The new modal state, committed only if the block is valid.
+        let mut position = self.position;
+        let inch = units.map_or(self.inch, |units| units == 20);
+        if inch != self.inch {
+            let factor = if inch { 1.0 / 25.4 } else { 25.4 };
+            for p in &mut position {
+                *p *= factor;
+            }
+        }
+        let incremental = distance.map_or(self.incremental, |distance| distance == 91);
+        let length = if inch { "inch" } else { "mm" };
+        let mut forth = String::new();
+        if let Some(feed) = block.word('f') {
+            let _ = write!(forth, "{} {}/min f ", literal(feed), length);
+        }
+        if let Some(speed) = block.word('s') {
+            let _ = write!(forth, "{} rpm s ", literal(speed));
+        }
+        if let Some(tool) = block.word('t') {
+            let _ = write!(forth, "{} t ", integer(tool)?);
+        }
+        // Stop codes take effect after the motion of the block.
+        let mut stops = String::new();
+        for &m in &block.m {
+            let code = integer(m)?;
+            match code {
+                0 | 1 | 2 | 30 | 60 => {
+                    let _ = write!(stops, "m{} ", code);
+                }
+                _ => {
+                    let _ = write!(forth, "m{} ", code);
+                }
+            }
+        }
+        let plane = match plane {
+            Some(code) => {
+                let _ = write!(forth, "g{} ", code);
+                match code {
+                    17 => Plane::Xy,
+                    18 => Plane::Zx,
+                    _ => Plane::Yz,
+                }
+            }
+            None => self.plane,
+        };
+        let mode = motion.or(self.motion);
+        let axes = [block.word('x'), block.word('y'), block.word('z')];
+        let offsets = [block.word('i'), block.word('j'), block.word('k')];
+        let radius = block.word('r');
+        let arc = radius.is_some() || offsets.iter().any(Option::is_some);
+        if motion.is_some() || arc || axes.iter().any(Option::is_some) {
+            let motion = mode.ok_or_else(invalid)?;
+            let mut target = position;
+            for (t, a) in target.iter_mut().zip(axes.iter()) {
+                if let Some(a) = *a {
+                    *t = if incremental { *t + a } else { a };
+                }
+            }
+            for t in &target {
+                let _ = write!(forth, "{} {} ", literal(*t), length);
+            }
+            if motion >= 2 {
+                let center = match radius {
+                    Some(_) if offsets.iter().any(Option::is_some) => return Err(invalid()),
+                    Some(r) => arc_center(plane, &position, &target, r, motion == 2)?,
+                    None if !arc => return Err(invalid()),
+                    None => {
+                        let mut center = position;
+                        for (c, o) in center.iter_mut().zip(offsets.iter()) {
+                            *c += o.unwrap_or(0.0);
+                        }
+                        center
+                    }
+                };
+                for c in &center {
+                    let _ = write!(forth, "{} {} ", literal(*c), length);
+                }
+            } else if arc {
+                return Err(invalid());
+            }
+            let _ = write!(forth, "g{} ", motion);
+            position = target;
+        }
+        forth.push_str(&stops);
+        self.motion = mode;
+        self.plane = plane;
+        self.inch = inch;
+        self.incremental = incremental;
+        self.position = position;
+        for &(n, value) in &block.assignments {
+            self.parameters[n] = value;
+        }
+        let len = forth.trim_end().len();
+        forth.truncate(len);
+        Ok(forth)
+    }
+}
+
+/// Centre of the arc of radius `r` in `plane` from `start` to `target`,
+/// clockwise or not. A negative `r` selects the arc of more than half a
+/// turn.
+fn arc_center(
+    plane: Plane,
+    start: &[f64; 3],
+    target: &[f64; 3],
+    r: f64,
+    clockwise: bool,
+) -> Result<[f64; 3]> {
+    let (a, b) = plane.axes();
+    let (da, db) = (target[a] - start[a], target[b] - start[b]);
+    let chord = da.hypot(db);
+    if chord == 0.0 || r.abs() < chord / 2.0 * (1.0 - 1e-9) {
+        return Err(invalid());
+    }
+    let h = (r * r - chord * chord / 4.0).max(0.0).sqrt();
+    // The centre of a short clockwise arc is right of the chord.
+    let side = if clockwise == (r > 0.0) { h } else { -h };
+    let mut center = *start;
+    center[a] = (start[a] + target[a]) / 2.0 + side * db / chord;
+    center[b] = (start[b] + target[b]) / 2.0 - side * da / chord;
+    Ok(center)
+}
+
+/// Whether the input source at `path` is G-code, judging by its extension.
+pub fn is_gcode_path(path: &str) -> bool {
+    match path.rfind('.') {
+        Some(i) => {
+            let extension = path[i + 1..].to_ascii_lowercase();
+            extension == "ngc" || extension == "nc" || extension == "gcode"
+        }
+        None => false,
+    }
+}
+
+fn invalid() -> ForthError {
+    ForthError::from(INVALID_GCODE)
+}
+
+/// Forth float literal of `value`.
+fn literal(value: f64) -> String {
+    // No "-0e".
+    format!("{}e", if value == 0.0 { 0.0 } else { value })
+}
+
+/// `value` as a code or a tool number, which must be a non-negative
+/// integer.
+fn integer(value: f64) -> Result<usize> {
+    let n = value.round();
+    if n < 0.0 || (value - n).abs() > 1e-4 {
+        Err(invalid())
+    } else {
+        Ok(n as usize)
+    }
+}
+
+/// Characters of `block` without comments, blanks, block delete and
+/// program delimiter, in lower case.
+fn strip(block: &str) -> Result<Vec<char>> {
+    let mut chars = Vec::with_capacity(block.len());
+    let mut input = block.chars();
+    while let Some(c) = input.next() {
+        match c {
+            '(' => {
+                if !input.any(|c| c == ')') {
+                    return Err(invalid());
+                }
+            }
+            ';' | '%' => break,
+            '/' if chars.is_empty() => {}
+            c if c.is_whitespace() => {}
+            c => chars.push(c.to_ascii_lowercase()),
+        }
+    }
+    Ok(chars)
+}
+
+/// Words of a block, with values of parameters read before the block.
+#[derive(Default)]
+struct Block {
+    g: Vec<f64>,
+    m: Vec<f64>,
+    words: [Option<f64>; 26],
+    assignments: Vec<(usize, f64)>,
+}
+
+impl Block {
+    fn word(&self, letter: char) -> Option<f64> {
+        self.words[letter as usize - 'a' as usize]
+    }
+}
+
+struct Parser<'a> {
+    chars: &'a [char],
+    pos: usize,
+    parameters: &'a [f64],
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn block(&mut self) -> Result<Block> {
+        let mut block = Block::default();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '#' => {
+                    let n = self.index()?;
+                    if !self.eat('=') {
+                        return Err(invalid());
+                    }
+                    let value = self.value()?;
+                    block.assignments.push((n, value));
+                }
+                'g' => block.g.push(self.value()?),
+                'm' => block.m.push(self.value()?),
+                'f' | 'i' | 'j' | 'k' | 'n' | 'r' | 's' | 't' | 'x' | 'y' | 'z' => {
+                    let value = self.value()?;
+                    let word = &mut block.words[c as usize - 'a' as usize];
+                    if word.is_some() {
+                        return Err(invalid());
+                    }
+                    *word = Some(value);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(block)
+    }
+
+    /// Number of a parameter.
+    fn index(&mut self) -> Result<usize> {
+        let n = integer(self.value()?)?;
+        if n == 0 || n >= PARAMETERS {
+            Err(invalid())
+        } else {
+            Ok(n)
+        }
+    }
+
+    fn value(&mut self) -> Result<f64> {
+        if self.eat('[') {
+            let value = self.expression()?;
+            if self.eat(']') {
+                Ok(value)
+            } else {
+                Err(invalid())
+            }
+        } else if self.eat('#') {
+            let n = self.index()?;
+            Ok(self.parameters[n])
+        } else if self.eat('-') {
+            Ok(-self.value()?)
+        } else if self.eat('+') {
+            self.value()
+        } else {
+            let start = self.pos;
+            while let Some(c) = self.peek() {
+                if c.is_ascii_digit() || c == '.' {
+                    self.pos += 1;
+                } else {
+                    break;
+                }
+            }
+            let number: String = self.chars[start..self.pos].iter().collect();
+            number.parse().map_err(|_| invalid())
+        }
+    }
+
+    fn expression(&mut self) -> Result<f64> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value += self.term()?;
+            } else if self.eat('-') {
+                value -= self.term()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<f64> {
+        let mut value = self.value()?;
+        loop {
+            if self.eat('*') {
+                value *= self.value()?;
+            } else if self.eat('/') {
+                let divisor = self.value()?;
+                if divisor == 0.0 {
+                    return Err(invalid());
+                }
+                value /= divisor;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Plane, Translator};
+    use core::Core;
+    use exception::INVALID_GCODE;
+    use loader::HasLoader;
+    use std::env;
+    use std::fs;
+    use vm::VMBuilder;
+
+    /// Float literals in `forth`.
+    fn floats(forth: &str) -> Vec<f64> {
+        forth
+            .split_whitespace()
+            .filter(|t| t.ends_with('e'))
+            .map(|t| t[..t.len() - 1].parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_translate_words() {
+        let gcode = &mut Translator::new();
+        assert_eq!(
+            gcode.translate("N10 m3 S1000 T2 F50 ; cut").unwrap(),
+            "50e mm/min f 1000e rpm s 2 t m3"
+        );
+        assert_eq!(gcode.translate("%").unwrap(), "");
+        assert_eq!(gcode.translate(" (only a comment) ").unwrap(), "");
+        assert_eq!(gcode.translate("G18 M8 M9").unwrap(), "m8 m9 g18");
+        assert_eq!(
+            gcode.translate("M2 G1 X10 M8").unwrap(),
+            "m8 10e mm 0e mm 0e mm g1 m2"
+        );
+        assert_eq!(gcode.translate("M30").unwrap(), "m30");
+        assert_eq!(
+            gcode.translate("/G0 X 1.5 Y-2").unwrap(),
+            "1.5e mm -2e mm 0e mm g0"
+        );
+    }
+
+    #[test]
+    fn test_translate_modes() {
+        let gcode = &mut Translator::new();
+        assert_eq!(
+            gcode.translate("G1 X10 Y20").unwrap(),
+            "10e mm 20e mm 0e mm g1"
+        );
+        assert_eq!(gcode.translate("G91 X5").unwrap(), "15e mm 20e mm 0e mm g1");
+        assert_eq!(
+            gcode.translate("G0 Z-1").unwrap(),
+            "15e mm 20e mm -1e mm g0"
+        );
+        assert_eq!(
+            gcode.translate("G90 G20 F10 X1").unwrap(),
+            format!(
+                "10e inch/min f 1e inch {}e inch {}e inch g0",
+                20.0 * (1.0 / 25.4),
+                -1.0 / 25.4
+            )
+        );
+        assert_eq!(gcode.translate("G21").unwrap(), "");
+        let position = gcode.position();
+        assert_ulps_eq!(position[0], 25.4);
+        assert_ulps_eq!(position[1], 20.0);
+        assert_ulps_eq!(position[2], -1.0);
+    }
+
+    #[test]
+    fn test_translate_arcs() {
+        let gcode = &mut Translator::new();
+        assert_eq!(
+            gcode.translate("G2 X2 I1 J0").unwrap(),
+            "2e mm 0e mm 0e mm 1e mm 0e mm 0e mm g2"
+        );
+        assert_eq!(
+            gcode.translate("G18 G3 X0 Z2 K1 I-1").unwrap(),
+            "g18 0e mm 0e mm 2e mm 1e mm 0e mm 1e mm g3"
+        );
+        gcode.translate("G17 G0 X0 Y0 Z0").unwrap();
+        for &(block, cx, cy) in &[
+            ("G2 X1 Y1 R1", 1.0, 0.0),
+            ("G3 X1 Y1 R1", 0.0, 1.0),
+            ("G2 X1 Y1 R-1", 0.0, 1.0),
+            ("G3 X1 Y1 R-1", 1.0, 0.0),
+        ] {
+            gcode.translate("G0 X0 Y0").unwrap();
+            let f = floats(&gcode.translate(block).unwrap());
+            assert_eq!(f.len(), 6);
+            assert_abs_diff_eq!(f[3], cx, epsilon = 1e-12);
+            assert_abs_diff_eq!(f[4], cy, epsilon = 1e-12);
+        }
+        let f = floats(&gcode.translate("G2 X3 Y1 R1").unwrap());
+        assert_abs_diff_eq!(f[3], 2.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(f[4], 1.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_translate_parameters() {
+        let gcode = &mut Translator::new();
+        assert_eq!(gcode.translate("#1=2 #2=[#1+1]").unwrap(), "");
+        assert_eq!(gcode.parameter(1), 2.0);
+        assert_eq!(gcode.parameter(2), 1.0);
+        assert_eq!(
+            gcode.translate("G0 X#1 Y[#1*3-[1+#2]/2] Z-#[#1]").unwrap(),
+            "2e mm 5e mm -1e mm g0"
+        );
+    }
+
+    #[test]
+    fn test_translate_errors() {
+        let gcode = &mut Translator::new();
+        for block in &[
+            "X1", "G0 G1 X1", "G1 X1 X2", "P1", "G4 P1", "G91.1", "(open",
+        ] {
+            assert_eq!(gcode.translate(block).unwrap_err().code(), INVALID_GCODE);
+        }
+        for block in &["#0=1", "#1 2", "F[1/0]", "F[1", "X1..2", "T-1", "T1.5"] {
+            assert_eq!(gcode.translate(block).unwrap_err().code(), INVALID_GCODE);
+        }
+        for block in &["G2 X1", "G1 X1 I1", "G2 X0 R1", "G2 X4 R1", "G2 X1 R1 I1"] {
+            assert_eq!(gcode.translate(block).unwrap_err().code(), INVALID_GCODE);
+        }
+    }
+
+    #[test]
+    fn test_translate_error_keeps_state() {
+        let gcode = &mut Translator::new();
+        gcode.translate("G1 X1").unwrap();
+        for block in &["G20 G91 G18 G2 X4 R1", "G20 G91 G19 G0 X1 M1.5", "G20 T-1"] {
+            assert_eq!(gcode.translate(block).unwrap_err().code(), INVALID_GCODE);
+        }
+        assert_eq!(gcode.plane(), Plane::Xy);
+        assert_eq!(gcode.position(), [1.0, 0.0, 0.0]);
+        assert_eq!(gcode.translate("X2").unwrap(), "2e mm 0e mm 0e mm g1");
+    }
+
+    #[test]
+    fn test_include_gcode() {
+        let dir = env::temp_dir().join(format!("rtforth-gcode-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("part.ngc");
+        fs::write(
+            &program,
+            b"%\r\nG21 G90 (mm)\r\nG1 X10 F600\r\nG91 Y5\r\n%\r\n",
+        )
+        .unwrap();
+        fs::write(dir.join("bad.nc"), b"G1 X1\nG5 X2\n").unwrap();
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(": f ;  : g1 ;");
+        vm.evaluate_input();
+        vm.set_source(&format!("s\" {}\" included", program.display()));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        let f = vm.f_stack().as_slice().to_vec();
+        assert_eq!(f.len(), 7);
+        for (a, b) in f
+            .iter()
+            .zip([0.01, 0.01, 0.0, 0.0, 0.01, 0.005, 0.0].iter())
+        {
+            assert_abs_diff_eq!(*a, *b, epsilon = 1e-15);
+        }
+        let sid = vm
+            .open_include(dir.join("bad.nc").to_str().unwrap(), false)
+            .unwrap();
+        assert_eq!(vm.load_line(sid).unwrap(), (20, true));
+        assert_eq!(
+            vm.lines()[sid - 1].as_ref().unwrap(),
+            "1e mm 0e mm 0e mm g1"
+        );
+        assert_eq!(vm.load_line(sid).unwrap_err().code(), INVALID_GCODE);
+        assert_eq!(vm.lines()[sid - 1].as_ref().unwrap(), "");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
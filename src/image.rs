@@ -0,0 +1,649 @@
+//! Dictionary images
+//!
+//! An image holds the word list, the data space and the other dictionary
+//! state of a VM, so that an application can boot from a file instead of
+//! running `add_core`, `load_core_fs` and its own Forth source again.
+//!
+//! Function pointers are not saved. The action and compilation semantics
+//! of a word are saved either as an index into a table of run-time
+//! routines known to `Image`, or as the name of a primitive with the same
+//! action or compilation semantics, which is looked up in the VM loading
+//! the image. So the loading VM must have been built with the same word
+//! sets and application primitives, but need not load `core.fs`. Words
+//! defined with `add_closure` are bound by name to closures of the loading
+//! VM.
+//!
+//! Code and data in the data space are not tagged, so addresses in them
+//! cannot be told from other numbers and could not be relocated. This
+//! module therefore requires feature `offset-addresses`, with which
+//! addresses are the same in every VM, so that an image can be loaded into
+//! any VM with enough data space.
+//!
+//! The heap of the memory-allocation word set is saved as well, so that
+//! pointers to allocated memory stay valid. The loading VM must have a
+//! heap of the same size.
+//!
+//! ```text
+//! : main ." hello" cr ;
+//! : save   ['] main s" app.img" turnkey ;  save
+//! ```
+
+use core::{Core, Word, WordType, BUCKET_SIZE, WORDLISTS};
+use exception::ForthError;
+use float::Float;
+use memory::{Memory, SystemVariables};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::mem;
+use units::Units;
+use Result;
+
+const MAGIC: &[u8; 8] = b"RTFIMG02";
+const NO_TURNKEY: usize = !0;
+const NO_CLOSURE: usize = !0;
+
+const RELOC_RUNTIME: u8 = 0;
+const RELOC_ACTION: u8 = 1;
+const RELOC_COMPILATION: u8 = 2;
+
+// How a function pointer is saved.
+enum Reloc {
+    Runtime(usize),
+    Action(String, usize),
+    Compilation(String, usize),
+}
+
+// Reader of image files
+struct Reader<'a> {
+    path: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn invalid(&self) -> ForthError {
+        ForthError::io(
+            self.path,
+            io::Error::new(io::ErrorKind::InvalidData, "invalid image"),
+        )
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len <= self.bytes.len() - self.pos {
+            let b = &self.bytes[self.pos..self.pos + len];
+            self.pos += len;
+            Ok(b)
+        } else {
+            Err(self.invalid())
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        let b = self.bytes(mem::size_of::<usize>())?;
+        let mut x = [0u8; mem::size_of::<usize>()];
+        x.copy_from_slice(b);
+        Ok(usize::from_ne_bytes(x))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.usize()?;
+        let b = self.bytes(len)?;
+        Ok(String::from_utf8_lossy(b).into_owned())
+    }
+}
+
+fn put_usize(buf: &mut Vec<u8>, x: usize) {
+    buf.extend_from_slice(&x.to_ne_bytes());
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    put_usize(buf, s.len());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn word_type_from_u8(t: u8) -> Option<WordType> {
+    match t {
+        x if x == WordType::Native as u8 => Some(WordType::Native),
+        x if x == WordType::Code as u8 => Some(WordType::Code),
+        x if x == WordType::Nest as u8 => Some(WordType::Nest),
+        x if x == WordType::Var as u8 => Some(WordType::Var),
+        x if x == WordType::Const as u8 => Some(WordType::Const),
+        x if x == WordType::Fconst as u8 => Some(WordType::Fconst),
+        x if x == WordType::Does as u8 => Some(WordType::Does),
+        x if x == WordType::Marker as u8 => Some(WordType::Marker),
+        x if x == WordType::Closure as u8 => Some(WordType::Closure),
+        x if x == WordType::Unit as u8 => Some(WordType::Unit),
+        _ => None,
+    }
+}
+
+pub trait Image: Core + Float + Units {
+    fn add_image(&mut self) {
+        self.add_primitive("save-image", Image::p_save_image);
+        self.add_primitive("turnkey", Image::p_turnkey);
+    }
+
+    /// Run-time routines which are not primitives of their own.
+    fn runtime_routines() -> Vec<primitive! {fn(&mut Self)}> {
+        vec![
+            Core::nest,
+            Core::p_var,
+            Core::p_const,
+            Core::xdoes,
+            Core::unmark,
+            Core::p_closure,
+            Float::p_fconst,
+            Core::compile_comma,
+            Core::compile_var,
+            Core::compile_const,
+            Core::compile_fconst,
+            Core::compile_leave,
+            Units::p_unit,
+            Units::p_to_unit,
+        ]
+    }
+
+    /// Save the dictionary to file `path`.
+    ///
+    /// If `turnkey` is some xt, `load_image` returns it, so that the
+    /// application can execute it after loading.
+    fn save_image(&mut self, path: &str, turnkey: Option<usize>) -> Result {
+        let routines: Vec<usize> = Self::runtime_routines()
+            .into_iter()
+            .map(|f| f as usize)
+            .collect();
+        let mut actions = HashMap::new();
+        let mut compilations = HashMap::new();
+        for w in &self.wordlist().words {
+            if w.word_type == WordType::Native {
+                let name = unsafe { self.data_space_const().get_str(w.nfa) };
+                actions
+                    .entry(w.action as usize)
+                    .or_insert_with(|| (name.to_string(), w.wordlist));
+                compilations
+                    .entry(w.compilation_semantics as usize)
+                    .or_insert_with(|| (name.to_string(), w.wordlist));
+            }
+        }
+        let reloc = |f: usize| -> Result<Reloc> {
+            if let Some(i) = routines.iter().position(|&r| r == f) {
+                Ok(Reloc::Runtime(i))
+            } else if let Some(&(ref name, wid)) = actions.get(&f) {
+                Ok(Reloc::Action(name.clone(), wid))
+            } else if let Some(&(ref name, wid)) = compilations.get(&f) {
+                Ok(Reloc::Compilation(name.clone(), wid))
+            } else {
+                Err(ForthError::io(
+                    path,
+                    io::Error::new(io::ErrorKind::InvalidInput, "unknown action"),
+                ))
+            }
+        };
+
+        let start = self.data_space().start();
+        let len = self.data_space().here() - start;
+        let mut buf = Vec::with_capacity(len + 64 * self.wordlist().len());
+        buf.extend_from_slice(MAGIC);
+        put_usize(&mut buf, mem::size_of::<usize>());
+        put_usize(&mut buf, start);
+        put_usize(&mut buf, self.data_space().limit());
+        put_usize(&mut buf, turnkey.unwrap_or(NO_TURNKEY));
+        put_usize(&mut buf, len);
+        buf.extend_from_slice(unsafe { self.data_space().buffer_from_raw_parts(start, len) });
+
+        put_usize(&mut buf, self.wordlist().len());
+        for w in &self.wordlist().words {
+            buf.push(w.word_type as u8);
+            buf.push(w.is_immediate as u8 | (w.is_compile_only as u8) << 1 | (w.hidden as u8) << 2);
+            put_usize(&mut buf, w.wordlist);
+            put_usize(&mut buf, w.link);
+            put_usize(&mut buf, w.hash as usize);
+            put_usize(&mut buf, w.nfa);
+            put_usize(&mut buf, w.dfa);
+            put_usize(&mut buf, w.doer);
+            for &f in &[w.action as usize, w.compilation_semantics as usize] {
+                match reloc(f)? {
+                    Reloc::Runtime(i) => {
+                        buf.push(RELOC_RUNTIME);
+                        put_usize(&mut buf, i);
+                    }
+                    Reloc::Action(name, wid) => {
+                        buf.push(RELOC_ACTION);
+                        put_string(&mut buf, &name);
+                        put_usize(&mut buf, wid);
+                    }
+                    Reloc::Compilation(name, wid) => {
+                        buf.push(RELOC_COMPILATION);
+                        put_string(&mut buf, &name);
+                        put_usize(&mut buf, wid);
+                    }
+                }
+            }
+        }
+        for &b in self.wordlist().buckets.iter() {
+            put_usize(&mut buf, b);
+        }
+        for &w in self.wordlist().search_order.iter() {
+            put_usize(&mut buf, w);
+        }
+        put_usize(&mut buf, self.wordlist().search_order_len);
+        put_usize(&mut buf, self.wordlist().current);
+        put_usize(&mut buf, self.wordlist().last_wordlist);
+        put_usize(&mut buf, self.wordlist().last);
+        put_usize(&mut buf, self.handler());
+        put_usize(&mut buf, self.labels().len());
+        for &l in self.labels() {
+            put_usize(&mut buf, l);
+        }
+        let heap_start = self.data_space().heap().start();
+        let heap_size = self.data_space().heap().size();
+        put_usize(&mut buf, heap_size);
+        buf.extend_from_slice(unsafe {
+            self.data_space()
+                .buffer_from_raw_parts(heap_start, heap_size)
+        });
+        for c in self.data_space().heap().index() {
+            put_usize(&mut buf, c);
+        }
+        fs::write(path, buf).map_err(|e| ForthError::io(path, e))
+    }
+
+    /// Replace the dictionary with the image in file `path`.
+    ///
+    /// Returns the turnkey xt of the image, if any. The dictionary is not
+    /// changed if the image cannot be loaded.
+    fn load_image(&mut self, path: &str) -> Result<Option<usize>> {
+        let bytes = fs::read(path).map_err(|e| ForthError::io(path, e))?;
+        let mut r = Reader {
+            path,
+            bytes: &bytes,
+            pos: 0,
+        };
+        if r.bytes(MAGIC.len())? != MAGIC || r.usize()? != mem::size_of::<usize>() {
+            return Err(r.invalid());
+        }
+        let old_start = r.usize()?;
+        // Limit of the data space of the saving VM, not needed.
+        r.usize()?;
+        let turnkey = r.usize()?;
+        let len = r.usize()?;
+        let data = r.bytes(len)?;
+        if len < mem::size_of::<SystemVariables>()
+            || len > self.data_space().dictionary_limit() - self.data_space().start()
+        {
+            return Err(r.invalid());
+        }
+        let start = self.data_space().start();
+        if old_start != start {
+            return Err(ForthError::io(
+                path,
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "image saved from a data space at another address",
+                ),
+            ));
+        }
+
+        // Primitives and closures of this VM by name.
+        let routines = Self::runtime_routines();
+        let mut actions = HashMap::new();
+        let mut compilations = HashMap::new();
+        let mut closures = HashMap::new();
+        for w in &self.wordlist().words {
+            let name = unsafe { self.data_space_const().get_str(w.nfa) }.to_string();
+            match w.word_type {
+                WordType::Native => {
+                    let key = (name, w.wordlist);
+                    actions.entry(key.clone()).or_insert(w.action);
+                    compilations.entry(key).or_insert(w.compilation_semantics);
+                }
+                WordType::Closure => {
+                    let i = unsafe { self.data_space_const().get_usize(w.dfa) };
+                    closures.entry((name, w.wordlist)).or_insert(i);
+                }
+                _ => {}
+            }
+        }
+
+        let count = r.usize()?;
+        let mut words = Vec::with_capacity(count);
+        let mut closures_to_bind = Vec::new();
+        for _ in 0..count {
+            let word_type = match word_type_from_u8(r.u8()?) {
+                Some(t) => t,
+                None => return Err(r.invalid()),
+            };
+            let flags = r.u8()?;
+            let wid = r.usize()?;
+            let link = r.usize()?;
+            let hash = r.usize()? as u32;
+            let nfa = r.usize()?;
+            let dfa = r.usize()?;
+            let doer = r.usize()?;
+            let mut fns: [primitive! {fn(&mut Self)}; 2] = [Core::noop, Core::noop];
+            for f in fns.iter_mut() {
+                let found = match r.u8()? {
+                    RELOC_RUNTIME => routines.get(r.usize()?).cloned(),
+                    RELOC_ACTION => {
+                        let key = (r.string()?, r.usize()?);
+                        actions.get(&key).cloned()
+                    }
+                    RELOC_COMPILATION => {
+                        let key = (r.string()?, r.usize()?);
+                        compilations.get(&key).cloned()
+                    }
+                    _ => None,
+                };
+                match found {
+                    Some(x) => *f = x,
+                    None => return Err(r.invalid()),
+                }
+            }
+            let mut word = Word::new(word_type, wid, fns[0], fns[1], nfa, dfa);
+            word.is_immediate = flags & 1 != 0;
+            word.is_compile_only = flags & 2 != 0;
+            word.hidden = flags & 4 != 0;
+            word.link = link;
+            word.hash = hash;
+            word.doer = doer;
+            if word_type == WordType::Closure {
+                closures_to_bind.push((nfa, dfa, wid));
+            }
+            words.push(word);
+        }
+        let mut buckets = [0; BUCKET_SIZE * WORDLISTS];
+        for b in buckets.iter_mut() {
+            *b = r.usize()?;
+        }
+        let mut search_order = [0; WORDLISTS];
+        for w in search_order.iter_mut() {
+            *w = r.usize()?;
+        }
+        let search_order_len = r.usize()?;
+        let current = r.usize()?;
+        let last_wordlist = r.usize()?;
+        let last = r.usize()?;
+        let handler = r.usize()?;
+        let label_count = r.usize()?;
+        let mut labels = Vec::with_capacity(label_count);
+        for _ in 0..label_count {
+            labels.push(r.usize()?);
+        }
+        if search_order_len > WORDLISTS || last >= count.max(1) || handler >= count.max(1) {
+            return Err(r.invalid());
+        }
+        let heap_size = r.usize()?;
+        if heap_size != self.data_space().heap().size() {
+            return Err(ForthError::io(
+                path,
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "image saved with a heap of another size",
+                ),
+            ));
+        }
+        let heap_data = r.bytes(heap_size)?;
+        let index_len = self.data_space().heap().index().len();
+        let mut heap_index = Vec::with_capacity(index_len);
+        for _ in 0..index_len {
+            heap_index.push(r.usize()?);
+        }
+        if !self.data_space().heap().is_index(&heap_index) {
+            return Err(r.invalid());
+        }
+
+        // The image is valid, replace the dictionary.
+        //
+        // Vectors in system variables hold function pointers of this VM.
+        let vectors = {
+            let sv = self.data_space().system_variables();
+            [
+                sv.compile_comma_vector(),
+                sv.compile_integer_vector(),
+                sv.compile_var_vector(),
+                sv.compile_const_vector(),
+                sv.compile_fconst_vector(),
+                sv.compile_float_vector(),
+            ]
+        };
+        let saved: Vec<isize> = vectors
+            .iter()
+            .map(|&v| unsafe { self.data_space().get_isize(v) })
+            .collect();
+        unsafe {
+            self.data_space()
+                .buffer_from_raw_parts_mut(start, len)
+                .copy_from_slice(data);
+        }
+        self.data_space().set_here(start + len);
+        let heap_start = self.data_space().heap().start();
+        unsafe {
+            self.data_space()
+                .buffer_from_raw_parts_mut(heap_start, heap_size)
+                .copy_from_slice(heap_data);
+        }
+        self.data_space().heap_mut().set_index(&heap_index);
+        for (&v, &x) in vectors.iter().zip(saved.iter()) {
+            unsafe { self.data_space().put_isize(x, v) };
+        }
+        for &(nfa, dfa, wid) in &closures_to_bind {
+            let mut i = NO_CLOSURE;
+            if start <= nfa && nfa + mem::size_of::<usize>() <= start + len {
+                let n = unsafe { self.data_space().get_usize(nfa) };
+                if n <= start + len - nfa - mem::size_of::<usize>() {
+                    let name = unsafe { self.data_space().get_str(nfa) }.to_string();
+                    if let Some(&x) = closures.get(&(name, wid)) {
+                        i = x;
+                    }
+                }
+            }
+            if start <= dfa && dfa + mem::size_of::<usize>() <= start + len {
+                unsafe { self.data_space().put_usize(i, dfa) };
+            }
+        }
+        {
+            let wordlist = self.wordlist_mut();
+            wordlist.words = words;
+            wordlist.buckets = buckets;
+            wordlist.temp_buckets = [0; BUCKET_SIZE * WORDLISTS];
+            wordlist.search_order = search_order;
+            wordlist.search_order_len = search_order_len;
+            wordlist.current = current;
+            wordlist.last_wordlist = last_wordlist;
+            wordlist.last = last;
+        }
+        self.set_handler(handler);
+        *self.labels_mut() = labels;
+        self.forward_bitset_mut().clear();
+        self.resolved_bitset_mut().clear();
+        // The block buffers were overwritten.
+        self.blocks().empty();
+        if self.data_space().is_safe() {
+            // Protect the loaded code.
+            self.set_safe_mode(true);
+        }
+        Ok(if turnkey == NO_TURNKEY {
+            None
+        } else {
+            Some(turnkey)
+        })
+    }
+
+    // ( c-addr u -- )
+    //
+    // Save the dictionary to the file with name c-addr u.
+    primitive! {fn p_save_image(&mut self) {
+        let (caddr, u) = self.s_stack().pop2();
+        let path = unsafe{ self.data_space().str_from_raw_parts(caddr as _, u as _) }.to_string();
+        if let Err(e) = self.save_image(&path, None) {
+            self.abort_with(e.code());
+        }
+    }}
+
+    // ( xt c-addr u -- )
+    //
+    // Save the dictionary to the file with name c-addr u, which starts
+    // with `xt` after loaded.
+    primitive! {fn p_turnkey(&mut self) {
+        let (xt, caddr, u) = self.s_stack().pop3();
+        let path = unsafe{ self.data_space().str_from_raw_parts(caddr as _, u as _) }.to_string();
+        if let Err(e) = self.save_image(&path, Some(xt as usize)) {
+            self.abort_with(e.code());
+        }
+    }}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Image;
+    use core::Core;
+    use loader::HasLoader;
+    use memory::Memory;
+    use mock_vm::{Mock, NewVM, VM};
+    use std::env;
+    use std::fs;
+    use vm::VMBuilder;
+
+    fn image_path(name: &str) -> String {
+        env::temp_dir()
+            .join(format!("rtforth-{}-{}.img", name, ::std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // Define words, and a variable holding a number which looks like an
+    // address in the data space.
+    fn define_words(vm: &mut VM) -> isize {
+        let n = (vm.data_space().start() + 64) as isize;
+        vm.s_stack().push(n);
+        vm.load_str(
+            "variable n  n !  variable v  42 v !  3 constant three
+             2.5e fconstant half5
+             : 2const create , , does> 2@ ;  4 40 2const range
+             : greet s\" hello\" type ;
+             : sum 0 10 0 do i + loop ;",
+        );
+        assert_eq!(vm.last_error(), None);
+        n
+    }
+
+    fn check_words(vm: &mut VM, n: isize) {
+        vm.set_source("n @ v @ three range sum");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [n, 42, 3, 4, 40, 45]);
+        vm.s_stack().reset();
+        vm.set_source("half5 f>s");
+        vm.evaluate_input();
+        assert_eq!(vm.s_stack().pop(), 2);
+        vm.set_output_buffer(String::new());
+        vm.set_source("greet : new-word 7 ; new-word");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.output_buffer().as_ref().unwrap(), "hello");
+        assert_eq!(vm.s_stack().pop(), 7);
+    }
+
+    #[test]
+    fn test_save_and_load_image() {
+        let path = image_path("save-load");
+        let vm = &mut VM::new();
+        let n = define_words(vm);
+        assert!(vm.save_image(&path, None).is_ok());
+        vm.set_source("0 n !  0 v !  : extra ;");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.load_image(&path).ok(), Some(None));
+        fs::remove_file(&path).unwrap();
+        assert!(vm.find("extra").is_none());
+        check_words(vm, n);
+    }
+
+    #[test]
+    fn test_load_image_into_other_vm() {
+        let path = image_path("other-vm");
+        let n = {
+            let vm = &mut VM::new();
+            vm.add_closure("answer", |vm: &mut VM| vm.s_stack().push(42));
+            let n = define_words(vm);
+            vm.set_source(&format!(
+                "variable p  64 allocate drop p !  1234 p @ !
+                 : main answer 1+ ;  : save ['] main s\" {}\" turnkey ;  save",
+                path
+            ));
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), None);
+            n
+        };
+        let vm = &mut VMBuilder::new().core_fs(false).build_with(Mock);
+        vm.add_closure("answer", |vm: &mut VM| vm.s_stack().push(41));
+        let main = vm.load_image(&path).unwrap().expect("turnkey");
+        fs::remove_file(&path).unwrap();
+        vm.execute_word(main);
+        vm.run();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().pop(), 42);
+        check_words(vm, n);
+        // The heap is restored with the pointer to it.
+        vm.s_stack().reset();
+        vm.set_source("p @ @  p @ free  16 allocate nip");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [1234, 0, 0]);
+    }
+
+    #[test]
+    fn test_load_image_with_other_heap_size() {
+        let path = image_path("heap-size");
+        let vm = &mut VM::new();
+        assert!(vm.save_image(&path, None).is_ok());
+        let vm = &mut VMBuilder::new()
+            .core_fs(false)
+            .heap_size(4096)
+            .build_with(Mock);
+        assert!(vm.load_image(&path).is_err());
+        fs::remove_file(&path).unwrap();
+        vm.set_source("1 2 +");
+        vm.evaluate_input();
+        assert_eq!(vm.s_stack().pop(), 3);
+    }
+
+    #[test]
+    fn test_turnkey() {
+        let path = image_path("turnkey");
+        let vm = &mut VM::new();
+        vm.add_closure("answer", |vm: &mut VM| vm.s_stack().push(42));
+        vm.set_source(&format!(
+            ": main answer 1+ ;  : save ['] main s\" {}\" turnkey ;  save",
+            path
+        ));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        vm.set_source(": main 0 ;");
+        vm.evaluate_input();
+        let main = vm.load_image(&path).unwrap().expect("turnkey");
+        fs::remove_file(&path).unwrap();
+        vm.execute_word(main);
+        vm.run();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [43]);
+    }
+
+    #[test]
+    fn test_load_invalid_image() {
+        let path = image_path("invalid");
+        fs::write(&path, b"RTFIMG01 garbage").unwrap();
+        let vm = &mut VM::new();
+        assert!(vm.load_image(&path).is_err());
+        assert!(vm.load_image("/non-existent/rtforth.img").is_err());
+        fs::remove_file(&path).unwrap();
+        vm.set_source("1 2 +");
+        vm.evaluate_input();
+        assert_eq!(vm.s_stack().pop(), 3);
+    }
+}
@@ -21,21 +21,34 @@ macro_rules! primitive {
 extern crate approx;
 extern crate hibitset;
 
+pub mod block;
 pub mod core;
+pub mod dsp;
+pub mod embed;
 pub mod env;
 pub mod exception;
 pub mod facility;
 pub mod file_access;
+pub mod fixed;
 pub mod float;
+pub mod gcode;
+#[cfg(feature = "offset-addresses")]
+pub mod image;
+pub mod linalg;
 pub mod loader;
 pub mod memory;
+pub mod memory_allocation;
 pub mod mock_vm;
+pub mod motion;
 pub mod output;
 pub(crate) mod parser;
+pub mod sandbox;
 pub mod tools;
 pub mod units;
+pub mod vm;
 
 use core::Core;
+use exception::ForthError;
 use memory::Memory;
 use std::result;
 
@@ -43,4 +56,4 @@ pub const TRUE: isize = -1;
 pub const FALSE: isize = 0;
 pub const NUM_TASKS: usize = 8;
 
-pub type Result = result::Result<(), isize>;
+pub type Result<T = ()> = result::Result<T, ForthError>;
@@ -0,0 +1,520 @@
+//! Linear algebra on float arrays
+//!
+//! Vectors and matrices are arrays of floats in data space. A matrix of
+//! `rows` by `cols` is stored row by row. Quaternions are arrays of four
+//! floats `w x y z`.
+//!
+//! Addresses are float-aligned as with `F@`. Arrays must lie in data space
+//! or the words abort with `INVALID_MEMORY_ADDRESS`. Results are written
+//! directly to the destination. A destination may overlap a source if the
+//! result has at most `SCRATCH_FLOATS` floats, it is then computed into a
+//! scratch array first; otherwise the words abort with
+//! `INVALID_NUMERIC_ARGUMENT`. A square matrix can always be transposed in
+//! place and `FV-AXPY` can always add to the vector itself. A NaN or
+//! infinite result raises the float status, see `Float::float_status`. If
+//! strict float mode aborts, the destination is left unchanged.
+//!
+//! ```
+//! use rtforth::core::Core;
+//! use rtforth::vm::VMBuilder;
+//!
+//! let mut vm = VMBuilder::new().build();
+//! vm.set_source("
+//!     create a  1e f, 2e f, 3e f,
+//!     create b  4e f, 5e f, 6e f,
+//!     a b 3 fv-dot
+//! ");
+//! vm.evaluate_input();
+//! assert_eq!(vm.f_stack().pop(), 32.0);
+//! ```
+
+use exception::{INVALID_MEMORY_ADDRESS, INVALID_NUMERIC_ARGUMENT, WRITE_TO_A_READ_ONLY_LOCATION};
+use float::{float_status_of, Float, FLOAT_INVALID};
+use memory::{DataSpace, Memory};
+use std::mem;
+
+/// Largest result in floats that may overlap a source, enough for a 4 by 4
+/// matrix.
+pub const SCRATCH_FLOATS: usize = 16;
+
+/// True if the float arrays of `a_len` floats at `a` and `b_len` floats at
+/// `b` share memory.
+fn overlaps(a: usize, a_len: usize, b: usize, b_len: usize) -> bool {
+    let size = mem::size_of::<f64>();
+    a_len != 0 && b_len != 0 && a < b + b_len * size && b < a + a_len * size
+}
+
+pub trait LinearAlgebra: Float {
+    fn add_linear_algebra(&mut self) {
+        self.add_primitive("fv-dot", LinearAlgebra::fv_dot);
+        self.add_primitive("fv-axpy", LinearAlgebra::fv_axpy);
+        self.add_primitive("fv-cross", LinearAlgebra::fv_cross);
+        self.add_primitive("fm*v", LinearAlgebra::fm_star_v);
+        self.add_primitive("fm*m", LinearAlgebra::fm_star_m);
+        self.add_primitive("fm-transpose", LinearAlgebra::fm_transpose);
+        self.add_primitive("fq*", LinearAlgebra::fq_star);
+        self.add_primitive("fq-normalize", LinearAlgebra::fq_normalize);
+    }
+
+    /// Float-aligned address of an array of `len` floats at `addr`.
+    ///
+    /// Aborts and returns `None` if `len` is negative, if the array is not
+    /// in data space, or if `write` is true and the array is write
+    /// protected.
+    fn float_array(&mut self, addr: isize, len: isize, write: bool) -> Option<usize> {
+        if len < 0 {
+            self.abort_with(INVALID_NUMERIC_ARGUMENT);
+            return None;
+        }
+        let start = self.data_space().start();
+        let limit = self.data_space().limit();
+        let addr = addr as usize;
+        let bytes = (len as usize).checked_mul(mem::size_of::<f64>());
+        let addr = if start <= addr && addr <= limit {
+            DataSpace::aligned_f64(addr)
+        } else {
+            !0
+        };
+        match bytes {
+            Some(bytes) if addr <= limit && bytes <= limit - addr => {
+                if write && self.data_space().is_read_only(addr, bytes) {
+                    self.abort_with(WRITE_TO_A_READ_ONLY_LOCATION);
+                    None
+                } else {
+                    Some(addr)
+                }
+            }
+            _ => {
+                self.abort_with(INVALID_MEMORY_ADDRESS);
+                None
+            }
+        }
+    }
+
+    /// Element `i` of the float array at `addr`.
+    fn float_at(&mut self, addr: usize, i: usize) -> f64 {
+        unsafe { self.data_space().get_f64(addr + i * mem::size_of::<f64>()) }
+    }
+
+    /// Raise the float status of `values` and store them in the float
+    /// array at `addr`. Nothing is stored if strict float mode aborts.
+    fn store_floats(&mut self, addr: usize, values: &[f64]) {
+        let status = values
+            .iter()
+            .fold(0, |status, &v| status | float_status_of(v, false));
+        if self.raise_float_status(status) {
+            return;
+        }
+        for (i, &v) in values.iter().enumerate() {
+            unsafe {
+                self.data_space()
+                    .put_f64(v, addr + i * mem::size_of::<f64>())
+            };
+        }
+    }
+
+    /// Store the result of `len` floats at `addr`, element `i` being
+    /// `f(self, i)`, as `store_floats` does. A staged result is computed
+    /// into a scratch array first, see `needs_scratch`. Otherwise `f` is
+    /// called twice for each element, once to raise the float status and
+    /// once to store it, so `f` must not read the elements stored before.
+    fn store_result<F>(&mut self, addr: usize, len: usize, staged: bool, f: F)
+    where
+        F: Fn(&mut Self, usize) -> f64,
+    {
+        if staged {
+            let mut scratch = [0.0; SCRATCH_FLOATS];
+            for (i, v) in scratch[..len].iter_mut().enumerate() {
+                *v = f(self, i);
+            }
+            self.store_floats(addr, &scratch[..len]);
+            return;
+        }
+        let mut status = 0;
+        for i in 0..len {
+            status |= float_status_of(f(self, i), false);
+        }
+        if self.raise_float_status(status) {
+            return;
+        }
+        for i in 0..len {
+            let v = f(self, i);
+            unsafe {
+                self.data_space()
+                    .put_f64(v, addr + i * mem::size_of::<f64>())
+            };
+        }
+    }
+
+    /// True if a result of `len` floats at `dest` overlapping one of
+    /// `sources` must be staged in a scratch array. Aborts with
+    /// `INVALID_NUMERIC_ARGUMENT` and returns `None` if it is larger than
+    /// `SCRATCH_FLOATS`.
+    fn needs_scratch(
+        &mut self,
+        dest: usize,
+        len: usize,
+        sources: &[(usize, usize)],
+    ) -> Option<bool> {
+        if !sources
+            .iter()
+            .any(|&(a, a_len)| overlaps(dest, len, a, a_len))
+        {
+            Some(false)
+        } else if len <= SCRATCH_FLOATS {
+            Some(true)
+        } else {
+            self.abort_with(INVALID_NUMERIC_ARGUMENT);
+            None
+        }
+    }
+
+    /// Number of elements of a `rows` by `cols` matrix. Aborts with
+    /// `INVALID_NUMERIC_ARGUMENT` and returns -1 if it does not fit.
+    fn matrix_len(&mut self, rows: isize, cols: isize) -> isize {
+        match rows.checked_mul(cols) {
+            Some(n) if rows >= 0 && cols >= 0 => n,
+            _ => {
+                self.abort_with(INVALID_NUMERIC_ARGUMENT);
+                -1
+            }
+        }
+    }
+
+    // FV-DOT ( f-addr1 f-addr2 u -- ) ( F: -- r )
+    // r is the dot product of the vectors of u floats at f-addr1 and
+    // f-addr2.
+    primitive! {fn fv_dot(&mut self) {
+        let (x, y, u) = self.s_stack().pop3();
+        let x = match self.float_array(x, u, false) { Some(a) => a, None => return };
+        let y = match self.float_array(y, u, false) { Some(a) => a, None => return };
+        let mut sum = 0.0;
+        for i in 0..u as usize {
+            sum += self.float_at(x, i) * self.float_at(y, i);
+        }
+        self.push_float_result(sum, false);
+    }}
+
+    // FV-AXPY ( f-addr1 f-addr2 u -- ) ( F: r -- )
+    // Add r times the vector of u floats at f-addr1 to the vector at
+    // f-addr2.
+    primitive! {fn fv_axpy(&mut self) {
+        let (x, y, u) = self.s_stack().pop3();
+        let a = self.f_stack().pop();
+        let x = match self.float_array(x, u, false) { Some(a) => a, None => return };
+        let y = match self.float_array(y, u, true) { Some(a) => a, None => return };
+        // Each element only depends on itself if the vectors are the same.
+        let sources: &[(usize, usize)] = if x == y { &[] } else { &[(x, u as usize)] };
+        let staged = match self.needs_scratch(y, u as usize, sources) { Some(s) => s, None => return };
+        self.store_result(y, u as usize, staged, |vm, i| a * vm.float_at(x, i) + vm.float_at(y, i));
+    }}
+
+    // FV-CROSS ( f-addr1 f-addr2 f-addr3 -- )
+    // Store the cross product of the 3D vectors at f-addr1 and f-addr2 at
+    // f-addr3.
+    primitive! {fn fv_cross(&mut self) {
+        let (a, b, c) = self.s_stack().pop3();
+        let a = match self.float_array(a, 3, false) { Some(a) => a, None => return };
+        let b = match self.float_array(b, 3, false) { Some(a) => a, None => return };
+        let c = match self.float_array(c, 3, true) { Some(a) => a, None => return };
+        let (a0, a1, a2) = (self.float_at(a, 0), self.float_at(a, 1), self.float_at(a, 2));
+        let (b0, b1, b2) = (self.float_at(b, 0), self.float_at(b, 1), self.float_at(b, 2));
+        self.store_floats(c, &[a1 * b2 - a2 * b1, a2 * b0 - a0 * b2, a0 * b1 - a1 * b0]);
+    }}
+
+    // FM*V ( f-addr1 f-addr2 f-addr3 rows cols -- )
+    // Store the product of the rows by cols matrix at f-addr1 and the
+    // vector of cols floats at f-addr2 at f-addr3, a vector of rows floats.
+    primitive! {fn fm_star_v(&mut self) {
+        let (rows, cols) = self.s_stack().pop2();
+        let (m, x, y) = self.s_stack().pop3();
+        let len = self.matrix_len(rows, cols);
+        if len < 0 {
+            return;
+        }
+        let m = match self.float_array(m, len, false) { Some(a) => a, None => return };
+        let x = match self.float_array(x, cols, false) { Some(a) => a, None => return };
+        let y = match self.float_array(y, rows, true) { Some(a) => a, None => return };
+        let (rows, cols) = (rows as usize, cols as usize);
+        let staged = match self.needs_scratch(y, rows, &[(m, len as usize), (x, cols)]) { Some(s) => s, None => return };
+        self.store_result(y, rows, staged, |vm, i| {
+            (0..cols).map(|j| vm.float_at(m, i * cols + j) * vm.float_at(x, j)).sum()
+        });
+    }}
+
+    // FM*M ( f-addr1 f-addr2 f-addr3 n m p -- )
+    // Store the product of the n by m matrix at f-addr1 and the m by p
+    // matrix at f-addr2 at f-addr3, an n by p matrix.
+    primitive! {fn fm_star_m(&mut self) {
+        let (n, m, p) = self.s_stack().pop3();
+        let (a, b, c) = self.s_stack().pop3();
+        let (a_len, b_len, c_len) = (self.matrix_len(n, m), self.matrix_len(m, p), self.matrix_len(n, p));
+        if a_len < 0 || b_len < 0 || c_len < 0 {
+            return;
+        }
+        let a = match self.float_array(a, a_len, false) { Some(a) => a, None => return };
+        let b = match self.float_array(b, b_len, false) { Some(a) => a, None => return };
+        let c = match self.float_array(c, c_len, true) { Some(a) => a, None => return };
+        let (m, p) = (m as usize, p as usize);
+        let c_len = c_len as usize;
+        let staged = match self.needs_scratch(c, c_len, &[(a, a_len as usize), (b, b_len as usize)]) { Some(s) => s, None => return };
+        self.store_result(c, c_len, staged, |vm, ij| {
+            let (i, j) = (ij / p, ij % p);
+            (0..m).map(|k| vm.float_at(a, i * m + k) * vm.float_at(b, k * p + j)).sum()
+        });
+    }}
+
+    // FM-TRANSPOSE ( f-addr1 f-addr2 rows cols -- )
+    // Store the transpose of the rows by cols matrix at f-addr1 at f-addr2,
+    // a cols by rows matrix.
+    primitive! {fn fm_transpose(&mut self) {
+        let (rows, cols) = self.s_stack().pop2();
+        let (a, b) = self.s_stack().pop2();
+        let len = self.matrix_len(rows, cols);
+        if len < 0 {
+            return;
+        }
+        let a = match self.float_array(a, len, false) { Some(a) => a, None => return };
+        let b = match self.float_array(b, len, true) { Some(a) => a, None => return };
+        let (rows, cols, len) = (rows as usize, cols as usize, len as usize);
+        if a == b && rows == cols {
+            let mut status = 0;
+            for i in 0..len {
+                status |= float_status_of(self.float_at(a, i), false);
+            }
+            if self.raise_float_status(status) {
+                return;
+            }
+            let size = mem::size_of::<f64>();
+            for i in 0..rows {
+                for j in i + 1..cols {
+                    let (x, y) = (self.float_at(a, i * cols + j), self.float_at(a, j * cols + i));
+                    unsafe {
+                        self.data_space().put_f64(y, a + (i * cols + j) * size);
+                        self.data_space().put_f64(x, a + (j * cols + i) * size);
+                    }
+                }
+            }
+            return;
+        }
+        let staged = match self.needs_scratch(b, len, &[(a, len)]) { Some(s) => s, None => return };
+        self.store_result(b, len, staged, |vm, ji| {
+            let (j, i) = (ji / rows, ji % rows);
+            vm.float_at(a, i * cols + j)
+        });
+    }}
+
+    // FQ* ( f-addr1 f-addr2 f-addr3 -- )
+    // Store the Hamilton product of the quaternions at f-addr1 and f-addr2
+    // at f-addr3.
+    primitive! {fn fq_star(&mut self) {
+        let (a, b, c) = self.s_stack().pop3();
+        let a = match self.float_array(a, 4, false) { Some(a) => a, None => return };
+        let b = match self.float_array(b, 4, false) { Some(a) => a, None => return };
+        let c = match self.float_array(c, 4, true) { Some(a) => a, None => return };
+        let (w1, x1, y1, z1) = (self.float_at(a, 0), self.float_at(a, 1), self.float_at(a, 2), self.float_at(a, 3));
+        let (w2, x2, y2, z2) = (self.float_at(b, 0), self.float_at(b, 1), self.float_at(b, 2), self.float_at(b, 3));
+        self.store_floats(c, &[
+            w1 * w2 - x1 * x2 - y1 * y2 - z1 * z2,
+            w1 * x2 + x1 * w2 + y1 * z2 - z1 * y2,
+            w1 * y2 - x1 * z2 + y1 * w2 + z1 * x2,
+            w1 * z2 + x1 * y2 - y1 * x2 + z1 * w2,
+        ]);
+    }}
+
+    // FQ-NORMALIZE ( f-addr -- )
+    // Scale the quaternion at f-addr to unit length. A zero quaternion is
+    // left as it is and raises `FLOAT_INVALID`.
+    primitive! {fn fq_normalize(&mut self) {
+        let q = self.s_stack().pop();
+        let q = match self.float_array(q, 4, true) { Some(a) => a, None => return };
+        let v = [self.float_at(q, 0), self.float_at(q, 1), self.float_at(q, 2), self.float_at(q, 3)];
+        let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if norm == 0.0 {
+            self.raise_float_status(FLOAT_INVALID);
+            return;
+        }
+        self.store_floats(q, &[v[0] / norm, v[1] / norm, v[2] / norm, v[3] / norm]);
+    }}
+}
+
+#[cfg(test)]
+mod tests {
+    use core::Core;
+    use exception::{
+        FLOATING_POINT_INVALID_ARGUMENT, FLOATING_POINT_RESULT_OUT_OF_RANGE,
+        INVALID_MEMORY_ADDRESS, INVALID_NUMERIC_ARGUMENT,
+    };
+    use float::{Float, FLOAT_INVALID};
+    use memory::Memory;
+    use std::mem;
+    use vm::{VMBuilder, VM};
+
+    fn floats(vm: &mut VM, name: &str, len: usize) -> Vec<f64> {
+        let xt = vm.find(name).expect(name);
+        let addr = vm.wordlist()[xt].dfa();
+        (0..len)
+            .map(|i| unsafe { vm.data_space().get_f64(addr + i * mem::size_of::<f64>()) })
+            .collect()
+    }
+
+    #[test]
+    fn test_vectors() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(
+            "create x  1e f, 2e f, 3e f,
+             create y  4e f, 5e f, 6e f,
+             create z  3 floats allot
+             x y 3 fv-dot  x x 0 fv-dot
+             2e x y 3 fv-axpy
+             x y z fv-cross",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.f_stack().as_slice(), [32.0, 0.0]);
+        assert_eq!(floats(vm, "y", 3), [6.0, 9.0, 12.0]);
+        assert_eq!(floats(vm, "z", 3), [-3.0, 6.0, -3.0]);
+    }
+
+    #[test]
+    fn test_matrices() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(
+            "create a  1e f, 2e f, 3e f,  4e f, 5e f, 6e f,
+             create b  7e f, 8e f,  9e f, 10e f,  11e f, 12e f,
+             create c  4 floats allot
+             create t  6 floats allot
+             create v  1e f, 0e f, -1e f,
+             a b c 2 3 2 fm*m
+             a t 2 3 fm-transpose
+             a v v 2 3 fm*v",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(floats(vm, "c", 4), [58.0, 64.0, 139.0, 154.0]);
+        assert_eq!(floats(vm, "t", 6), [1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+        assert_eq!(floats(vm, "v", 3), [-2.0, -2.0, -1.0]);
+    }
+
+    #[test]
+    fn test_overlapping_destination() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(
+            "create a  1e f, 2e f,  3e f, 4e f,
+             create b  0e f, 1e f,  1e f, 0e f,
+             create r  1e f, 2e f, 3e f,  4e f, 5e f, 6e f,
+             a b a 2 2 2 fm*m
+             b b 2 2 fm-transpose
+             r r 2 3 fm-transpose",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(floats(vm, "a", 4), [2.0, 1.0, 4.0, 3.0]);
+        assert_eq!(floats(vm, "b", 4), [0.0, 1.0, 1.0, 0.0]);
+        assert_eq!(floats(vm, "r", 6), [1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+        vm.set_source(
+            "create s  5 5 * floats allot
+             : fill ( f-addr u -- ) ( F: r -- )  0 ?do fdup dup f! float+ loop drop fdrop ;
+             s 25 0.5e fill
+             s s 5 5 fm-transpose",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(floats(vm, "s", 25), [0.5; 25].to_vec());
+        vm.set_source("s s s 5 5 5 fm*m");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_NUMERIC_ARGUMENT));
+        assert_eq!(floats(vm, "s", 25), [0.5; 25].to_vec());
+    }
+
+    #[test]
+    fn test_quaternions() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(
+            "create i  0e f, 1e f, 0e f, 0e f,
+             create j  0e f, 0e f, 1e f, 0e f,
+             create k  4 floats allot
+             create q  1e f, 1e f, 1e f, 1e f,
+             i j k fq*  q fq-normalize",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(floats(vm, "k", 4), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(floats(vm, "q", 4), [0.5, 0.5, 0.5, 0.5]);
+        vm.set_source("create zero 4 floats allot  zero fq-normalize");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(floats(vm, "zero", 4), [0.0; 4]);
+        assert_eq!(vm.float_status(), FLOAT_INVALID);
+        vm.set_strict_float(true);
+        vm.set_source("zero fq-normalize");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(FLOATING_POINT_INVALID_ARGUMENT));
+        assert_eq!(floats(vm, "zero", 4), [0.0; 4]);
+    }
+
+    #[test]
+    fn test_strict_float_keeps_destination() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(
+            "1e308 fdup f+ fconstant inf
+             create x  1e f, inf f,
+             create m  1e f, 2e f, 3e f, inf f,
+             create y  7e f, 7e f,
+             create q  4 floats allot",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        vm.set_strict_float(true);
+        for source in &[
+            "1e x y 2 fv-axpy",
+            "x x y 2 1 fm*v",
+            "m x y 2 2 fm*v",
+            "x x y 2 1 1 fm*m",
+            "x y 1 2 fm-transpose",
+            "x m y fv-cross",
+        ] {
+            vm.set_error(None);
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(
+                vm.last_error(),
+                Some(FLOATING_POINT_RESULT_OUT_OF_RANGE),
+                "{}",
+                source
+            );
+            assert_eq!(floats(vm, "y", 2), [7.0, 7.0], "{}", source);
+        }
+        vm.set_error(None);
+        vm.set_source("m m 2 2 fm-transpose");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(FLOATING_POINT_RESULT_OUT_OF_RANGE));
+        assert_eq!(floats(vm, "m", 4), [1.0, 2.0, 3.0, f64::INFINITY]);
+        vm.set_error(None);
+        vm.set_source("m m m 2 2 2 fm*m");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(FLOATING_POINT_RESULT_OUT_OF_RANGE));
+        assert_eq!(floats(vm, "m", 4), [1.0, 2.0, 3.0, f64::INFINITY]);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source("create x 1e f,  x x -1 fv-dot");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_NUMERIC_ARGUMENT));
+        vm.set_error(None);
+        vm.set_source("x x 100000000 fv-dot");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_MEMORY_ADDRESS));
+        vm.set_error(None);
+        vm.set_source("-8 x 1 fv-dot");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_MEMORY_ADDRESS));
+        vm.set_error(None);
+        vm.set_source("x x x -1 -1 -1 fm*m");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_NUMERIC_ARGUMENT));
+        assert_eq!(vm.f_stack().len(), 0);
+    }
+}
@@ -1,14 +1,75 @@
 use core::Core;
-use exception::{FILE_IO_EXCEPTION, INVALID_NUMERIC_ARGUMENT};
+use exception::{
+    ForthError, FILE_IO_EXCEPTION, INVALID_MEMORY_ADDRESS, INVALID_NUMERIC_ARGUMENT,
+    NON_EXISTENT_FILE,
+};
+use gcode::{self, Translator};
 use memory::Memory;
 use output::Output;
-use std::fs::File;
+use std::env;
+use std::fs::{self, File};
 use std::io::BufRead;
 use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use Result;
 
 pub struct Source {
     reader: BufReader<File>,
     path: String,
+    /// Translator of a G-code source, see `gcode::is_gcode_path`.
+    gcode: Option<Translator>,
+}
+
+/// Files included by INCLUDED and REQUIRED, and the library path searched
+/// for them
+pub struct Includes {
+    files: Vec<PathBuf>,
+    path: Vec<PathBuf>,
+}
+
+impl Includes {
+    /// No files included and library path `path`.
+    pub fn new(path: Vec<PathBuf>) -> Includes {
+        Includes {
+            files: Vec::new(),
+            path,
+        }
+    }
+
+    /// Library path from environment variable `RTFORTH_PATH`, a list of
+    /// directories in the format of `PATH`.
+    pub fn from_env() -> Includes {
+        let path = match env::var_os("RTFORTH_PATH") {
+            Some(p) => env::split_paths(&p).collect(),
+            None => Vec::new(),
+        };
+        Includes::new(path)
+    }
+
+    /// Directories searched for files to include.
+    pub fn path(&self) -> &[PathBuf] {
+        &self.path
+    }
+
+    pub fn set_path(&mut self, path: Vec<PathBuf>) {
+        self.path = path;
+    }
+
+    /// Canonical paths of files included.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Record file with canonical path `file` as included. Returns false
+    /// if it has been recorded before.
+    pub fn record(&mut self, file: PathBuf) -> bool {
+        if self.files.contains(&file) {
+            false
+        } else {
+            self.files.push(file);
+            true
+        }
+    }
 }
 
 pub trait HasLoader: Core + Output {
@@ -18,6 +79,7 @@ pub trait HasLoader: Core + Output {
         self.add_primitive(".source-path", HasLoader::dot_source_path);
         self.add_primitive("load-line", HasLoader::p_load_line);
         self.add_primitive(".source-line", HasLoader::dot_source_line);
+        self.add_primitive("_open-include", HasLoader::p_open_include);
     }
 
     /// ( c-addr u file-id -- source-id )
@@ -38,24 +100,17 @@ pub trait HasLoader: Core + Output {
         let (caddr, u, id )= self.s_stack().pop3();
         if id > 0 && id - 1 < self.files().len() as isize {
             match self.files_mut()[id as usize - 1].take() {
-                Some(file) => {
-                    let position = self.sources().iter().position(|x| {
-                        x.is_none()
-                    });
-                    let reader = BufReader::new(file);
-                    let path = String::from(unsafe{ self.data_space().str_from_raw_parts(caddr as _, u as _)} );
-                    match position {
-                        Some(sid) => {
-                            self.sources_mut()[sid] = Some(Source {reader, path});
-                            self.s_stack().push(sid as isize + 1);
+                Some(handle) => {
+                    let file = match handle.into_file() {
+                        Ok(file) => file,
+                        Err(_) => {
+                            self.abort_with(FILE_IO_EXCEPTION);
+                            return;
                         }
-                        None => {
-                            let sid = self.sources().len() as isize;
-                            self.s_stack().push(sid as isize + 1);
-                            self.sources_mut().push(Some(Source {reader, path}));
-                            self.lines_mut().push(Some(String::with_capacity(128)));
-                        }
-                    }
+                    };
+                    let path = String::from(unsafe{ self.data_space().str_from_raw_parts(caddr as _, u as _)} );
+                    let sid = self.add_source(file, path);
+                    self.s_stack().push(sid as isize);
                 }
                 None => {
                     self.abort_with(INVALID_NUMERIC_ARGUMENT);
@@ -124,38 +179,132 @@ pub trait HasLoader: Core + Output {
         }
     }}
 
+    /// Add an input source reading from `file` at `path`. Returns the
+    /// source id.
+    fn add_source(&mut self, file: File, path: String) -> usize {
+        let reader = BufReader::new(file);
+        let gcode = if gcode::is_gcode_path(&path) {
+            Some(Translator::new())
+        } else {
+            None
+        };
+        let source = Source {
+            reader,
+            path,
+            gcode,
+        };
+        let position = self.sources().iter().position(|x| x.is_none());
+        match position {
+            Some(sid) => {
+                self.sources_mut()[sid] = Some(source);
+                sid + 1
+            }
+            None => {
+                self.sources_mut().push(Some(source));
+                self.lines_mut().push(Some(String::with_capacity(128)));
+                self.sources().len()
+            }
+        }
+    }
+
+    // ( c-addr u flag -- source-id | 0 )
+    //
+    // Find the file named c-addr u, see `resolve_include`, and record it as
+    // included. Open it as input source, unless flag is true and it has
+    // been included before, in which case 0 is returned.
+    primitive! {fn p_open_include(&mut self) {
+        let (caddr, u, once) = self.s_stack().pop3();
+        let (caddr, u) = (caddr as usize, u as usize);
+        if !(self.data_space().start() <= caddr && caddr + u <= self.data_space().limit()) {
+            self.abort_with(INVALID_MEMORY_ADDRESS);
+            return;
+        }
+        let name = String::from(unsafe{ self.data_space().str_from_raw_parts(caddr, u) });
+        match self.open_include(&name, once != 0) {
+            Ok(sid) => self.s_stack().push(sid as isize),
+            Err(e) => self.abort_with(e.code()),
+        }
+    }}
+
+    /// Path of the file to include for `name`.
+    ///
+    /// An absolute `name` is used as it is. A relative one is searched in
+    /// the directory of the file being loaded, in the current directory
+    /// and in the directories of the library path, in this order.
+    fn resolve_include(&mut self, name: &str) -> Option<PathBuf> {
+        let name = Path::new(name);
+        if name.is_absolute() {
+            return if name.is_file() {
+                Some(name.to_path_buf())
+            } else {
+                None
+            };
+        }
+        let mut dirs = Vec::new();
+        let id = self.source_id();
+        if id > 0 {
+            if let Some(Some(ref source)) = self.sources().get(id as usize - 1) {
+                if let Some(dir) = Path::new(&source.path).parent() {
+                    dirs.push(dir.to_path_buf());
+                }
+            }
+        }
+        dirs.push(PathBuf::new());
+        dirs.extend(self.includes().path().iter().cloned());
+        dirs.into_iter().map(|d| d.join(name)).find(|p| p.is_file())
+    }
+
+    /// Open the file to include for `name` as input source and record it
+    /// as included. If `once` is true and the file has been included
+    /// before, it is not opened and 0 is returned. Otherwise returns the
+    /// source id.
+    fn open_include(&mut self, name: &str, once: bool) -> Result<usize> {
+        let path = match self.resolve_include(name) {
+            Some(p) => p,
+            None => return Err(ForthError::from(NON_EXISTENT_FILE)),
+        };
+        let display = path.to_string_lossy().into_owned();
+        let canonical = fs::canonicalize(&path).map_err(|e| ForthError::io(&display, e))?;
+        if !self.includes().record(canonical) && once {
+            return Ok(0);
+        }
+        let file = File::open(&path).map_err(|e| ForthError::io(&display, e))?;
+        Ok(self.add_source(file, display))
+    }
+
     /// ( source-id -- count not-eof? )
     ///
     /// Load one line from source to input buffer.
     primitive! {fn p_load_line(&mut self) {
         let id = self.s_stack().pop() as usize;
         match self.load_line(id) {
-            Err(e) => self.abort_with(e),
+            Err(e) => self.abort_with(e.code()),
             Ok((len, not_eof)) => {
                 self.s_stack().push2(len as isize, if not_eof { -1 } else { 0 });
             }
         }
     }}
 
-    /// Load a line from file into input buffer.
+    /// Load a line from file into input buffer. A line of a G-code source
+    /// is translated into Forth, see `gcode::Translator`.
     ///
     /// Returns Ok((length, not-eof)) if successful.
-    fn load_line(&mut self, source_id: usize) -> Result<(usize, bool), isize> {
+    fn load_line(&mut self, source_id: usize) -> Result<(usize, bool)> {
         // Read line
         if !(source_id > 0 && source_id - 1 < self.sources().len()) {
-            return Err(INVALID_NUMERIC_ARGUMENT);
+            return Err(ForthError::from(INVALID_NUMERIC_ARGUMENT));
         }
         let mut source = match self.sources_mut()[source_id - 1].take() {
             Some(s) => s,
             None => {
-                return Err(INVALID_NUMERIC_ARGUMENT);
+                return Err(ForthError::from(INVALID_NUMERIC_ARGUMENT));
             }
         };
         let mut line = match self.lines_mut()[source_id - 1].take() {
             Some(line) => line,
             None => {
                 self.sources_mut()[source_id - 1] = Some(source);
-                return Err(INVALID_NUMERIC_ARGUMENT);
+                return Err(ForthError::from(INVALID_NUMERIC_ARGUMENT));
             }
         };
         line.clear();
@@ -174,7 +323,21 @@ pub trait HasLoader: Core + Output {
                     Ok((len, not_eof))
                 }
             }
-            Err(_) => Err(FILE_IO_EXCEPTION),
+            Err(e) => Err(ForthError::io(&source.path, e)),
+        };
+        let result = match (result, source.gcode.as_mut()) {
+            (Ok((_, not_eof)), Some(translator)) => match translator.translate(&line) {
+                Ok(forth) => {
+                    line = forth;
+                    Ok((line.len(), not_eof))
+                }
+                Err(e) => {
+                    // Nothing of an invalid block is evaluated.
+                    line.clear();
+                    Err(e)
+                }
+            },
+            (result, _) => result,
         };
         self.lines_mut()[source_id - 1] = Some(line);
         self.sources_mut()[source_id - 1] = Some(source);
@@ -190,10 +353,10 @@ pub trait HasLoader: Core + Output {
         self.evaluate_input();
     }
 
-    fn load(&mut self, path_name: &str) -> Result<(), isize> {
+    fn load(&mut self, path_name: &str) -> Result {
         let mut reader = match File::open(&path_name) {
-            Err(_) => {
-                return Err(FILE_IO_EXCEPTION);
+            Err(e) => {
+                return Err(ForthError::io(path_name, e));
             }
             Ok(file) => BufReader::new(file),
         };
@@ -211,13 +374,13 @@ pub trait HasLoader: Core + Output {
                         self.set_input_buffer(input_buffer);
                         self.evaluate_input();
                         if let Some(e) = self.last_error() {
-                            return Err(e);
+                            return Err(ForthError::from(e));
                         }
                     }
                 }
-                Err(_) => {
+                Err(e) => {
                     self.set_input_buffer(input_buffer);
-                    return Err(FILE_IO_EXCEPTION);
+                    return Err(ForthError::io(path_name, e));
                 }
             };
         }
@@ -235,3 +398,74 @@ pub trait HasLoader: Core + Output {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::Core;
+    use exception::NON_EXISTENT_FILE;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use vm::VMBuilder;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rtforth-{}-{}", name, ::std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_include_relative_to_including_file() {
+        let dir = temp_dir("relative");
+        fs::write(dir.join("main.fs"), b"1 s\" lib/a.fs\" included\n").unwrap();
+        fs::write(dir.join("lib").join("a.fs"), b"2 require b.fs\n").unwrap();
+        fs::write(dir.join("lib").join("b.fs"), b"3\n").unwrap();
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(&format!("s\" {}\" included", dir.join("main.fs").display()));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [1, 2, 3]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_path() {
+        let dir = temp_dir("path");
+        fs::write(dir.join("lib").join("c.fs"), b"4\n").unwrap();
+        let vm = &mut VMBuilder::new().include_path(vec![dir.join("lib")]).build();
+        assert_eq!(vm.includes().path(), [dir.join("lib")]);
+        vm.set_source("s\" c.fs\" included  require c.fs");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [4]);
+        assert_eq!(vm.includes().files().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_require_once() {
+        let dir = temp_dir("once");
+        fs::write(dir.join("lib").join("d.fs"), b"5\n").unwrap();
+        let vm = &mut VMBuilder::new().include_path(vec![dir.clone()]).build();
+        vm.set_source(&format!(
+            "require lib/d.fs  require lib/../lib/d.fs  s\" {}\" required",
+            dir.join("lib").join("d.fs").display()
+        ));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [5]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_missing_file() {
+        let vm = &mut VMBuilder::new().include_path(Vec::new()).build();
+        vm.set_source("s\" rtforth-no-such-file.fs\" true _open-include");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(NON_EXISTENT_FILE));
+        vm.set_source("s\" rtforth-no-such-file.fs\" false _open-include");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(NON_EXISTENT_FILE));
+        assert!(vm.includes().files().is_empty());
+    }
+}
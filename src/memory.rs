@@ -1,5 +1,8 @@
 extern crate libc;
 
+use fixed::DEFAULT_FRACTION_BITS;
+use float::DEFAULT_PRECISION;
+use memory_allocation::Heap;
 use std::alloc::{alloc_zeroed, dealloc, Layout};
 use std::marker;
 use std::mem;
@@ -8,6 +11,10 @@ use std::slice;
 pub struct SystemVariables {
     null: isize,
     base: isize,
+    precision: isize,
+    float_status: isize,
+    strict_float: isize,
+    fraction_bits: isize,
     compile_comma: isize,
     compile_integer: isize,
     compile_var: isize,
@@ -16,33 +23,68 @@ pub struct SystemVariables {
     compile_float: isize,
 }
 
+/// Forth address of the data space with feature `offset-addresses`.
+///
+/// It is not zero so that an instruction pointer of zero is still outside
+/// the data space.
+#[cfg(feature = "offset-addresses")]
+pub const OFFSET_BASE: usize = 0x10000;
+
 impl SystemVariables {
+    // Forth address of field at host address `field`.
+    #[cfg(not(feature = "offset-addresses"))]
+    fn address(&self, field: usize) -> usize {
+        field
+    }
+
+    // Forth address of field at host address `field`.
+    #[cfg(feature = "offset-addresses")]
+    fn address(&self, field: usize) -> usize {
+        field - self as *const _ as usize + OFFSET_BASE
+    }
+
     pub fn base_addr(&self) -> usize {
-        &self.base as *const _ as usize
+        self.address(&self.base as *const _ as usize)
+    }
+
+    pub fn precision_addr(&self) -> usize {
+        self.address(&self.precision as *const _ as usize)
+    }
+
+    pub fn float_status_addr(&self) -> usize {
+        self.address(&self.float_status as *const _ as usize)
+    }
+
+    pub fn strict_float_addr(&self) -> usize {
+        self.address(&self.strict_float as *const _ as usize)
+    }
+
+    pub fn fraction_bits_addr(&self) -> usize {
+        self.address(&self.fraction_bits as *const _ as usize)
     }
 
     pub fn compile_comma_vector(&self) -> usize {
-        &self.compile_comma as *const _ as usize
+        self.address(&self.compile_comma as *const _ as usize)
     }
 
     pub fn compile_integer_vector(&self) -> usize {
-        &self.compile_integer as *const _ as usize
+        self.address(&self.compile_integer as *const _ as usize)
     }
 
     pub fn compile_var_vector(&self) -> usize {
-        &self.compile_var as *const _ as usize
+        self.address(&self.compile_var as *const _ as usize)
     }
 
     pub fn compile_const_vector(&self) -> usize {
-        &self.compile_const as *const _ as usize
+        self.address(&self.compile_const as *const _ as usize)
     }
 
     pub fn compile_fconst_vector(&self) -> usize {
-        &self.compile_fconst as *const _ as usize
+        self.address(&self.compile_fconst as *const _ as usize)
     }
 
     pub fn compile_float_vector(&self) -> usize {
-        &self.compile_float as *const _ as usize
+        self.address(&self.compile_float as *const _ as usize)
     }
 }
 
@@ -52,6 +94,10 @@ pub struct DataSpace {
     layout: Layout,
     cap: usize,
     len: usize,
+    heap: Heap,
+    safe: bool,
+    // Write-protected ranges in safe mode, sorted by address.
+    read_only: Vec<(usize, usize)>,
     marker: marker::PhantomData<SystemVariables>,
 }
 
@@ -66,6 +112,12 @@ impl DataSpace {
     }
 
     pub fn with_capacity(cap: usize) -> Self {
+        Self::with_heap(cap, 0)
+    }
+
+    /// Data space of `cap` bytes, the top `heap_size` bytes of which are
+    /// the heap for ALLOCATE, FREE and RESIZE.
+    pub fn with_heap(cap: usize, heap_size: usize) -> Self {
         let ptr: *mut u8;
         let layout = Layout::from_size_align(cap, page_size::get()).unwrap();
         unsafe {
@@ -79,10 +131,21 @@ impl DataSpace {
             layout,
             cap,
             len: mem::size_of::<SystemVariables>(),
+            heap: Heap::empty(),
+            safe: false,
+            read_only: Vec::new(),
             marker: marker::PhantomData,
         };
         result.system_variables_mut().null = 0;
         result.system_variables_mut().base = 10;
+        result.system_variables_mut().precision = DEFAULT_PRECISION;
+        result.system_variables_mut().fraction_bits = DEFAULT_FRACTION_BITS;
+        let heap_size = heap_size.min(cap - result.len) & !15;
+        if heap_size > 0 {
+            let offset = (cap - heap_size) & !15;
+            let start = result.start() + offset;
+            result.heap = Heap::new(ptr as usize + offset, start, heap_size);
+        }
         result
     }
 
@@ -95,6 +158,75 @@ impl DataSpace {
     pub fn system_variables_mut(&mut self) -> &mut SystemVariables {
         unsafe { &mut *(self.inner.offset(0) as *mut SystemVariables) }
     }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    // Safe mode
+
+    pub fn is_safe(&self) -> bool {
+        self.safe
+    }
+
+    /// Turn safe mode on or off.
+    ///
+    /// Turning it on write-protects the execution vectors in
+    /// `SystemVariables`. Turning it off removes all protection.
+    pub fn set_safe(&mut self, on: bool) {
+        self.safe = on;
+        self.read_only.clear();
+        if on {
+            let vectors = self.system_variables().compile_comma_vector();
+            let end = self.start() + mem::size_of::<SystemVariables>();
+            self.protect(vectors, end);
+        }
+    }
+
+    /// Write-protect addresses from `start` up to but not including `end`
+    /// in safe mode.
+    pub fn protect(&mut self, start: usize, end: usize) {
+        if !self.safe || start >= end {
+            return;
+        }
+        match self.read_only.last_mut() {
+            Some(last) if last.1 == start => {
+                last.1 = end;
+                return;
+            }
+            _ => {}
+        }
+        self.read_only.push((start, end));
+        if self.read_only.len() > 1 && self.read_only[self.read_only.len() - 2].0 > start {
+            self.read_only.sort();
+        }
+    }
+
+    /// Is any of the `len` bytes at `addr` write-protected?
+    pub fn is_read_only(&self, addr: usize, len: usize) -> bool {
+        match self.read_only.binary_search_by(|r| r.0.cmp(&addr)) {
+            Ok(_) => true,
+            Err(i) => {
+                (i > 0 && self.read_only[i - 1].1 > addr)
+                    || (i < self.read_only.len() && self.read_only[i].0 < addr.saturating_add(len))
+            }
+        }
+    }
+
+    // Protected ranges above here are gone, for example after a marker.
+    fn unprotect_above(&mut self, here: usize) {
+        while let Some(&(_, end)) = self.read_only.last() {
+            if end > here {
+                self.read_only.pop();
+            } else {
+                break;
+            }
+        }
+    }
 }
 
 impl Drop for DataSpace {
@@ -105,6 +237,7 @@ impl Drop for DataSpace {
     }
 }
 
+#[cfg(not(feature = "offset-addresses"))]
 impl Memory for DataSpace {
     fn start(&self) -> usize {
         unsafe { self.inner.offset(0) as usize }
@@ -122,13 +255,63 @@ impl Memory for DataSpace {
         unsafe { self.inner.offset(self.len as isize) as usize }
     }
 
+    fn dictionary_limit(&self) -> usize {
+        if self.heap.size() > 0 {
+            self.heap.start()
+        } else {
+            self.limit()
+        }
+    }
+
     fn set_here(&mut self, pos: usize) {
         // here is allowed to be 1 place after the last memory address.
-        if self.start() <= pos && pos <= self.limit() {
+        if self.start() <= pos && pos <= self.dictionary_limit() {
             let len = pos as isize - self.start() as isize;
             self.len = len as usize;
+            self.unprotect_above(pos);
+        }
+    }
+}
+
+/// Forth addresses are offsets from `OFFSET_BASE`, the same in every VM
+/// and every run.
+#[cfg(feature = "offset-addresses")]
+impl Memory for DataSpace {
+    fn start(&self) -> usize {
+        OFFSET_BASE
+    }
+
+    fn limit(&self) -> usize {
+        OFFSET_BASE + self.cap
+    }
+
+    fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    fn here(&self) -> usize {
+        OFFSET_BASE + self.len
+    }
+
+    fn dictionary_limit(&self) -> usize {
+        if self.heap.size() > 0 {
+            self.heap.start()
+        } else {
+            self.limit()
         }
     }
+
+    fn set_here(&mut self, pos: usize) {
+        // here is allowed to be 1 place after the last memory address.
+        if self.start() <= pos && pos <= self.dictionary_limit() {
+            self.len = pos - OFFSET_BASE;
+            self.unprotect_above(pos);
+        }
+    }
+
+    fn to_host(&self, addr: usize) -> usize {
+        (self.inner as usize).wrapping_add(addr.wrapping_sub(OFFSET_BASE))
+    }
 }
 
 pub trait Memory {
@@ -148,26 +331,40 @@ pub trait Memory {
         self.start() <= pos && pos < self.limit()
     }
 
+    /// Upper limit of the dictionary, below the heap if there is one
+    fn dictionary_limit(&self) -> usize {
+        self.limit()
+    }
+
     /// Next free space
     fn here(&self) -> usize;
 
     /// Set next free space.
     fn set_here(&mut self, pos: usize);
 
+    /// Host address of Forth address `addr`.
+    fn to_host(&self, addr: usize) -> usize {
+        addr
+    }
+
     unsafe fn get_u8(&self, addr: usize) -> u8 {
-        *(addr as *mut u8)
+        *(self.to_host(addr) as *mut u8)
     }
 
     unsafe fn get_usize(&self, addr: usize) -> usize {
-        *(addr as *mut usize)
+        *(self.to_host(addr) as *mut usize)
     }
 
     unsafe fn get_isize(&self, addr: usize) -> isize {
-        *(addr as *mut isize)
+        *(self.to_host(addr) as *mut isize)
     }
 
     unsafe fn get_f64(&self, addr: usize) -> f64 {
-        *(addr as *mut f64)
+        *(self.to_host(addr) as *mut f64)
+    }
+
+    unsafe fn get_f32(&self, addr: usize) -> f32 {
+        *(self.to_host(addr) as *mut f32)
     }
 
     unsafe fn get_str(&self, addr: usize) -> &str {
@@ -177,27 +374,30 @@ pub trait Memory {
     }
 
     unsafe fn str_from_raw_parts(&self, addr: usize, len: usize) -> &str {
-        mem::transmute(slice::from_raw_parts::<u8>(addr as *const u8, len))
+        mem::transmute(slice::from_raw_parts::<u8>(
+            self.to_host(addr) as *const u8,
+            len,
+        ))
     }
 
     unsafe fn buffer_from_raw_parts(&self, addr: usize, len: usize) -> &[u8] {
-        slice::from_raw_parts::<u8>(addr as *const u8, len)
+        slice::from_raw_parts::<u8>(self.to_host(addr) as *const u8, len)
     }
 
     unsafe fn buffer_from_raw_parts_mut(&mut self, addr: usize, len: usize) -> &mut [u8] {
-        slice::from_raw_parts_mut::<u8>(addr as *mut u8, len)
+        slice::from_raw_parts_mut::<u8>(self.to_host(addr) as *mut u8, len)
     }
 
     // Basic operations
 
     unsafe fn put_u8(&mut self, v: u8, pos: usize) {
-        *(pos as *mut u8) = v;
+        *(self.to_host(pos) as *mut u8) = v;
     }
 
     #[allow(dead_code)]
     fn compile_u8(&mut self, v: u8) {
         let here = self.here();
-        if here < self.limit() {
+        if here < self.dictionary_limit() {
             unsafe {
                 self.put_u8(v, here);
             }
@@ -208,12 +408,12 @@ pub trait Memory {
     }
 
     unsafe fn put_usize(&mut self, v: usize, pos: usize) {
-        *(pos as *mut usize) = v;
+        *(self.to_host(pos) as *mut usize) = v;
     }
 
     fn compile_usize(&mut self, v: usize) {
         let here = self.here();
-        if here + mem::size_of::<usize>() <= self.limit() {
+        if here + mem::size_of::<usize>() <= self.dictionary_limit() {
             unsafe {
                 self.put_usize(v, here);
             }
@@ -236,12 +436,12 @@ pub trait Memory {
     }
 
     unsafe fn put_isize(&mut self, v: isize, pos: usize) {
-        *(pos as *mut isize) = v;
+        *(self.to_host(pos) as *mut isize) = v;
     }
 
     fn compile_isize(&mut self, v: isize) {
         let here = self.here();
-        if here + mem::size_of::<isize>() <= self.limit() {
+        if here + mem::size_of::<isize>() <= self.dictionary_limit() {
             unsafe {
                 self.put_isize(v, here);
             }
@@ -251,12 +451,16 @@ pub trait Memory {
         }
     }
     unsafe fn put_f64(&mut self, v: f64, pos: usize) {
-        *(pos as *mut f64) = v;
+        *(self.to_host(pos) as *mut f64) = v;
+    }
+
+    unsafe fn put_f32(&mut self, v: f32, pos: usize) {
+        *(self.to_host(pos) as *mut f32) = v;
     }
 
     fn compile_f64(&mut self, v: f64) {
         let here = self.here();
-        if here + mem::size_of::<f64>() <= self.limit() {
+        if here + mem::size_of::<f64>() <= self.dictionary_limit() {
             unsafe {
                 self.put_f64(v, here);
             }
@@ -273,12 +477,12 @@ pub trait Memory {
         if pos + len + mem::size_of::<usize>() <= self.limit() {
             let mut p = pos;
             unsafe {
-                *(p as *mut u8) = len as u8;
+                self.put_u8(len as u8, p);
             }
             for byte in &bytes[0..len] {
                 p += 1;
                 unsafe {
-                    *(p as *mut u8) = *byte;
+                    self.put_u8(*byte, p);
                 }
             }
         } else {
@@ -290,7 +494,7 @@ pub trait Memory {
         let bytes = s.as_bytes();
         let here = self.here();
         let len = bytes.len();
-        if here + len + mem::size_of::<usize>() <= self.limit() {
+        if here + len + mem::size_of::<usize>() <= self.dictionary_limit() {
             self.compile_usize(len);
             for byte in bytes {
                 self.compile_u8(*byte);
@@ -325,6 +529,18 @@ pub trait Memory {
         self.set_here(Self::aligned_f64(here));
     }
 
+    /// First single-float-aligned address greater than or equal to `pos`.
+    fn aligned_f32(pos: usize) -> usize {
+        let align = mem::align_of::<f32>();
+        (pos + align - 1) & align.wrapping_neg()
+    }
+
+    /// If the data-space pointer is not single-float-aligned, reserve enough space to align it.
+    fn align_f32(&mut self) {
+        let here = self.here();
+        self.set_here(Self::aligned_f32(here));
+    }
+
     /// First address aligned to 16-byte boundary greater than or equal to `pos`.
     fn aligned_16(pos: usize) -> usize {
         let align = 16;
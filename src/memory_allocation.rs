@@ -0,0 +1,652 @@
+//! Memory-Allocation word set
+//!
+//! The heap is carved from the top of the data space, see
+//! `DataSpace::with_heap`. It is managed by a two-level segregated fit
+//! (TLSF) allocator, so that ALLOCATE, FREE and RESIZE take bounded time,
+//! independent of the number of blocks in the heap.
+//!
+//! Each block has a header of two cells, the address of the previous
+//! block in the heap and the size of the block with two flags in its low
+//! bits. A free block also keeps the links of its free list in the first
+//! two cells of its payload. The heap ends with a used block of size 0.
+
+use core::Core;
+use exception::{ALLOCATE_EXCEPTION, FREE_EXCEPTION, RESIZE_EXCEPTION};
+use memory::Memory;
+use output::Output;
+use std::mem;
+use std::ptr;
+
+const CELL: usize = mem::size_of::<usize>();
+const HEADER: usize = 2 * CELL;
+const MIN_BLOCK: usize = 2 * CELL;
+const SL_LOG2: usize = 4;
+const SL_COUNT: usize = 1 << SL_LOG2;
+const FL_COUNT: usize = mem::size_of::<usize>() * 8;
+// Blocks smaller than SMALL_BLOCK are kept in first-level list 0.
+const SMALL_BLOCK: usize = SL_COUNT * CELL;
+const FREE_BIT: usize = 1;
+const PREV_FREE_BIT: usize = 2;
+const FLAGS: usize = FREE_BIT | PREV_FREE_BIT;
+
+fn log2(x: usize) -> usize {
+    FL_COUNT - 1 - x.leading_zeros() as usize
+}
+
+// First and second level indices of a block of `size` bytes.
+fn mapping(size: usize) -> (usize, usize) {
+    if size < SMALL_BLOCK {
+        (0, size / CELL)
+    } else {
+        let f = log2(size);
+        let sl = (size >> (f - SL_LOG2)) ^ SL_COUNT;
+        (f - log2(SMALL_BLOCK) + 1, sl)
+    }
+}
+
+/// Usage of the heap, see `Heap::usage`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapUsage {
+    /// Size of the heap in bytes, including block headers.
+    pub total: usize,
+    /// Bytes allocated to used blocks.
+    pub used: usize,
+    /// Number of used blocks.
+    pub used_blocks: usize,
+    /// Bytes in free blocks.
+    pub free: usize,
+    /// Number of free blocks.
+    pub free_blocks: usize,
+    /// Size of the largest free block.
+    pub largest_free: usize,
+}
+
+/// TLSF heap in the data space
+pub struct Heap {
+    // Host address of the first block.
+    host: usize,
+    // Forth address of the first block.
+    start: usize,
+    size: usize,
+    fl_bitmap: usize,
+    sl_bitmap: [u32; FL_COUNT],
+    free_lists: [[usize; SL_COUNT]; FL_COUNT],
+}
+
+impl Heap {
+    /// A heap without space.
+    pub fn empty() -> Heap {
+        Heap {
+            host: 0,
+            start: 0,
+            size: 0,
+            fl_bitmap: 0,
+            sl_bitmap: [0; FL_COUNT],
+            free_lists: [[0; SL_COUNT]; FL_COUNT],
+        }
+    }
+
+    /// A heap of `size` bytes at host address `host`, which is Forth address
+    /// `start`. Both addresses should be cell aligned and `start` not zero.
+    pub fn new(host: usize, start: usize, size: usize) -> Heap {
+        let mut heap = Heap::empty();
+        let size = size & !(CELL - 1);
+        if size >= 2 * HEADER + MIN_BLOCK {
+            heap.host = host;
+            heap.start = start;
+            heap.size = size;
+            let sentinel = heap.sentinel();
+            unsafe {
+                heap.set(start, 0);
+                heap.set(start + CELL, (size - 2 * HEADER) | FREE_BIT);
+                heap.set(sentinel, start);
+                heap.set(sentinel + CELL, PREV_FREE_BIT);
+            }
+            heap.insert(start);
+        }
+        heap
+    }
+
+    /// Forth address of the heap.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Size of the heap in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Index of the free blocks as cells, the bitmaps and the heads of the
+    /// free lists. Together with the bytes of the heap, it is the state of
+    /// the heap, saved in dictionary images.
+    pub fn index(&self) -> Vec<usize> {
+        let mut cells = Vec::with_capacity(1 + FL_COUNT + FL_COUNT * SL_COUNT);
+        cells.push(self.fl_bitmap);
+        cells.extend(self.sl_bitmap.iter().map(|&b| b as usize));
+        for list in self.free_lists.iter() {
+            cells.extend_from_slice(list);
+        }
+        cells
+    }
+
+    /// Could `cells` be an index of this heap, given by `index`?
+    pub fn is_index(&self, cells: &[usize]) -> bool {
+        let end = self.start + self.size;
+        cells.len() == 1 + FL_COUNT + FL_COUNT * SL_COUNT
+            && cells[1..1 + FL_COUNT]
+                .iter()
+                .all(|&b| b <= u32::MAX as usize)
+            && cells[1 + FL_COUNT..]
+                .iter()
+                .all(|&b| b == 0 || (self.start <= b && b < end))
+    }
+
+    /// Restore the index given by `index`. Returns false and leaves the
+    /// heap as it is if `cells` is not an index of this heap.
+    pub fn set_index(&mut self, cells: &[usize]) -> bool {
+        if !self.is_index(cells) {
+            return false;
+        }
+        self.fl_bitmap = cells[0];
+        for (b, &c) in self.sl_bitmap.iter_mut().zip(cells[1..].iter()) {
+            *b = c as u32;
+        }
+        for (i, list) in self.free_lists.iter_mut().enumerate() {
+            let first = 1 + FL_COUNT + i * SL_COUNT;
+            list.copy_from_slice(&cells[first..first + SL_COUNT]);
+        }
+        true
+    }
+
+    // Used block of size 0 at the end of the heap.
+    fn sentinel(&self) -> usize {
+        self.start + self.size - HEADER
+    }
+
+    unsafe fn get(&self, addr: usize) -> usize {
+        *((self.host + (addr - self.start)) as *const usize)
+    }
+
+    unsafe fn set(&mut self, addr: usize, v: usize) {
+        *((self.host + (addr - self.start)) as *mut usize) = v;
+    }
+
+    fn block_size(&self, b: usize) -> usize {
+        unsafe { self.get(b + CELL) & !FLAGS }
+    }
+
+    fn flags(&self, b: usize) -> usize {
+        unsafe { self.get(b + CELL) & FLAGS }
+    }
+
+    fn set_size(&mut self, b: usize, size: usize, flags: usize) {
+        unsafe { self.set(b + CELL, size | flags) }
+    }
+
+    fn set_flags(&mut self, b: usize, flags: usize) {
+        let size = self.block_size(b);
+        self.set_size(b, size, flags);
+    }
+
+    fn prev_phys(&self, b: usize) -> usize {
+        unsafe { self.get(b) }
+    }
+
+    fn set_prev_phys(&mut self, b: usize, prev: usize) {
+        unsafe { self.set(b, prev) }
+    }
+
+    fn next_phys(&self, b: usize) -> usize {
+        b + HEADER + self.block_size(b)
+    }
+
+    fn is_free(&self, b: usize) -> bool {
+        self.flags(b) & FREE_BIT != 0
+    }
+
+    // Put free block `b` into its free list.
+    fn insert(&mut self, b: usize) {
+        let (fl, sl) = mapping(self.block_size(b));
+        let head = self.free_lists[fl][sl];
+        unsafe {
+            self.set(b + HEADER, head);
+            self.set(b + HEADER + CELL, 0);
+            if head != 0 {
+                self.set(head + HEADER + CELL, b);
+            }
+        }
+        self.free_lists[fl][sl] = b;
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmap[fl] |= 1 << sl;
+    }
+
+    // Take free block `b` out of its free list.
+    fn remove(&mut self, b: usize) {
+        let (fl, sl) = mapping(self.block_size(b));
+        let (next, prev) = unsafe { (self.get(b + HEADER), self.get(b + HEADER + CELL)) };
+        unsafe {
+            if next != 0 {
+                self.set(next + HEADER + CELL, prev);
+            }
+            if prev != 0 {
+                self.set(prev + HEADER, next);
+            }
+        }
+        if self.free_lists[fl][sl] == b {
+            self.free_lists[fl][sl] = next;
+            if next == 0 {
+                self.sl_bitmap[fl] &= !(1 << sl);
+                if self.sl_bitmap[fl] == 0 {
+                    self.fl_bitmap &= !(1 << fl);
+                }
+            }
+        }
+    }
+
+    // First block of the first non-empty free list at or above (fl, sl).
+    fn search(&self, fl: usize, sl: usize) -> Option<usize> {
+        let mut fl = fl;
+        let mut sl_map = self.sl_bitmap[fl] & (!0u32 << sl);
+        if sl_map == 0 {
+            let fl_map = if fl + 1 < FL_COUNT {
+                self.fl_bitmap & (!0usize << (fl + 1))
+            } else {
+                0
+            };
+            if fl_map == 0 {
+                return None;
+            }
+            fl = fl_map.trailing_zeros() as usize;
+            sl_map = self.sl_bitmap[fl];
+        }
+        Some(self.free_lists[fl][sl_map.trailing_zeros() as usize])
+    }
+
+    // Free block of at least `size` bytes.
+    fn find_suitable(&self, size: usize) -> Option<usize> {
+        // Round up to the next list, all blocks of which are large enough.
+        let rounded = if size >= SMALL_BLOCK {
+            size + (1 << (log2(size) - SL_LOG2)) - 1
+        } else {
+            size
+        };
+        let (fl, sl) = mapping(rounded);
+        if fl < FL_COUNT {
+            if let Some(b) = self.search(fl, sl) {
+                return Some(b);
+            }
+        }
+        // Blocks in the list of `size` may be too small, but the first one
+        // is worth a look before giving up.
+        let (fl, sl) = mapping(size);
+        let b = self.free_lists[fl][sl];
+        if b != 0 && self.block_size(b) >= size {
+            Some(b)
+        } else {
+            None
+        }
+    }
+
+    // Mark block `b` free, merge it with its free neighbours and put it
+    // into a free list.
+    fn release(&mut self, b: usize) {
+        let mut b = b;
+        let mut size = self.block_size(b);
+        if self.flags(b) & PREV_FREE_BIT != 0 {
+            // Keep the stale header of `b` free, so that freeing it again
+            // is detected.
+            self.set_flags(b, FREE_BIT);
+            let prev = self.prev_phys(b);
+            self.remove(prev);
+            size += self.block_size(prev) + HEADER;
+            b = prev;
+        }
+        let next = b + HEADER + size;
+        if self.is_free(next) {
+            self.remove(next);
+            size += self.block_size(next) + HEADER;
+        }
+        self.set_size(b, size, FREE_BIT);
+        let next = b + HEADER + size;
+        self.set_prev_phys(next, b);
+        let flags = self.flags(next) | PREV_FREE_BIT;
+        self.set_flags(next, flags);
+        self.insert(b);
+    }
+
+    // Shrink used block `b` to `size` bytes if the rest is large enough
+    // for a block.
+    fn split(&mut self, b: usize, size: usize) {
+        let cur = self.block_size(b);
+        if cur >= size + HEADER + MIN_BLOCK {
+            let rest = b + HEADER + size;
+            self.set_size(rest, cur - size - HEADER, 0);
+            self.set_prev_phys(rest, b);
+            let flags = self.flags(b);
+            self.set_size(b, size, flags);
+            let next = self.next_phys(rest);
+            self.set_prev_phys(next, rest);
+            self.release(rest);
+        }
+    }
+
+    // Address of the used block with payload `p`, if valid.
+    fn used_block(&self, p: usize) -> Option<usize> {
+        if self.size == 0
+            || p < self.start + HEADER
+            || p >= self.sentinel()
+            || (p - self.start) & (CELL - 1) != 0
+        {
+            return None;
+        }
+        let b = p - HEADER;
+        if self.is_free(b) {
+            return None;
+        }
+        let next = b + HEADER + self.block_size(b);
+        if next > self.sentinel() || self.prev_phys(next) != b {
+            return None;
+        }
+        Some(b)
+    }
+
+    fn adjust(n: usize) -> usize {
+        let n = (n + CELL - 1) & !(CELL - 1);
+        if n < MIN_BLOCK {
+            MIN_BLOCK
+        } else {
+            n
+        }
+    }
+
+    /// Allocate `n` bytes. Returns the Forth address of the space.
+    pub fn allocate(&mut self, n: usize) -> Option<usize> {
+        if n > self.size {
+            return None;
+        }
+        let size = Heap::adjust(n);
+        let b = self.find_suitable(size)?;
+        self.remove(b);
+        let flags = self.flags(b) & !FREE_BIT;
+        self.set_flags(b, flags);
+        let next = self.next_phys(b);
+        let flags = self.flags(next) & !PREV_FREE_BIT;
+        self.set_flags(next, flags);
+        self.split(b, size);
+        Some(b + HEADER)
+    }
+
+    /// Free the space at `p` given by `allocate` or `resize`.
+    ///
+    /// Returns false if `p` is not such a space.
+    pub fn free(&mut self, p: usize) -> bool {
+        match self.used_block(p) {
+            Some(b) => {
+                self.release(b);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Change the size of the space at `p` to `n` bytes, moving it if
+    /// necessary. Returns the new address of the space.
+    ///
+    /// The space at `p` is not changed if it cannot be resized.
+    pub fn resize(&mut self, p: usize, n: usize) -> Option<usize> {
+        let b = self.used_block(p)?;
+        if n > self.size {
+            return None;
+        }
+        let size = Heap::adjust(n);
+        let cur = self.block_size(b);
+        if size <= cur {
+            self.split(b, size);
+            return Some(p);
+        }
+        let next = self.next_phys(b);
+        if self.is_free(next) && cur + HEADER + self.block_size(next) >= size {
+            self.remove(next);
+            let total = cur + HEADER + self.block_size(next);
+            let flags = self.flags(b);
+            self.set_size(b, total, flags);
+            let after = self.next_phys(b);
+            self.set_prev_phys(after, b);
+            let flags = self.flags(after) & !PREV_FREE_BIT;
+            self.set_flags(after, flags);
+            self.split(b, size);
+            return Some(p);
+        }
+        let q = self.allocate(n)?;
+        unsafe {
+            ptr::copy_nonoverlapping(
+                (self.host + (p - self.start)) as *const u8,
+                (self.host + (q - self.start)) as *mut u8,
+                cur,
+            );
+        }
+        self.release(b);
+        Some(q)
+    }
+
+    /// Walk through the heap and sum up its blocks.
+    pub fn usage(&self) -> HeapUsage {
+        let mut usage = HeapUsage {
+            total: self.size,
+            ..HeapUsage::default()
+        };
+        if self.size == 0 {
+            return usage;
+        }
+        let sentinel = self.sentinel();
+        let mut b = self.start;
+        while b < sentinel {
+            let size = self.block_size(b);
+            if self.is_free(b) {
+                usage.free += size;
+                usage.free_blocks += 1;
+                if size > usage.largest_free {
+                    usage.largest_free = size;
+                }
+            } else {
+                usage.used += size;
+                usage.used_blocks += 1;
+            }
+            b = self.next_phys(b);
+        }
+        usage
+    }
+}
+
+pub trait MemoryAllocation: Core + Output {
+    /// Add memory-allocation primitives.
+    fn add_memory_allocation(&mut self) {
+        self.add_primitive("allocate", MemoryAllocation::allocate);
+        self.add_primitive("free", MemoryAllocation::free);
+        self.add_primitive("resize", MemoryAllocation::resize);
+        self.add_primitive(".memory", MemoryAllocation::dot_memory);
+    }
+
+    // Run-time: ( u -- a-addr ior )
+    //
+    // Allocate u bytes in the heap.
+    primitive! {fn allocate(&mut self) {
+        let u = self.s_stack().pop();
+        let result = if u < 0 {
+            None
+        } else {
+            self.data_space().heap_mut().allocate(u as usize)
+        };
+        match result {
+            Some(a) => self.s_stack().push2(a as isize, 0),
+            None => self.s_stack().push2(0, ALLOCATE_EXCEPTION),
+        }
+    }}
+
+    // Run-time: ( a-addr -- ior )
+    //
+    // Free the space at a-addr given by ALLOCATE or RESIZE.
+    primitive! {fn free(&mut self) {
+        let a = self.s_stack().pop();
+        if self.data_space().heap_mut().free(a as usize) {
+            self.s_stack().push(0);
+        } else {
+            self.s_stack().push(FREE_EXCEPTION);
+        }
+    }}
+
+    // Run-time: ( a-addr1 u -- a-addr2 ior )
+    //
+    // Change the size of the space at a-addr1 to u bytes. a-addr1 is
+    // returned unchanged if the space cannot be resized.
+    primitive! {fn resize(&mut self) {
+        let (a, u) = self.s_stack().pop2();
+        let result = if u < 0 {
+            None
+        } else {
+            self.data_space().heap_mut().resize(a as usize, u as usize)
+        };
+        match result {
+            Some(a2) => self.s_stack().push2(a2 as isize, 0),
+            None => self.s_stack().push2(a, RESIZE_EXCEPTION),
+        }
+    }}
+
+    // Run-time: ( -- )
+    //
+    // Display usage of the dictionary and the heap.
+    primitive! {fn dot_memory(&mut self) {
+        let start = self.data_space().start();
+        let used = self.data_space().here() - start;
+        let total = self.data_space().dictionary_limit() - start;
+        let heap = self.data_space().heap().usage();
+        let text = format!(
+            "dictionary {}/{} heap {}/{} used {} free {} largest {}",
+            used, total, heap.used, heap.total, heap.used_blocks, heap.free_blocks,
+            heap.largest_free
+        );
+        self.push_output(&text);
+    }}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heap, HEADER};
+    use core::Core;
+    use exception::{ALLOCATE_EXCEPTION, FREE_EXCEPTION};
+    use memory::Memory;
+    use vm::VMBuilder;
+
+    #[test]
+    fn test_allocate_free() {
+        let vm = &mut VMBuilder::new().heap_size(4096).build();
+        vm.set_source("100 allocate  200 allocate");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        let (a, ior1, b, ior2) = {
+            let s = vm.s_stack().as_slice();
+            (s[0], s[1], s[2], s[3])
+        };
+        assert_eq!((ior1, ior2), (0, 0));
+        assert!(a != b);
+        assert!(vm.data_space().heap().start() <= a as usize);
+        vm.s_stack().reset();
+        vm.s_stack().push(a);
+        vm.set_source("free");
+        vm.evaluate_input();
+        assert_eq!(vm.s_stack().pop(), 0);
+        vm.set_source("1000000 allocate  -1 allocate  0 free  here free");
+        vm.evaluate_input();
+        assert_eq!(
+            vm.s_stack().as_slice(),
+            [
+                0,
+                ALLOCATE_EXCEPTION,
+                0,
+                ALLOCATE_EXCEPTION,
+                FREE_EXCEPTION,
+                FREE_EXCEPTION
+            ]
+        );
+        vm.s_stack().reset();
+        // Free and merge all blocks, the whole heap is available again.
+        vm.s_stack().push(b);
+        vm.set_source("free");
+        vm.evaluate_input();
+        assert_eq!(vm.s_stack().pop(), 0);
+        let usage = vm.data_space().heap().usage();
+        assert_eq!(usage.used_blocks, 0);
+        assert_eq!(usage.free_blocks, 1);
+        assert_eq!(usage.largest_free, 4096 - 2 * HEADER);
+    }
+
+    #[test]
+    fn test_resize() {
+        let vm = &mut VMBuilder::new().heap_size(4096).build();
+        vm.set_source(
+            "variable p  16 allocate drop p !  12345 p @ !
+             16 allocate drop constant q
+             p @ 1000 resize  swap p !
+             p @ @  p @ q <>  p @ 8 resize nip  p @ 100000 resize",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        let p = vm.s_stack().get(4);
+        assert_eq!(
+            vm.s_stack().as_slice(),
+            [0, 12345, -1, 0, p, super::RESIZE_EXCEPTION]
+        );
+    }
+
+    #[test]
+    fn test_heap_blocks() {
+        let mut buf = vec![0usize; 512];
+        let host = buf.as_mut_ptr() as usize;
+        let heap = &mut Heap::new(host, 0x1000, 512 * super::CELL);
+        let mut blocks = Vec::new();
+        while let Some(p) = heap.allocate(40) {
+            blocks.push(p);
+        }
+        assert!(blocks.len() > 10);
+        for (i, &p) in blocks.iter().enumerate() {
+            if i % 2 == 0 {
+                assert!(heap.free(p));
+            }
+        }
+        assert!(!heap.free(blocks[0]));
+        assert_eq!(heap.usage().used_blocks, blocks.len() / 2);
+        for (i, &p) in blocks.iter().enumerate() {
+            if i % 2 == 1 {
+                assert!(heap.free(p));
+            }
+        }
+        let usage = heap.usage();
+        assert_eq!(usage.free_blocks, 1);
+        assert_eq!(heap.allocate(usage.largest_free), Some(0x1000 + HEADER));
+    }
+
+    #[test]
+    fn test_heap_beyond_dictionary() {
+        for &heap_size in &[0, 4096] {
+            let vm = &mut VMBuilder::new()
+                .data_capacity(0x10000)
+                .heap_size(heap_size)
+                .build();
+            let start = vm.data_space().start();
+            assert_eq!(vm.data_space().dictionary_limit() - start, 0x10000);
+            assert_eq!(vm.data_space().heap().size(), heap_size);
+        }
+    }
+
+    #[test]
+    fn test_dot_memory() {
+        let vm = &mut VMBuilder::new().heap_size(1024).build();
+        vm.set_output_buffer(String::new());
+        vm.set_source(".memory");
+        vm.evaluate_input();
+        let out = vm.output_buffer().take().unwrap();
+        assert!(out.starts_with("dictionary "));
+        assert!(out.contains(" heap 0/1024 used 0 free 1 "));
+    }
+}
@@ -0,0 +1,416 @@
+//! Motion profiles
+//!
+//! A motion profile plans a point-to-point move of a distance, starting and
+//! ending at rest, within limits of velocity, acceleration and, for S-curve
+//! profiles, jerk. A trapezoidal profile accelerates at the acceleration
+//! limit, cruises and decelerates. An S-curve profile has continuous
+//! acceleration made of seven phases of constant jerk. If the distance is
+//! too short to reach a limit, the profile stays below it.
+//!
+//! A profile is an array of `PROFILE_FLOATS` floats in data space, so that
+//! a servo task can sample it with `PROFILE@` without planning it again.
+//! Units are those of the arguments, for example meters and seconds.
+//!
+//! ```
+//! use rtforth::core::Core;
+//! use rtforth::vm::VMBuilder;
+//!
+//! let mut vm = VMBuilder::new().build();
+//! vm.set_source("
+//!     create p  /profile allot
+//!     0.1e 0.05e 0.5e p trapezoid-profile
+//!     p profile-duration
+//! ");
+//! vm.evaluate_input();
+//! assert!((vm.f_stack().pop() - 2.1).abs() < 1e-12);
+//! ```
+
+use exception::INVALID_NUMERIC_ARGUMENT;
+use float::float_status_of;
+use linalg::LinearAlgebra;
+use std::mem;
+
+/// Phases of constant jerk of a profile.
+pub const SEGMENTS: usize = 7;
+
+/// Floats of a profile in data space, see `Profile::to_floats`.
+pub const PROFILE_FLOATS: usize = 2 + 5 * SEGMENTS;
+
+/// A phase of constant jerk starting at time `start` with `position`,
+/// `velocity` and `acceleration`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Segment {
+    pub start: f64,
+    pub position: f64,
+    pub velocity: f64,
+    pub acceleration: f64,
+    pub jerk: f64,
+}
+
+/// A move of `distance` taking time `duration`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub duration: f64,
+    pub distance: f64,
+    pub segments: [Segment; SEGMENTS],
+}
+
+// Are all limits positive and finite?
+fn valid_limits(limits: &[f64]) -> bool {
+    limits.iter().all(|&x| x > 0.0 && x.is_finite())
+}
+
+impl Profile {
+    /// Trapezoidal profile of `distance` with velocity limit `vmax` and
+    /// acceleration limit `amax`. `None` if `distance` is not finite or a
+    /// limit is not positive.
+    pub fn trapezoidal(distance: f64, vmax: f64, amax: f64) -> Option<Profile> {
+        if !distance.is_finite() || !valid_limits(&[vmax, amax]) {
+            return None;
+        }
+        let d = distance.abs();
+        let (v, tv) = if d >= vmax * vmax / amax {
+            (vmax, (d - vmax * vmax / amax) / vmax)
+        } else {
+            ((d * amax).sqrt(), 0.0)
+        };
+        let ta = v / amax;
+        Some(Profile::from_phases(
+            distance,
+            &[(ta, amax, 0.0), (tv, 0.0, 0.0), (ta, -amax, 0.0)],
+        ))
+    }
+
+    /// S-curve profile of `distance` with velocity limit `vmax`,
+    /// acceleration limit `amax` and jerk limit `jmax`. `None` if
+    /// `distance` is not finite or a limit is not positive.
+    pub fn s_curve(distance: f64, vmax: f64, amax: f64, jmax: f64) -> Option<Profile> {
+        if !distance.is_finite() || !valid_limits(&[vmax, amax, jmax]) {
+            return None;
+        }
+        let d = distance.abs();
+        // Jerk time and constant acceleration time of reaching velocity v.
+        let phases = |v: f64| {
+            if v * jmax >= amax * amax {
+                (amax / jmax, v / amax - amax / jmax)
+            } else {
+                ((v / jmax).sqrt(), 0.0)
+            }
+        };
+        let (tj, tca) = phases(vmax);
+        // Distance of accelerating to vmax and decelerating to rest.
+        let da = vmax * (2.0 * tj + tca);
+        let (tj, tca, tv) = if d >= da {
+            (tj, tca, (d - da) / vmax)
+        } else {
+            // Peak velocity v with v * v / amax + v * amax / jmax == d.
+            let b = amax * amax / jmax;
+            let v = (-b + (b * b + 4.0 * d * amax).sqrt()) / 2.0;
+            if v >= b {
+                (amax / jmax, v / amax - amax / jmax, 0.0)
+            } else {
+                ((d / (2.0 * jmax)).cbrt(), 0.0, 0.0)
+            }
+        };
+        let ap = jmax * tj;
+        Some(Profile::from_phases(
+            distance,
+            &[
+                (tj, 0.0, jmax),
+                (tca, ap, 0.0),
+                (tj, ap, -jmax),
+                (tv, 0.0, 0.0),
+                (tj, 0.0, -jmax),
+                (tca, -ap, 0.0),
+                (tj, -ap, jmax),
+            ],
+        ))
+    }
+
+    // Profile of phases of duration, initial acceleration and jerk of a
+    // move of |distance| in the direction of `distance`.
+    fn from_phases(distance: f64, phases: &[(f64, f64, f64)]) -> Profile {
+        let sign = if distance < 0.0 { -1.0 } else { 1.0 };
+        let mut segments = [Segment::default(); SEGMENTS];
+        let (mut t, mut p, mut v) = (0.0, 0.0, 0.0);
+        for (segment, &(dt, a, j)) in segments.iter_mut().zip(phases.iter()) {
+            *segment = Segment {
+                start: t,
+                position: sign * p,
+                velocity: sign * v,
+                acceleration: sign * a,
+                jerk: sign * j,
+            };
+            p += v * dt + a * dt * dt / 2.0 + j * dt * dt * dt / 6.0;
+            v += a * dt + j * dt * dt / 2.0;
+            t += dt;
+        }
+        for segment in segments.iter_mut().skip(phases.len()) {
+            *segment = Segment {
+                start: t,
+                position: distance,
+                ..Segment::default()
+            };
+        }
+        Profile {
+            duration: t,
+            distance,
+            segments,
+        }
+    }
+
+    /// Position, velocity and acceleration at time `t`. The move is at
+    /// rest before time zero and after `duration`. All three are NaN if `t`
+    /// is not finite.
+    pub fn sample(&self, t: f64) -> (f64, f64, f64) {
+        if !t.is_finite() {
+            return (f64::NAN, f64::NAN, f64::NAN);
+        } else if t >= self.duration {
+            return (self.distance, 0.0, 0.0);
+        } else if t < 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let s = match self.segments.iter().rev().find(|s| s.start <= t) {
+            Some(s) => s,
+            None => &self.segments[0],
+        };
+        let dt = t - s.start;
+        (
+            s.position
+                + s.velocity * dt
+                + s.acceleration * dt * dt / 2.0
+                + s.jerk * dt * dt * dt / 6.0,
+            s.velocity + s.acceleration * dt + s.jerk * dt * dt / 2.0,
+            s.acceleration + s.jerk * dt,
+        )
+    }
+
+    /// The profile as floats, `duration`, `distance` and then `start`,
+    /// `position`, `velocity`, `acceleration` and `jerk` of every segment.
+    pub fn to_floats(&self) -> [f64; PROFILE_FLOATS] {
+        let mut floats = [0.0; PROFILE_FLOATS];
+        floats[0] = self.duration;
+        floats[1] = self.distance;
+        for (s, x) in self.segments.iter().zip(floats[2..].chunks_mut(5)) {
+            x.copy_from_slice(&[s.start, s.position, s.velocity, s.acceleration, s.jerk]);
+        }
+        floats
+    }
+
+    /// Profile of `floats` written by `to_floats`.
+    pub fn from_floats(floats: &[f64; PROFILE_FLOATS]) -> Profile {
+        let mut segments = [Segment::default(); SEGMENTS];
+        for (s, x) in segments.iter_mut().zip(floats[2..].chunks(5)) {
+            *s = Segment {
+                start: x[0],
+                position: x[1],
+                velocity: x[2],
+                acceleration: x[3],
+                jerk: x[4],
+            };
+        }
+        Profile {
+            duration: floats[0],
+            distance: floats[1],
+            segments,
+        }
+    }
+}
+
+pub trait Motion: LinearAlgebra {
+    fn add_motion(&mut self) {
+        self.add_primitive("/profile", Motion::slash_profile);
+        self.add_primitive("trapezoid-profile", Motion::trapezoid_profile);
+        self.add_primitive("s-curve-profile", Motion::s_curve_profile);
+        self.add_primitive("profile@", Motion::profile_fetch);
+        self.add_primitive("profile-duration", Motion::profile_duration);
+    }
+
+    /// Store `profile` at `addr`, or abort with `INVALID_NUMERIC_ARGUMENT`
+    /// if it is `None`.
+    fn store_profile(&mut self, addr: isize, profile: Option<Profile>) {
+        let addr = match self.float_array(addr, PROFILE_FLOATS as isize, true) {
+            Some(addr) => addr,
+            None => return,
+        };
+        match profile {
+            Some(profile) => self.store_floats(addr, &profile.to_floats()),
+            None => self.abort_with(INVALID_NUMERIC_ARGUMENT),
+        }
+    }
+
+    /// Profile at `addr`. Aborts and returns `None` if it is not in data
+    /// space.
+    fn profile_at(&mut self, addr: isize) -> Option<Profile> {
+        let addr = self.float_array(addr, PROFILE_FLOATS as isize, false)?;
+        let mut floats = [0.0; PROFILE_FLOATS];
+        for (i, x) in floats.iter_mut().enumerate() {
+            *x = self.float_at(addr, i);
+        }
+        Some(Profile::from_floats(&floats))
+    }
+
+    // /PROFILE ( -- u )
+    // u is the size in address units of a profile.
+    primitive! {fn slash_profile(&mut self) {
+        self.s_stack().push((PROFILE_FLOATS * mem::size_of::<f64>()) as isize);
+    }}
+
+    // TRAPEZOID-PROFILE ( f-addr -- ) ( F: r-distance r-vmax r-amax -- )
+    // Plan a trapezoidal profile into the profile at f-addr.
+    primitive! {fn trapezoid_profile(&mut self) {
+        let addr = self.s_stack().pop();
+        let (d, v, a) = self.f_stack().pop3();
+        self.store_profile(addr, Profile::trapezoidal(d, v, a));
+    }}
+
+    // S-CURVE-PROFILE ( f-addr -- ) ( F: r-distance r-vmax r-amax r-jmax -- )
+    // Plan an S-curve profile into the profile at f-addr.
+    primitive! {fn s_curve_profile(&mut self) {
+        let addr = self.s_stack().pop();
+        let (v, a, j) = self.f_stack().pop3();
+        let d = self.f_stack().pop();
+        self.store_profile(addr, Profile::s_curve(d, v, a, j));
+    }}
+
+    // PROFILE@ ( f-addr -- ) ( F: r-t -- r-position r-velocity r-acceleration )
+    // Sample the profile at f-addr at time r-t. A NaN or infinite r-t
+    // gives NaNs and raises the float status.
+    primitive! {fn profile_fetch(&mut self) {
+        let addr = self.s_stack().pop();
+        let t = self.f_stack().pop();
+        if let Some(profile) = self.profile_at(addr) {
+            let (p, v, a) = profile.sample(t);
+            let status = float_status_of(p, false) | float_status_of(v, false) | float_status_of(a, false);
+            if !self.raise_float_status(status) {
+                self.f_stack().push3(p, v, a);
+            }
+        }
+    }}
+
+    // PROFILE-DURATION ( f-addr -- ) ( F: -- r )
+    // r is the duration of the profile at f-addr.
+    primitive! {fn profile_duration(&mut self) {
+        let addr = self.s_stack().pop();
+        if let Some(addr) = self.float_array(addr, PROFILE_FLOATS as isize, false) {
+            let duration = self.float_at(addr, 0);
+            self.f_stack().push(duration);
+        }
+    }}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Core;
+    use exception::{
+        FLOATING_POINT_INVALID_ARGUMENT, INVALID_MEMORY_ADDRESS, INVALID_NUMERIC_ARGUMENT,
+    };
+    use float::{Float, FLOAT_INVALID};
+    use vm::VMBuilder;
+
+    // Check that `profile` moves `distance` continuously within the limits.
+    fn check(profile: &Profile, distance: f64, vmax: f64, amax: f64) {
+        let eps = 1e-9;
+        let steps = 10_000;
+        let dt = profile.duration / steps as f64;
+        let mut last = profile.sample(0.0);
+        assert_eq!(last.0, 0.0);
+        for i in 1..=steps {
+            let (p, v, a) = profile.sample(i as f64 * dt);
+            assert!(v.abs() <= vmax + eps && a.abs() <= amax + eps);
+            assert!((p - last.0).abs() <= vmax * dt + eps);
+            assert!(v * distance >= -eps);
+            last = (p, v, a);
+        }
+        let end = profile.sample(profile.duration - 1e-12);
+        assert!((end.0 - distance).abs() < 1e-9 && end.1.abs() < 1e-9);
+        assert_eq!(profile.sample(profile.duration + 1.0), (distance, 0.0, 0.0));
+        assert_eq!(profile.sample(-1.0), (0.0, 0.0, 0.0));
+        assert!(profile.sample(f64::NAN).0.is_nan());
+        assert!(profile.sample(f64::INFINITY).1.is_nan());
+    }
+
+    #[test]
+    fn test_trapezoidal() {
+        let p = Profile::trapezoidal(0.1, 0.05, 0.5).unwrap();
+        assert!((p.duration - 2.1).abs() < 1e-12);
+        assert!((p.sample(1.0).1 - 0.05).abs() < 1e-12);
+        check(&p, 0.1, 0.05, 0.5);
+        // Too short to reach vmax.
+        let p = Profile::trapezoidal(-0.002, 0.05, 0.5).unwrap();
+        assert!((p.duration - 2.0 * (0.002f64 / 0.5).sqrt()).abs() < 1e-12);
+        check(&p, -0.002, 0.05, 0.5);
+        let p = Profile::trapezoidal(0.0, 0.05, 0.5).unwrap();
+        assert_eq!(p.duration, 0.0);
+        assert!(Profile::trapezoidal(0.1, 0.0, 0.5).is_none());
+        assert!(Profile::trapezoidal(0.1, 0.05, -0.5).is_none());
+    }
+
+    #[test]
+    fn test_s_curve() {
+        // Reaching vmax and amax, reaching only vmax, reaching only amax and
+        // reaching neither.
+        for &(d, v, a, j) in &[
+            (0.1, 0.05, 0.5, 10.0),
+            (0.1, 0.05, 0.5, 5.0),
+            (-0.01, 0.05, 0.5, 100.0),
+            (0.001, 0.05, 0.5, 10.0),
+        ] {
+            let p = Profile::s_curve(d, v, a, j).unwrap();
+            check(&p, d, v, a);
+            let steps = 1000;
+            let dt = p.duration / steps as f64;
+            for i in 1..steps {
+                let a0 = p.sample((i - 1) as f64 * dt).2;
+                let a1 = p.sample(i as f64 * dt).2;
+                assert!((a1 - a0).abs() <= j * dt + 1e-9);
+            }
+        }
+        let p = Profile::s_curve(0.1, 0.05, 0.5, 10.0).unwrap();
+        assert!((p.duration - (0.1 / 0.05 + 0.05 / 0.5 + 0.5 / 10.0)).abs() < 1e-12);
+        assert!(Profile::s_curve(0.1, 0.05, 0.5, (-1f64).sqrt()).is_none());
+        assert_eq!(Profile::from_floats(&p.to_floats()), p);
+    }
+
+    #[test]
+    fn test_profile_words() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(
+            "create p /profile allot
+             0.1e 0.05e 0.5e 10e p s-curve-profile
+             p profile-duration  1e p profile@",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.f_stack().len(), 4);
+        assert!((vm.f_stack().get(0) - 2.15).abs() < 1e-12);
+        assert!((vm.f_stack().get(1) - 0.05 * (1.0 - 0.075)).abs() < 1e-12);
+        assert!((vm.f_stack().get(2) - 0.05).abs() < 1e-12);
+        assert_eq!(vm.f_stack().get(3), 0.0);
+
+        vm.set_source("0.1e 0e 0.5e p trapezoid-profile");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_NUMERIC_ARGUMENT));
+        vm.set_error(None);
+        vm.set_source("0e -1 profile@");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_MEMORY_ADDRESS));
+        vm.set_error(None);
+        vm.f_stack().reset();
+        vm.f_stack().push(f64::NAN);
+        vm.set_source("p profile@");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.f_stack().len(), 3);
+        assert!(vm.f_stack().get(0).is_nan());
+        assert_eq!(vm.float_status(), FLOAT_INVALID);
+        vm.f_stack().reset();
+        vm.set_strict_float(true);
+        vm.f_stack().push(f64::INFINITY);
+        vm.set_source("p profile@");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(FLOATING_POINT_INVALID_ARGUMENT));
+        assert_eq!(vm.f_stack().len(), 0);
+    }
+}
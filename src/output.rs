@@ -1,7 +1,116 @@
 use core::Core;
-use exception::UNSUPPORTED_BASE_FOR_INTEGER_CONVERSION;
+use exception::{PARSED_STRING_OVERFLOW, UNSUPPORTED_BASE_FOR_INTEGER_CONVERSION};
 use memory::Memory;
 use std::fmt::Write;
+use std::io::{self, Write as IoWrite};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+/// Size of each of the transient buffers of S"
+const TRANSIENT_BUFFER_SIZE: usize = 256;
+
+/// Destination of the output of a VM
+///
+/// Words such as EMIT, TYPE and . append to the output buffer of the VM.
+/// FLUSH-OUTPUT writes the buffer as a line to the output sink and
+/// FLUSH-TO-ERR to the error sink, see `Core::output_sink` and
+/// `Core::error_sink`. If the output buffer has been taken, the words
+/// write to the output sink directly. Errors in writing are left to the
+/// sink.
+pub trait Sink: Send {
+    /// Write `line`, which has no line terminator.
+    fn write_line(&mut self, line: &str);
+
+    /// Write `text`, a part of a line. By default it is written as a line.
+    fn write(&mut self, text: &str) {
+        self.write_line(text)
+    }
+}
+
+/// Sink writing lines to standard output, the default output sink.
+pub struct Stdout;
+
+impl Sink for Stdout {
+    fn write_line(&mut self, line: &str) {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+
+    fn write(&mut self, text: &str) {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let _ = write!(out, "{}", text);
+        let _ = out.flush();
+    }
+}
+
+/// Sink writing lines to standard error, the default error sink.
+pub struct Stderr;
+
+impl Sink for Stderr {
+    fn write_line(&mut self, line: &str) {
+        let _ = writeln!(io::stderr(), "{}", line);
+    }
+
+    fn write(&mut self, text: &str) {
+        let _ = write!(io::stderr(), "{}", text);
+    }
+}
+
+/// Sink keeping lines in memory, for example to check the output of a
+/// VM in tests. Clones share the lines.
+///
+/// ```
+/// use rtforth::core::Core;
+/// use rtforth::output::Capture;
+/// use rtforth::vm::VMBuilder;
+///
+/// let capture = Capture::new();
+/// let mut vm = VMBuilder::new()
+///     .output_sink(Box::new(capture.clone()))
+///     .build();
+/// vm.set_source("1 2 + . flush-output");
+/// vm.evaluate_input();
+/// assert_eq!(capture.lines(), ["3 "]);
+/// ```
+#[derive(Clone, Default)]
+pub struct Capture {
+    lines: Arc<Mutex<Vec<String>>>,
+}
+
+impl Capture {
+    pub fn new() -> Capture {
+        Capture::default()
+    }
+
+    /// Lines written so far.
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().expect("capture").clone()
+    }
+
+    /// Remove and return the lines written so far.
+    pub fn take(&self) -> Vec<String> {
+        mem::take(&mut *self.lines.lock().expect("capture"))
+    }
+}
+
+impl Sink for Capture {
+    fn write_line(&mut self, line: &str) {
+        self.lines.lock().expect("capture").push(line.to_string());
+    }
+}
+
+/// Sink calling a function with each line, for example to send it to a
+/// log, a socket or a GUI widget.
+pub struct Callback<F: FnMut(&str) + Send>(pub F);
+
+impl<F: FnMut(&str) + Send> Sink for Callback<F> {
+    fn write_line(&mut self, line: &str) {
+        (self.0)(line)
+    }
+}
 
 /// Types that can output to console.
 pub trait Output: Core {
@@ -9,7 +118,7 @@ pub trait Output: Core {
     fn add_output(&mut self) {
         self.add_primitive("emit", Output::emit);
         self.add_primitive("type", Output::p_type);
-        self.add_immediate_and_compile_only("s\"", Output::s_quote);
+        self.add_immediate("s\"", Output::s_quote);
         self.add_immediate_and_compile_only(".\"", Output::dot_quote);
         self.add_immediate(".(", Output::dot_paren);
         self.add_primitive(".r", Output::dot_r);
@@ -17,30 +126,31 @@ pub trait Output: Core {
         self.add_primitive("flush-output", Output::flush_output);
         self.references().idx_s_quote = self.find("_s\"").expect("_s\" undefined");
         self.references().idx_type = self.find("type").expect("type undefined");
+        // Index of the last used transient buffer of S" and the buffers.
+        self.data_space().align();
+        let buffers = self.data_space().here();
+        self.data_space().compile_usize(0);
+        self.data_space().allot(2 * TRANSIENT_BUFFER_SIZE as isize);
+        self.references().s_quote_buffers = buffers;
     }
 
+    /// Append `text` to the output buffer, or write it to the output sink
+    /// if there is no output buffer.
     fn push_output(&mut self, text: &str) {
-        match self.output_buffer().take() {
-            Some(mut buffer) => {
-                buffer.push_str(text);
-                self.set_output_buffer(buffer);
-            }
-            None => {}
+        if let Some(buffer) = self.output_buffer().as_mut() {
+            buffer.push_str(text);
+            return;
         }
+        self.output_sink().write(text);
     }
 
     /// Run-time: ( x -- )
     ///
     /// Put x into output buffer.
     primitive! {fn emit(&mut self) {
-        let ch = self.s_stack().pop();
-        match self.output_buffer().take() {
-            Some(mut buffer) => {
-                buffer.push(ch as u8 as char);
-                self.set_output_buffer(buffer);
-            }
-            None => {}
-        }
+        let ch = self.s_stack().pop() as u8 as char;
+        let mut utf8 = [0; 4];
+        self.push_output(ch.encode_utf8(&mut utf8));
     }}
 
     /// Run-time: ( c-addr u -- )
@@ -57,10 +167,20 @@ pub trait Output: Core {
                 }
                 self.set_output_buffer(buffer);
             }
-            None => {}
+            None => {
+                let text = self.pop_str().map(str::to_string);
+                if let Some(text) = text {
+                    self.output_sink().write(&text);
+                }
+            }
         }
     }}
 
+    /// Interpretation: ( "ccc<quote>" -- c-addr u )
+    ///
+    /// Parse ccc delimited by " (double-quote). Store the resulting string in a transient
+    /// buffer. There are two such buffers, used alternately.
+    ///
     /// Compilation: ( "ccc<quote>" -- )
     ///
     /// Parse ccc delimited by " (double-quote). Append the run-time semantics given below to the
@@ -71,6 +191,10 @@ pub trait Output: Core {
     /// Return c-addr and u describing a string consisting of the characters ccc. A program
     /// shall not alter the returned string.
     primitive! {fn s_quote(&mut self) {
+        if !self.state().is_compiling {
+            self.interpret_s_quote();
+            return;
+        }
         let input_buffer = self.input_buffer().take().unwrap();
         {
             let source = &input_buffer[self.state().source_index+1..input_buffer.len()];
@@ -84,18 +208,50 @@ pub trait Output: Core {
                 None => source,
             };
             let cnt = s.len();
-            let idx = self.references().idx_s_quote;
-            let compilation_semantics = self.wordlist()[idx].compilation_semantics;
-            self.s_stack().push(idx as isize);
-            compilation_semantics(self);
-            self.data_space().compile_str(s);
-            self.data_space().align();
+            // Token of _s", length, string and padding.
+            if self.check_space(cnt + 3 * mem::size_of::<usize>()) {
+                let idx = self.references().idx_s_quote;
+                let compilation_semantics = self.wordlist()[idx].compilation_semantics;
+                self.s_stack().push(idx as isize);
+                compilation_semantics(self);
+                self.data_space().compile_str(s);
+                self.data_space().align();
+            }
             // ignore the space following S"
             self.state().source_index = self.state().source_index + 1 + cnt as usize + 1;
         }
         self.set_input_buffer(input_buffer);
     }}
 
+    /// Interpretation semantics of S".
+    ///
+    /// Abort with `PARSED_STRING_OVERFLOW` if the string does not fit into
+    /// a transient buffer.
+    fn interpret_s_quote(&mut self) {
+        // Skip the space following S".
+        self.state().source_index += 1;
+        self.s_stack().push('"' as isize);
+        self._parse();
+        let buffers = self.references().s_quote_buffers;
+        let i = unsafe { self.data_space().get_usize(buffers) } ^ 1;
+        let addr = buffers + mem::size_of::<usize>() + i * TRANSIENT_BUFFER_SIZE;
+        let token = self.last_token().take().expect("token");
+        let len = token.len();
+        if len > TRANSIENT_BUFFER_SIZE {
+            self.set_last_token(token);
+            self.abort_with(PARSED_STRING_OVERFLOW);
+            return;
+        }
+        unsafe {
+            self.data_space().put_usize(i, buffers);
+            self.data_space()
+                .buffer_from_raw_parts_mut(addr, len)
+                .copy_from_slice(&token.as_bytes()[..len]);
+        }
+        self.set_last_token(token);
+        self.s_stack().push2(addr as isize, len as isize);
+    }
+
     /// Compilation: ( "ccc<quote>" -- )
     ///
     /// Parse ccc delimited by " (double-quote). Append the run-time semantics given below to the
@@ -106,9 +262,11 @@ pub trait Output: Core {
     /// Display ccc.
     primitive! {fn dot_quote(&mut self) {
         self.s_quote();
-        let idx_type = self.references().idx_type;
-        self.s_stack().push(idx_type as isize);
-        self.compile_comma();
+        if self.last_error().is_none() {
+            let idx_type = self.references().idx_type;
+            self.s_stack().push(idx_type as isize);
+            self.compile_comma();
+        }
     }}
 
     /// Execution: ( "ccc&lt;paren&gt;" -- )
@@ -118,9 +276,7 @@ pub trait Output: Core {
         self.s_stack().push(')' as isize);
         self._parse();
         let last_token = self.last_token().take().unwrap();
-        if let Some(ref mut buffer) = *self.output_buffer() {
-            buffer.extend(last_token.chars());
-        }
+        self.push_output(&last_token);
         self.set_last_token(last_token);
     }}
 
@@ -130,38 +286,23 @@ pub trait Output: Core {
     primitive! {fn dot_r(&mut self) {
         let base_addr = self.data_space().system_variables().base_addr();
         let base = unsafe{ self.data_space().get_isize(base_addr) };
-        let mut valid_base = true;
         let (n1, n2) = self.s_stack().pop2();
-        if let Some(mut buf) = self.output_buffer().take() {
-            self.hold_buffer().clear();
-            match base {
-                2 => {
-                    write!(self.hold_buffer(), "{:b}", n1).unwrap();
-                }
-                8 => {
-                    write!(self.hold_buffer(), "{:o}", n1).unwrap();
-                }
-                10 => {
-                    write!(self.hold_buffer(), "{}", n1).unwrap();
-                }
-                16 => {
-                    write!(self.hold_buffer(), "{:X}", n1).unwrap();
-                }
-                _ => {
-                    valid_base = false;
-                }
+        let width = if n2 > 0 { n2 as usize } else { 0 };
+        let mut text = mem::take(self.hold_buffer());
+        text.clear();
+        match base {
+            2 => write!(text, "{:>1$b}", n1, width).unwrap(),
+            8 => write!(text, "{:>1$o}", n1, width).unwrap(),
+            10 => write!(text, "{:>1$}", n1, width).unwrap(),
+            16 => write!(text, "{:>1$X}", n1, width).unwrap(),
+            _ => {
+                *self.hold_buffer() = text;
+                self.abort_with(UNSUPPORTED_BASE_FOR_INTEGER_CONVERSION);
+                return;
             }
-            if valid_base {
-                for _ in 0..(n2 - self.hold_buffer().len() as isize) {
-                    buf.push(' ');
-                }
-                buf.push_str(self.hold_buffer());
-            }
-            self.set_output_buffer(buf);
-        }
-        if !valid_base {
-            self.abort_with(UNSUPPORTED_BASE_FOR_INTEGER_CONVERSION);
         }
+        self.push_output(&text);
+        *self.hold_buffer() = text;
     }}
 
     /// Run-time: ( n1 n2 -- ) ( F: r -- )
@@ -173,53 +314,37 @@ pub trait Output: Core {
     primitive! {fn fdot_r(&mut self) {
         let r = self.f_stack().pop();
         let (n1, n2) = self.s_stack().pop2();
-        if let Some(mut buf) = self.output_buffer().take() {
-            self.hold_buffer().clear();
-            match n2 {
-                0 => write!(self.hold_buffer(), "{:.0}", r).unwrap(),
-                1 => write!(self.hold_buffer(), "{:.1}", r).unwrap(),
-                2 => write!(self.hold_buffer(), "{:.2}", r).unwrap(),
-                3 => write!(self.hold_buffer(), "{:.3}", r).unwrap(),
-                4 => write!(self.hold_buffer(), "{:.4}", r).unwrap(),
-                5 => write!(self.hold_buffer(), "{:.5}", r).unwrap(),
-                6 => write!(self.hold_buffer(), "{:.6}", r).unwrap(),
-                7 => write!(self.hold_buffer(), "{:.7}", r).unwrap(),
-                8 => write!(self.hold_buffer(), "{:.8}", r).unwrap(),
-                9 => write!(self.hold_buffer(), "{:.9}", r).unwrap(),
-                10 => write!(self.hold_buffer(), "{:.10}", r).unwrap(),
-                11 => write!(self.hold_buffer(), "{:.11}", r).unwrap(),
-                12 => write!(self.hold_buffer(), "{:.12}", r).unwrap(),
-                13 => write!(self.hold_buffer(), "{:.13}", r).unwrap(),
-                14 => write!(self.hold_buffer(), "{:.14}", r).unwrap(),
-                15 => write!(self.hold_buffer(), "{:.15}", r).unwrap(),
-                16 => write!(self.hold_buffer(), "{:.16}", r).unwrap(),
-                _ => write!(self.hold_buffer(), "{:.17}", r).unwrap(),
-            }
-            for _ in 0..(n1 - self.hold_buffer().len() as isize) {
-                buf.push(' ');
-            }
-            buf.push_str(self.hold_buffer());
-            self.set_output_buffer(buf);
-        }
+        let width = if n1 > 0 { n1 as usize } else { 0 };
+        let precision = if n2 < 0 { 17 } else { n2.min(17) as usize };
+        let mut text = mem::take(self.hold_buffer());
+        text.clear();
+        write!(text, "{:>1$.2$}", r, width, precision).unwrap();
+        self.push_output(&text);
+        *self.hold_buffer() = text;
     }}
 
+    // Run-time: ( -- )
+    //
+    // Write the output buffer, if not empty, to the output sink and clear
+    // it.
     primitive! {fn flush_output(&mut self) {
-        match self.output_buffer().as_mut() {
-            Some(buf) => {
-                if buf.len() > 0 {
-                    println!("{}", buf);
-                    buf.clear();
-                }
+        if let Some(mut buf) = self.output_buffer().take() {
+            if !buf.is_empty() {
+                self.output_sink().write_line(&buf);
+                buf.clear();
             }
-            None => {}
+            self.set_output_buffer(buf);
         }
     }}
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{Callback, Capture};
     use core::Core;
-    use mock_vm::VM;
+    use mock_vm::{NewVM, VM};
+    use std::sync::mpsc;
+    use vm::VMBuilder;
 
     #[test]
     fn test_s_quote_and_type() {
@@ -240,4 +365,49 @@ mod tests {
         assert_eq!(vm.s_stack().as_slice(), []);
         assert_eq!(vm.output_buffer().clone().unwrap(), "*+");
     }
+
+    #[test]
+    fn test_sinks() {
+        let (output, error) = (Capture::new(), Capture::new());
+        let vm = &mut VMBuilder::new()
+            .output_sink(Box::new(output.clone()))
+            .error_sink(Box::new(error.clone()))
+            .build();
+        vm.set_source("flush-output  42 emit 7 . flush-output");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        vm.set_source(": f  .\" oops\" flush-to-err ;  f  flush-output");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(output.take(), ["*7 "]);
+        assert_eq!(output.lines(), Vec::<String>::new());
+        assert_eq!(error.lines(), ["oops"]);
+
+        let (tx, rx) = mpsc::channel();
+        *vm.output_sink() = Box::new(Callback(move |line: &str| {
+            tx.send(line.to_string()).unwrap();
+        }));
+        vm.set_source("1 2 .r flush-output");
+        vm.evaluate_input();
+        assert_eq!(rx.try_recv(), Ok(String::from(" 1")));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_output_without_buffer() {
+        let output = Capture::new();
+        let vm = &mut VMBuilder::new()
+            .output_sink(Box::new(output.clone()))
+            .build();
+        vm.output_buffer().take();
+        vm.set_source("42 emit");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(output.take(), ["*"]);
+        vm.set_source(": hi  s\" hi\" type ;  hi  7 3 .r  1.5e 5 2 f.r  .( done)");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(output.take(), ["hi", "  7", " 1.50", " done"]);
+        assert!(vm.output_buffer().is_none());
+    }
 }
@@ -0,0 +1,256 @@
+//! Resource limits for evaluating untrusted input
+//!
+//! A sandbox is installed for the current task with `Core::set_sandbox`.
+//! Violations abort with `SANDBOX_INSTRUCTION_LIMIT`, `SANDBOX_DATA_LIMIT`
+//! or `SANDBOX_DEFINITION`. Words outside of the allowlist are not found
+//! and so raise `UNDEFINED_WORD`. Their execution tokens, for example
+//! obtained by arithmetic, are rejected by `EXECUTE`, `COMPILE,`,
+//! `POSTPONE` and `>BODY` with `INVALID_EXECUTION_TOKEN`, also when these
+//! are called by allowed words.
+//!
+//! ```
+//! use rtforth::core::Core;
+//! use rtforth::exception::SANDBOX_INSTRUCTION_LIMIT;
+//! use rtforth::sandbox::Sandbox;
+//! use rtforth::vm::VMBuilder;
+//!
+//! let mut vm = VMBuilder::new().build();
+//! vm.set_source(": forever begin again ;");
+//! vm.evaluate_input();
+//! vm.set_sandbox(Some(Sandbox::new().max_instructions(10_000)));
+//! vm.set_source("forever");
+//! vm.evaluate_input();
+//! assert_eq!(vm.last_error(), Some(SANDBOX_INSTRUCTION_LIMIT));
+//! ```
+
+use std::collections::HashSet;
+
+/// Limits of a sandbox
+#[derive(Clone, Debug, Default)]
+pub struct Sandbox {
+    max_instructions: Option<u64>,
+    max_data_growth: Option<usize>,
+    allowlist: bool,
+    allowed_words: HashSet<String>,
+    allowed_wordlists: Vec<usize>,
+    definitions: bool,
+    // Set by Core::set_sandbox.
+    pub(crate) data_limit: usize,
+    pub(crate) first_word: usize,
+    pub(crate) allowed_xts: Vec<bool>,
+    // Counted during an evaluation.
+    pub(crate) executed: u64,
+    pub(crate) depth: usize,
+}
+
+impl Sandbox {
+    /// A sandbox without limits, in which definitions are allowed.
+    pub fn new() -> Sandbox {
+        Sandbox {
+            definitions: true,
+            ..Sandbox::default()
+        }
+    }
+
+    /// Maximum number of instructions executed by the inner interpreter in
+    /// one evaluation of the input buffer.
+    pub fn max_instructions(mut self, n: u64) -> Self {
+        self.max_instructions = Some(n);
+        self
+    }
+
+    /// Maximum growth of the data space in bytes, counted from `here` when
+    /// the sandbox is installed.
+    pub fn max_data_growth(mut self, bytes: usize) -> Self {
+        self.max_data_growth = Some(bytes);
+        self
+    }
+
+    /// Allow words `names` to be found. Once a word or word list is
+    /// allowed, other words defined before the sandbox is installed are
+    /// hidden.
+    pub fn allow_words(mut self, names: &[&str]) -> Self {
+        self.allowlist = true;
+        for name in names {
+            self.allowed_words.insert(name.to_lowercase());
+        }
+        self
+    }
+
+    /// Allow all words in word list `wid` to be found, see `allow_words`.
+    pub fn allow_wordlist(mut self, wid: usize) -> Self {
+        self.allowlist = true;
+        self.allowed_wordlists.push(wid);
+        self
+    }
+
+    /// Allow or disallow defining new words.
+    pub fn definitions(mut self, allowed: bool) -> Self {
+        self.definitions = allowed;
+        self
+    }
+
+    /// Are definitions allowed?
+    pub fn definitions_allowed(&self) -> bool {
+        self.definitions
+    }
+
+    /// May word `xt` named `name` in word list `wid` be found?
+    pub fn allows(&self, xt: usize, name: &str, wid: usize) -> bool {
+        !self.allowlist
+            || xt >= self.first_word
+            || self.allowed_wordlists.contains(&wid)
+            || self.allowed_words.contains(&name.to_ascii_lowercase())
+    }
+
+    /// May the word with execution token `xt` be executed or compiled?
+    ///
+    /// Only valid after the sandbox is installed with `Core::set_sandbox`.
+    pub fn allows_xt(&self, xt: usize) -> bool {
+        !self.allowlist || xt >= self.first_word || self.allowed_xts.get(xt) == Some(&true)
+    }
+
+    /// Count an instruction. Returns false if the limit is exceeded.
+    pub(crate) fn count(&mut self) -> bool {
+        self.executed += 1;
+        match self.max_instructions {
+            Some(max) => self.executed <= max,
+            None => true,
+        }
+    }
+
+    pub(crate) fn limits_instructions(&self) -> bool {
+        self.max_instructions.is_some()
+    }
+
+    pub(crate) fn set_base(&mut self, here: usize, words: usize) {
+        self.data_limit = match self.max_data_growth {
+            Some(n) => here.saturating_add(n),
+            None => !0,
+        };
+        self.first_word = words;
+        self.executed = 0;
+        self.depth = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sandbox;
+    use core::Core;
+    use exception::{
+        INVALID_EXECUTION_TOKEN, SANDBOX_DATA_LIMIT, SANDBOX_DEFINITION, SANDBOX_INSTRUCTION_LIMIT,
+        UNDEFINED_WORD,
+    };
+    use vm::VMBuilder;
+
+    #[test]
+    fn test_max_instructions() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(": forever begin again ;  : count 0 100 0 do 1+ loop ;");
+        vm.evaluate_input();
+        vm.set_sandbox(Some(Sandbox::new().max_instructions(1000)));
+        vm.set_source("forever");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(SANDBOX_INSTRUCTION_LIMIT));
+        vm.reset();
+        // The limit is per evaluation.
+        for _ in 0..10 {
+            vm.set_source("count");
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), None);
+            assert_eq!(vm.s_stack().pop(), 100);
+        }
+        vm.set_sandbox(None);
+        vm.set_source("count count count count count count count count count count");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+    }
+
+    #[test]
+    fn test_max_data_growth() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_sandbox(Some(Sandbox::new().max_data_growth(100)));
+        vm.set_source("200 allot");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(SANDBOX_DATA_LIMIT));
+        vm.reset();
+        vm.set_source("50 allot");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        vm.set_source("60 allot");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(SANDBOX_DATA_LIMIT));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_sandbox(Some(Sandbox::new().allow_words(&[":", ";", "DUP", "*"])));
+        vm.set_source(": sq dup * ;  3 sq");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().pop(), 9);
+        for source in &["3 drop", "s\" x\" r/o open-file", "' drop"] {
+            vm.reset();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), Some(UNDEFINED_WORD));
+        }
+    }
+
+    #[test]
+    fn test_allowlist_xt() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_source(": run-drop ['] drop execute ;");
+        vm.evaluate_input();
+        let drop = vm.find("drop").expect("drop");
+        vm.set_sandbox(Some(Sandbox::new().allow_words(&[
+            ":", ";", "dup", "execute", "compile,", ">body", "[", "]", "run-drop",
+        ])));
+        for source in &[
+            format!("3 {} execute", drop),
+            format!("{} >body", drop),
+            format!(": cc compile, ;  : foo 3 [ {} cc ] ;", drop),
+            // Allowed words cannot execute hidden words either.
+            "3 run-drop".to_string(),
+        ] {
+            vm.reset();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), Some(INVALID_EXECUTION_TOKEN), "{}", source);
+        }
+        let dup = vm.find("dup").expect("dup");
+        vm.reset();
+        vm.set_source(&format!(
+            "3 {} execute  : cc compile, ;  : 2dup [ {} dup cc cc ] ; 4 2dup",
+            dup, dup
+        ));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [3, 3, 4, 4, 4]);
+        vm.set_sandbox(None);
+        vm.s_stack().reset();
+        vm.set_source(&format!("3 4 {} execute", drop));
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().as_slice(), [3]);
+    }
+
+    #[test]
+    fn test_definitions() {
+        let vm = &mut VMBuilder::new().build();
+        vm.set_sandbox(Some(Sandbox::new().definitions(false)));
+        for source in &[": foo ;", "variable x", "create y", "3 constant z"] {
+            vm.reset();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), Some(SANDBOX_DEFINITION));
+        }
+        vm.reset();
+        vm.set_source("1 2 +");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().pop(), 3);
+    }
+}
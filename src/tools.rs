@@ -143,14 +143,13 @@ pub trait Tools: Output {
         }
     }}
 
-    /// Flush output buffer to standard error output. `flush-to-err ( -- )`
+    /// Flush output buffer to the error sink, standard error output by
+    /// default. `flush-to-err ( -- )`
     primitive! {fn flush_to_err(&mut self) {
-        match self.output_buffer().as_mut() {
-            Some(out) => {
-                eprintln!("{}", out);
-                out.clear();
-            }
-            None => {}
+        if let Some(mut out) = self.output_buffer().take() {
+            self.error_sink().write_line(&out);
+            out.clear();
+            self.set_output_buffer(out);
         }
     }}
 }
@@ -1,109 +1,521 @@
-use core::Core;
+//! Units word set
+//!
+//! Unit words convert a float in the unit to SI units, so that `10e mm` is
+//! `0.01e`. Angles are in radians. Every unit word `name` has a reverse
+//! `>name` converting from SI units for display, as in `0.01e >mm f.`.
+//!
+//! New units are defined with UNIT:, UNIT*: and UNIT/: in Forth,
+//!
+//! ```text
+//! unit: inch 0.0254 meter
+//! unit/: inch/rev inch rev
+//! unit: percent 0.01
+//! ```
+//!
+//! or with `Units::add_unit` in Rust.
+//!
+//! # Checked units
+//!
+//! With feature `checked-units`, every float on the floating-point stack
+//! carries a `Dimension`, or an unknown one. Unit words give floats their
+//! dimension, float literals are dimensionless, and floats fetched from
+//! memory are of unknown dimension. Float words check and propagate
+//! dimensions, so that adding a length to an angle aborts with
+//! `ARGUMENT_TYPE_MISMATCH`. A float of unknown dimension passes every
+//! check.
+//!
+//! Without the feature, `Tag` is the empty `Unchecked` and the checks
+//! compile to nothing.
+
+use core::{Core, WordType};
+use exception::{
+    ARGUMENT_TYPE_MISMATCH, INVALID_NUMERIC_ARGUMENT, UNDEFINED_WORD, UNEXPECTED_END_OF_FILE,
+};
+use float::{to_float, Float};
+use memory::{DataSpace, Memory};
 use std::f64::consts::PI;
+use std::mem;
+use std::ops::{Div, Mul};
 
 const RPM: f64 = 2.0 * PI / 60.0;
 
-pub trait Units: Core {
-    fn add_units(&mut self) {
-        self.add_primitive("meter", Units::from_meter);
-        self.add_primitive("mm", Units::from_mm);
-        self.add_primitive("um", Units::from_um);
+/// Exponents of the base dimensions length, mass, time, electric current,
+/// temperature and angle of a quantity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dimension(pub [i8; 6]);
+
+impl Dimension {
+    pub const NONE: Dimension = Dimension([0, 0, 0, 0, 0, 0]);
+    pub const LENGTH: Dimension = Dimension([1, 0, 0, 0, 0, 0]);
+    pub const MASS: Dimension = Dimension([0, 1, 0, 0, 0, 0]);
+    pub const TIME: Dimension = Dimension([0, 0, 1, 0, 0, 0]);
+    pub const CURRENT: Dimension = Dimension([0, 0, 0, 1, 0, 0]);
+    pub const TEMPERATURE: Dimension = Dimension([0, 0, 0, 0, 1, 0]);
+    pub const ANGLE: Dimension = Dimension([0, 0, 0, 0, 0, 1]);
+
+    /// Square root, `None` if an exponent is odd.
+    pub fn sqrt(self) -> Option<Dimension> {
+        let mut result = self;
+        for e in result.0.iter_mut() {
+            if *e % 2 != 0 {
+                return None;
+            }
+            *e /= 2;
+        }
+        Some(result)
+    }
+}
 
-        self.add_primitive("deg", Units::from_deg);
-        self.add_primitive("rad", Units::from_rad);
+impl Mul for Dimension {
+    type Output = Dimension;
+    fn mul(self, rhs: Dimension) -> Dimension {
+        let mut result = self;
+        for (e, r) in result.0.iter_mut().zip(rhs.0.iter()) {
+            *e = e.wrapping_add(*r);
+        }
+        result
+    }
+}
 
-        self.add_primitive("hr", Units::from_hour);
-        self.add_primitive("minute", Units::from_minute);
-        self.add_primitive("sec", Units::from_sec);
-        self.add_primitive("msec", Units::from_msec);
-        self.add_primitive("usec", Units::from_usec);
+impl Div for Dimension {
+    type Output = Dimension;
+    fn div(self, rhs: Dimension) -> Dimension {
+        let mut result = self;
+        for (e, r) in result.0.iter_mut().zip(rhs.0.iter()) {
+            *e = e.wrapping_sub(*r);
+        }
+        result
+    }
+}
 
-        self.add_primitive("mm/min", Units::mm_per_min);
-        self.add_primitive("mm/sec", Units::mm_per_sec);
-        self.add_primitive("um/msec", Units::um_per_msec);
+/// Dimension of a float on the floating-point stack, see `Stack::dim`.
+#[cfg(feature = "checked-units")]
+pub type Tag = Option<Dimension>;
+
+/// Dimension of a float on the floating-point stack, see `Stack::dim`.
+#[cfg(not(feature = "checked-units"))]
+pub type Tag = Unchecked;
+
+/// Tag of floats when dimensions are not checked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Unchecked;
+
+/// Dimension checks of float words.
+///
+/// Functions returning `None` found a mismatch.
+pub trait DimensionTag: Copy {
+    /// Tag of a float of dimension `d`.
+    fn known(d: Dimension) -> Self;
+    /// Tag of the sum or the difference of floats tagged `a` and `b`.
+    fn sum(a: Self, b: Self) -> Option<Self>;
+    /// Tag of the product of floats tagged `a` and `b`.
+    fn product(a: Self, b: Self) -> Self;
+    /// Tag of the quotient of floats tagged `a` and `b`.
+    fn quotient(a: Self, b: Self) -> Self;
+    /// Tag of the square root of a float tagged `a`.
+    fn root(a: Self) -> Option<Self>;
+    /// `a` if it is of dimension `d`, or unknown.
+    fn check(a: Self, d: Dimension) -> Option<Self>;
+}
 
-        self.add_primitive("rpm", Units::rpm);
-        self.add_primitive("hz", Units::hertz);
-        self.add_primitive("1/sec", Units::hertz);
+impl DimensionTag for Option<Dimension> {
+    fn known(d: Dimension) -> Self {
+        Some(d)
     }
 
-    primitive! {fn from_meter(&mut self) {
-        // Do nothing.
-    }}
+    fn sum(a: Self, b: Self) -> Option<Self> {
+        match (a, b) {
+            (Some(x), Some(y)) if x != y => None,
+            (Some(_), _) => Some(a),
+            _ => Some(b),
+        }
+    }
 
-    primitive! {fn from_mm(&mut self) {
-        let t = self.f_stack().pop();
-        self.f_stack().push(0.001 * t);
-    }}
+    fn product(a: Self, b: Self) -> Self {
+        match (a, b) {
+            (Some(x), Some(y)) => Some(x * y),
+            _ => None,
+        }
+    }
 
-    primitive! {fn from_um(&mut self) {
-        let t = self.f_stack().pop();
-        self.f_stack().push(0.000001 * t);
-    }}
+    fn quotient(a: Self, b: Self) -> Self {
+        match (a, b) {
+            (Some(x), Some(y)) => Some(x / y),
+            _ => None,
+        }
+    }
 
-    primitive! {fn from_deg(&mut self) {
-        let t = self.f_stack().pop();
-        self.f_stack().push(t*PI/180.0);
-    }}
+    fn root(a: Self) -> Option<Self> {
+        match a {
+            Some(x) => x.sqrt().map(Some),
+            None => Some(None),
+        }
+    }
 
-    primitive! {fn from_rad(&mut self) {
-        // Do nothing.
-    }}
+    fn check(a: Self, d: Dimension) -> Option<Self> {
+        match a {
+            Some(x) if x != d => None,
+            _ => Some(a),
+        }
+    }
+}
 
-    primitive! {fn from_hour(&mut self) {
-        let t = self.f_stack().pop();
-        self.f_stack().push(60.0 * 60.0 * t);
-    }}
+impl DimensionTag for Unchecked {
+    #[inline(always)]
+    fn known(_d: Dimension) -> Self {
+        Unchecked
+    }
 
-    primitive! {fn from_minute(&mut self) {
-        let t = self.f_stack().pop();
-        self.f_stack().push(60.0 * t);
-    }}
+    #[inline(always)]
+    fn sum(_a: Self, _b: Self) -> Option<Self> {
+        Some(Unchecked)
+    }
 
-    primitive! {fn from_sec(&mut self) {
-        // Do nothing.
-    }}
+    #[inline(always)]
+    fn product(_a: Self, _b: Self) -> Self {
+        Unchecked
+    }
 
-    primitive! {fn from_msec(&mut self) {
-        let t = self.f_stack().pop();
-        self.f_stack().push(0.001 * t);
-    }}
+    #[inline(always)]
+    fn quotient(_a: Self, _b: Self) -> Self {
+        Unchecked
+    }
 
-    primitive! {fn from_usec(&mut self) {
-        let t = self.f_stack().pop();
-        self.f_stack().push(0.000001 * t);
-    }}
+    #[inline(always)]
+    fn root(_a: Self) -> Option<Self> {
+        Some(Unchecked)
+    }
+
+    #[inline(always)]
+    fn check(_a: Self, _d: Dimension) -> Option<Self> {
+        Some(Unchecked)
+    }
+}
+
+/// Tag of the dimensionless result of a function of a float tagged `tag`,
+/// such as FSIN, or `None` if `tag` is neither dimensionless nor of
+/// dimension `d`.
+pub fn unitless<T: DimensionTag>(tag: T, d: Dimension) -> Option<T> {
+    if T::check(tag, Dimension::NONE).is_some() || T::check(tag, d).is_some() {
+        Some(T::known(Dimension::NONE))
+    } else {
+        None
+    }
+}
+
+/// A unit of measurement, `factor` times the SI unit of `dimension`
+/// plus `offset`.
+///
+/// A value `x` in the unit is `factor * x + offset` in the SI unit.
+/// `offset` is zero except for units such as degree Celsius.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Unit {
+    pub factor: f64,
+    pub offset: f64,
+    pub dimension: Dimension,
+}
+
+impl Unit {
+    /// `factor` times the SI unit of `dimension`.
+    pub fn new(factor: f64, dimension: Dimension) -> Unit {
+        Unit {
+            factor,
+            offset: 0.0,
+            dimension,
+        }
+    }
+
+    /// `factor` times this unit, ignoring its offset.
+    pub fn scaled(self, factor: f64) -> Unit {
+        Unit::new(factor * self.factor, self.dimension)
+    }
+}
+
+/// Product of two units, ignoring their offsets.
+impl Mul for Unit {
+    type Output = Unit;
+    fn mul(self, rhs: Unit) -> Unit {
+        Unit::new(self.factor * rhs.factor, self.dimension * rhs.dimension)
+    }
+}
+
+/// Quotient of two units, ignoring their offsets.
+impl Div for Unit {
+    type Output = Unit;
+    fn div(self, rhs: Unit) -> Unit {
+        Unit::new(self.factor / rhs.factor, self.dimension / rhs.dimension)
+    }
+}
+
+// Padding to align the factor, factor, offset, dimension of a unit word
+// and padding after it.
+const UNIT_DATA_SIZE: usize = (mem::size_of::<f64>() - 1)
+    + 2 * mem::size_of::<f64>()
+    + mem::size_of::<Dimension>()
+    + (mem::size_of::<usize>() - 1);
+
+pub trait Units: Float {
+    fn add_units(&mut self) {
+        self.add_primitive("unit:", Units::unit_colon);
+        self.add_primitive("unit*:", Units::unit_star_colon);
+        self.add_primitive("unit/:", Units::unit_slash_colon);
+
+        let length = Dimension::LENGTH;
+        let time = Dimension::TIME;
+        let angle = Dimension::ANGLE;
+        let velocity = length / time;
+        let acceleration = velocity / time;
+        let force = Dimension::MASS * acceleration;
+        let power = force * velocity;
+        let catalogue = [
+            ("meter", 1.0, length),
+            ("mm", 0.001, length),
+            ("um", 0.000_001, length),
+            ("inch", 0.0254, length),
+            ("mil", 0.000_025_4, length),
+            ("deg", PI / 180.0, angle),
+            ("rad", 1.0, angle),
+            ("rev", 2.0 * PI, angle),
+            ("hr", 60.0 * 60.0, time),
+            ("minute", 60.0, time),
+            ("sec", 1.0, time),
+            ("msec", 0.001, time),
+            ("usec", 0.000_001, time),
+            ("mm/min", 0.001 / 60.0, velocity),
+            ("mm/sec", 0.001, velocity),
+            ("um/msec", 0.000_001 / 0.001, velocity),
+            ("inch/min", 0.0254 / 60.0, velocity),
+            ("mm/rev", 0.001 / (2.0 * PI), length / angle),
+            ("rpm", RPM, angle / time),
+            ("deg/sec", PI / 180.0, angle / time),
+            ("hz", 1.0, Dimension::NONE / time),
+            ("1/sec", 1.0, Dimension::NONE / time),
+            ("gravity", 9.806_65, acceleration),
+            ("kg", 1.0, Dimension::MASS),
+            ("gram", 0.001, Dimension::MASS),
+            ("newton", 1.0, force),
+            ("newton-meter", 1.0, force * length),
+            ("watt", 1.0, power),
+            ("ampere", 1.0, Dimension::CURRENT),
+            ("ma", 0.001, Dimension::CURRENT),
+            ("volt", 1.0, power / Dimension::CURRENT),
+            ("kelvin", 1.0, Dimension::TEMPERATURE),
+        ];
+        for &(name, factor, dimension) in catalogue.iter() {
+            self.add_unit(name, Unit::new(factor, dimension));
+        }
+        self.add_unit(
+            "degc",
+            Unit {
+                factor: 1.0,
+                offset: 273.15,
+                dimension: Dimension::TEMPERATURE,
+            },
+        );
+    }
+
+    /// Define unit word `name` and its reverse `>name`.
+    ///
+    /// `name ( F: r1 -- r2 )` converts `r1` in the unit to `r2` in the SI
+    /// unit, `>name ( F: r1 -- r2 )` converts `r1` in the SI unit to `r2` in
+    /// the unit.
+    ///
+    /// ```
+    /// use rtforth::units::{Unit, Units};
+    /// use rtforth::vm::VM;
+    ///
+    /// let vm = &mut VM::new();
+    /// let mm = vm.unit("mm").unwrap();
+    /// vm.add_unit("cm", mm.scaled(10.0));
+    /// ```
+    fn add_unit(&mut self, name: &str, unit: Unit) {
+        let reverse = format!(">{}", name);
+        self.define_unit(name.to_string(), unit, Units::p_unit);
+        self.define_unit(reverse, unit, Units::p_to_unit);
+    }
+
+    /// Unit of unit word `name`, `None` if there is no such unit.
+    fn unit(&mut self, name: &str) -> Option<Unit> {
+        match self.find(name) {
+            Some(xt) if self.wordlist()[xt].word_type() == WordType::Unit => {
+                let dfa = self.wordlist()[xt].dfa();
+                Some(self.unit_at(dfa))
+            }
+            _ => None,
+        }
+    }
+
+    // Define unit word `name` with action `action`.
+    fn define_unit(&mut self, name: String, unit: Unit, action: primitive! {fn(&mut Self)}) {
+        let count = self.wordlist().len();
+        self.define_named_with_data(
+            name,
+            WordType::Unit,
+            action,
+            Core::compile_comma,
+            UNIT_DATA_SIZE,
+        );
+        // The word is only defined if there is space for UNIT_DATA_SIZE
+        // bytes of data.
+        if self.wordlist().len() > count {
+            self.data_space().align_f64();
+            self.data_space().compile_f64(unit.factor);
+            self.data_space().compile_f64(unit.offset);
+            for &e in unit.dimension.0.iter() {
+                self.data_space().compile_u8(e as u8);
+            }
+            self.data_space().align();
+        }
+    }
 
-    primitive! {fn mm_per_min(&mut self) {
+    // Unit stored at `dfa` by `define_unit`.
+    fn unit_at(&mut self, dfa: usize) -> Unit {
+        let pos = DataSpace::aligned_f64(dfa);
+        let size = mem::size_of::<f64>();
+        let mut dimension = Dimension::NONE;
+        unsafe {
+            for (i, e) in dimension.0.iter_mut().enumerate() {
+                *e = self.data_space().get_u8(pos + 2 * size + i) as i8;
+            }
+            Unit {
+                factor: self.data_space().get_f64(pos),
+                offset: self.data_space().get_f64(pos + size),
+                dimension,
+            }
+        }
+    }
+
+    // Parse the name of a unit word. Aborts if there is no such unit.
+    fn parse_unit(&mut self) -> Option<Unit> {
+        self.parse_word();
+        let last_token = self.last_token().take().expect("last token");
+        let unit = self.unit(&last_token);
+        let empty = last_token.is_empty();
+        self.set_last_token(last_token);
+        if empty {
+            self.abort_with(UNEXPECTED_END_OF_FILE);
+        } else if unit.is_none() {
+            self.abort_with(UNDEFINED_WORD);
+        }
+        unit
+    }
+
+    // Parse the name of a new unit.
+    fn parse_unit_name(&mut self) -> Option<String> {
+        self.parse_word();
+        let name = self.last_token().clone().expect("last token");
+        if name.is_empty() {
+            self.abort_with(UNEXPECTED_END_OF_FILE);
+            None
+        } else {
+            Some(name)
+        }
+    }
+
+    // Run-time of unit words.
+    primitive! {fn p_unit(&mut self) {
+        let wp = self.state().word_pointer();
+        let dfa = self.wordlist()[wp].dfa();
+        let unit = self.unit_at(dfa);
+        if Tag::check(self.f_stack().dim(0), Dimension::NONE).is_none() {
+            self.abort_with(ARGUMENT_TYPE_MISMATCH);
+            return;
+        }
         let t = self.f_stack().pop();
-        self.f_stack().push(0.001/60.0 * t);
+        self.push_tagged_result(unit.factor * t + unit.offset, false, Some(Tag::known(unit.dimension)));
     }}
 
-    primitive! {fn mm_per_sec(&mut self) {
+    // Run-time of reverse unit words.
+    primitive! {fn p_to_unit(&mut self) {
+        let wp = self.state().word_pointer();
+        let dfa = self.wordlist()[wp].dfa();
+        let unit = self.unit_at(dfa);
+        if Tag::check(self.f_stack().dim(0), unit.dimension).is_none() {
+            self.abort_with(ARGUMENT_TYPE_MISMATCH);
+            return;
+        }
         let t = self.f_stack().pop();
-        self.f_stack().push(0.001 * t);
+        self.push_tagged_result((t - unit.offset) / unit.factor, false, Some(Tag::known(Dimension::NONE)));
     }}
 
-    primitive! {fn um_per_msec(&mut self) {
-        let t = self.f_stack().pop();
-        self.f_stack().push(0.000001/0.001 * t);
+    // UNIT: ( "<spaces>name" "<spaces>r" "<spaces>unit" -- )
+    // Define unit word `name` equal to `r` times unit word `unit`, and its
+    // reverse `>name`. For example, `unit: inch 0.0254 meter`. Without
+    // `unit`, that is if the next word is not a unit word, `name` is the
+    // dimensionless scale `r`, as in `unit: percent 0.01`. `r` must be
+    // finite and not zero.
+    primitive! {fn unit_colon(&mut self) {
+        let name = match self.parse_unit_name() {
+            Some(name) => name,
+            None => return,
+        };
+        self.parse_word();
+        let token = self.last_token().take().expect("last token");
+        let factor = to_float(&token);
+        let empty = token.is_empty();
+        self.set_last_token(token);
+        if empty {
+            self.abort_with(UNEXPECTED_END_OF_FILE);
+            return;
+        }
+        let factor = match factor {
+            Some(factor) if factor != 0.0 && factor.is_finite() => factor,
+            _ => {
+                self.abort_with(INVALID_NUMERIC_ARGUMENT);
+                return;
+            }
+        };
+        let source_index = self.state().source_index;
+        self.parse_word();
+        let token = self.last_token().take().expect("last token");
+        let unit = self.unit(&token);
+        self.set_last_token(token);
+        let unit = match unit {
+            Some(unit) => unit,
+            None => {
+                self.state().source_index = source_index;
+                Unit::new(1.0, Dimension::NONE)
+            }
+        };
+        self.add_unit(&name, unit.scaled(factor));
     }}
 
-    primitive! {fn rpm(&mut self) {
-        let t = self.f_stack().pop();
-        self.f_stack().push(t*RPM);
+    // UNIT*: ( "<spaces>name" "<spaces>unit1" "<spaces>unit2" -- )
+    // Define unit word `name`, the product of `unit1` and `unit2`, and its
+    // reverse `>name`. For example, `unit*: newton-meter newton meter`.
+    primitive! {fn unit_star_colon(&mut self) {
+        let name = match self.parse_unit_name() {
+            Some(name) => name,
+            None => return,
+        };
+        if let Some(unit1) = self.parse_unit() {
+            if let Some(unit2) = self.parse_unit() {
+                self.add_unit(&name, unit1 * unit2);
+            }
+        }
     }}
 
-    primitive! {fn hertz(&mut self) {
-        let t = self.f_stack().pop();
-        self.f_stack().push(t);
+    // UNIT/: ( "<spaces>name" "<spaces>unit1" "<spaces>unit2" -- )
+    // Define unit word `name`, the quotient of `unit1` and `unit2`, and its
+    // reverse `>name`. For example, `unit/: mm/rev mm rev`.
+    primitive! {fn unit_slash_colon(&mut self) {
+        let name = match self.parse_unit_name() {
+            Some(name) => name,
+            None => return,
+        };
+        if let Some(unit1) = self.parse_unit() {
+            if let Some(unit2) = self.parse_unit() {
+                self.add_unit(&name, unit1 / unit2);
+            }
+        }
     }}
 }
 
 #[cfg(test)]
 mod tests {
     use core::Core;
-    use mock_vm::VM;
+    use memory::Memory;
+    use mock_vm::{NewVM, VM};
     use std::f64::consts::PI;
 
     fn double_value_check(res: f64, exp: f64) -> bool {
@@ -266,4 +678,208 @@ mod tests {
         let t = vm.f_stack().pop();
         assert!(double_value_check(t, 2.0));
     }
+
+    #[test]
+    fn test_dimension() {
+        use super::Dimension;
+        let velocity = Dimension::LENGTH / Dimension::TIME;
+        assert_eq!(velocity.0, [1, 0, -1, 0, 0, 0]);
+        assert_eq!(velocity * Dimension::TIME, Dimension::LENGTH);
+        let area = Dimension::LENGTH * Dimension::LENGTH;
+        assert_eq!(area.sqrt(), Some(Dimension::LENGTH));
+        assert_eq!(velocity.sqrt(), None);
+    }
+
+    #[cfg(feature = "checked-units")]
+    #[test]
+    fn test_checked_units() {
+        use super::Dimension;
+        use exception::ARGUMENT_TYPE_MISMATCH;
+        let vm = &mut VM::new();
+        vm.set_source("3e mm 4e mm f+ 2e sec f/ fdup f*");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        let area = Dimension::LENGTH * Dimension::LENGTH;
+        assert_eq!(
+            vm.f_stack().dim(0),
+            Some(area / Dimension::TIME / Dimension::TIME)
+        );
+        vm.set_source("fsqrt 1e sec f* 1e mm fswap fover f- f< 30e deg fsin");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().len(), 1);
+        assert_eq!(vm.f_stack().dim(0), Some(Dimension::NONE));
+        vm.set_source("0.5e fasin 1e rad f+ 2e f*");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.f_stack().dim(0), Some(Dimension::ANGLE));
+
+        // Floats fetched from memory are of unknown dimension.
+        vm.set_source("fvariable x 1e mm x f! x f@ 1e deg f+");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.f_stack().dim(0), Some(Dimension::ANGLE));
+
+        for source in &[
+            "1e mm 1e deg f+",
+            "1e mm 1e sec f<",
+            "1e mm 1e mm f* fsqrt fsin",
+            "1e mm fexp",
+            "1e mm mm",
+            "1e mm 1e mm f* 1e sec f/ fsqrt",
+            "1e sec >mm",
+        ] {
+            vm.set_error(None);
+            vm.f_stack().reset();
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(vm.last_error(), Some(ARGUMENT_TYPE_MISMATCH), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_reverse_units() {
+        let vm = &mut VM::new();
+        vm.set_source("2.5e mm >mm  90e deg >deg  1e minute >sec  25e degc >kelvin");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.f_stack().len(), 4);
+        assert!(double_value_check(vm.f_stack().get(0), 2.5));
+        assert!(double_value_check(vm.f_stack().get(1), 90.0));
+        assert!(double_value_check(vm.f_stack().get(2), 60.0));
+        assert!(double_value_check(vm.f_stack().get(3), 298.15));
+        vm.f_stack().reset();
+        vm.set_source(
+            "300e kelvin >degc 1e inch >mil 120e rpm 1e sec f* >rev 1e gravity 1e sec f* >mm/sec",
+        );
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.f_stack().len(), 4);
+        assert!(double_value_check(vm.f_stack().get(0), 26.85));
+        assert!(double_value_check(vm.f_stack().get(1), 1000.0));
+        assert!(double_value_check(vm.f_stack().get(2), 2.0));
+        assert!(double_value_check(vm.f_stack().get(3), 9806.65));
+    }
+
+    #[test]
+    fn test_strict_float_units() {
+        use exception::FLOATING_POINT_RESULT_OUT_OF_RANGE;
+        use float::{Float, FLOAT_OVERFLOW};
+        let vm = &mut VM::new();
+        vm.set_strict_float(true);
+        for source in &["1e308 meter >um", "1e308 hr"] {
+            vm.set_error(None);
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(
+                vm.last_error(),
+                Some(FLOATING_POINT_RESULT_OUT_OF_RANGE),
+                "{}",
+                source
+            );
+            assert_eq!(vm.f_stack().len(), 0);
+        }
+        assert_eq!(vm.float_status(), FLOAT_OVERFLOW);
+    }
+
+    #[test]
+    fn test_define_units() {
+        use super::{Dimension, Units};
+        use exception::{INVALID_NUMERIC_ARGUMENT, UNDEFINED_WORD, UNEXPECTED_END_OF_FILE};
+        let vm = &mut VM::new();
+        vm.set_source("unit: foot 12 inch  unit/: foot/min foot minute  unit*: mm*rev mm rev");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        vm.set_source("1e foot 1e foot/min 1e mm*rev 1e rad f/ >mm 1e mm/sec >foot/min");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.f_stack().len(), 4);
+        assert!(double_value_check(vm.f_stack().get(0), 0.3048));
+        assert!(double_value_check(vm.f_stack().get(1), 0.3048 / 60.0));
+        assert!(double_value_check(vm.f_stack().get(2), 2.0 * PI));
+        assert!(double_value_check(vm.f_stack().get(3), 0.06 / 0.3048));
+
+        vm.set_source("unit: percent 0.01  unit: ppm 1e-6 2e percent 3e ppm");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert!(double_value_check(vm.f_stack().get(4), 0.02));
+        assert!(double_value_check(vm.f_stack().get(5), 0.000_003));
+        assert_eq!(vm.unit("ppm").unwrap().dimension, Dimension::NONE);
+        vm.f_stack().reset();
+        vm.set_source("1e newton 1e ampere 1e volt 1e watt 1e gravity 1e newton-meter");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.f_stack().len(), 6);
+        assert_eq!(
+            vm.unit("newton-meter").unwrap().dimension.0,
+            [2, 1, -2, 0, 0, 0]
+        );
+        vm.set_source("1e g");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(UNDEFINED_WORD));
+        vm.set_error(None);
+        vm.set_source("unit: bad 12 parsec");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(UNDEFINED_WORD));
+        vm.set_error(None);
+        vm.set_source("unit: bad twelve inch");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(INVALID_NUMERIC_ARGUMENT));
+        vm.set_error(None);
+        vm.set_source("unit/: bad inch");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(UNEXPECTED_END_OF_FILE));
+        vm.set_error(None);
+        vm.set_source("unit: bad");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(UNEXPECTED_END_OF_FILE));
+        vm.set_error(None);
+        for source in &[
+            "unit: bad 0 inch",
+            "unit: bad 0e",
+            "unit: bad 1e400 inch",
+            "unit: bad nan",
+        ] {
+            vm.set_source(source);
+            vm.evaluate_input();
+            assert_eq!(
+                vm.last_error(),
+                Some(INVALID_NUMERIC_ARGUMENT),
+                "{}",
+                source
+            );
+            vm.set_error(None);
+        }
+    }
+
+    #[test]
+    fn test_define_unit_overflow() {
+        use exception::DICTIONARY_OVERFLOW;
+        let vm = &mut VM::new();
+        vm.set_source("unused 40 - allot  unit: foot 12 inch");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), Some(DICTIONARY_OVERFLOW));
+        assert!(vm.find("foot").is_none());
+        assert!(vm.data_space().here() <= vm.data_space().dictionary_limit());
+    }
+
+    #[test]
+    fn test_add_unit() {
+        use super::{Dimension, Unit, Units};
+        let vm = &mut VM::new();
+        assert_eq!(vm.unit("mm"), Some(Unit::new(0.001, Dimension::LENGTH)));
+        assert_eq!(vm.unit("dup"), None);
+        assert_eq!(vm.unit("nosuchunit"), None);
+        let mm = vm.unit("mm").unwrap();
+        let sec = vm.unit("sec").unwrap();
+        vm.add_unit("cm", mm.scaled(10.0));
+        vm.add_unit("mm/sec2", mm / sec / sec);
+        let unit = vm.unit("MM/SEC2").unwrap();
+        assert_eq!(unit.dimension.0, [1, 0, -2, 0, 0, 0]);
+        vm.set_source("3e cm >mm  2e mm/sec2");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert!(double_value_check(vm.f_stack().get(0), 30.0));
+        assert!(double_value_check(vm.f_stack().get(1), 0.002));
+    }
 }
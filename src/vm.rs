@@ -0,0 +1,783 @@
+//! Configurable virtual machine
+//!
+//! `VM<T>` implements `Core` and all word sets of this crate, so that
+//! applications do not need to implement the accessors of `Core` by
+//! themselves. Application specific state is kept in the user data of
+//! type `T`, which primitives added by the application can reach through
+//! `VM::user_data` and `VM::user_data_mut`.
+//!
+//! ```
+//! use rtforth::core::Core;
+//! use rtforth::vm::VMBuilder;
+//!
+//! let mut vm = VMBuilder::new().data_capacity(128 * 1024).build();
+//! vm.set_source("1 2 +");
+//! vm.evaluate_input();
+//! assert_eq!(vm.s_stack().pop(), 3);
+//! ```
+
+use block::{Block, Blocks, DEFAULT_BLOCK_BUFFERS};
+use core::{
+    Closure, Control, Core, FloatDims, ForwardReferences, Stack, State, Wordlist,
+    DEFAULT_STACK_DEPTH,
+};
+use dsp::Dsp;
+use env::Environment;
+use facility::Facility;
+use file_access::{FileAccess, FileHandle};
+use fixed::Fixed;
+use float::Float;
+use hibitset::BitSet;
+#[cfg(feature = "offset-addresses")]
+use image::Image;
+use linalg::LinearAlgebra;
+use loader::{HasLoader, Includes, Source};
+use memory::DataSpace;
+use memory_allocation::MemoryAllocation;
+use motion::Motion;
+use output::{Output, Sink, Stderr, Stdout};
+use sandbox::Sandbox;
+use std::path::PathBuf;
+use std::time::Instant;
+use tools::Tools;
+use units::Units;
+use NUM_TASKS;
+
+const BUFFER_SIZE: usize = 0x400;
+
+/// Task
+///
+/// Each task has its own input buffer but shares the
+/// dictionary and output buffer owned by virtual machine.
+pub struct Task {
+    awake: bool,
+    state: State,
+    s_stk: Stack<isize>,
+    r_stk: Stack<isize>,
+    c_stk: Stack<Control>,
+    f_stk: Stack<f64, FloatDims>,
+    inbuf: Option<String>,
+    files: Vec<Option<FileHandle>>,
+    sources: Vec<Option<Source>>,
+    lines: Vec<Option<String>>,
+    sandbox: Option<Sandbox>,
+}
+
+impl Task {
+    /// Create a task without input buffer.
+    pub fn new_background() -> Task {
+        Task {
+            awake: false,
+            state: State::new(),
+            s_stk: Stack::new(0x12345678),
+            r_stk: Stack::new(0x12345678),
+            c_stk: Stack::new(Control::Canary),
+            f_stk: Stack::new(1.234567890),
+            inbuf: None,
+            files: Vec::new(),
+            sources: Vec::new(),
+            lines: Vec::new(),
+            sandbox: None,
+        }
+    }
+
+    /// Create a task with input buffer.
+    pub fn new_terminal() -> Task {
+        let mut task = Task::new_background();
+        task.inbuf = Some(String::with_capacity(BUFFER_SIZE));
+        task
+    }
+}
+
+/// Source of `system_time_ns`
+pub enum Clock {
+    /// Nanoseconds elapsed since the creation of the VM.
+    Monotonic,
+    /// Time which only changes when `VM::advance` is called.
+    /// Useful for tests and simulations. `run_until` does not return
+    /// before a later deadline unless the program ends, so prefer
+    /// `run_for` with this clock.
+    Manual,
+    /// Time in nanoseconds given by an application supplied function,
+    /// for example a clock synchronized to a fieldbus.
+    Custom(Box<dyn Fn() -> u64 + Send>),
+}
+
+/// Builder of `VM`
+///
+/// By default the VM has a data space of 64 KiB plus a heap of 16 KiB,
+/// 1000 labels, `NUM_TASKS` tasks, a monotonic clock, all word sets
+/// installed and `core.fs` loaded.
+///
+/// `core.fs` uses words from the output, tools, facility, float,
+/// file-access and loader word sets. If any of them is disabled, disable
+/// `core_fs` too.
+pub struct VMBuilder {
+    data_capacity: usize,
+    heap_size: usize,
+    label_count: u32,
+    task_count: usize,
+    data_stack_depth: usize,
+    return_stack_depth: usize,
+    float_stack_depth: usize,
+    clock: Clock,
+    output: bool,
+    tools: bool,
+    environment: bool,
+    facility: bool,
+    float: bool,
+    units: bool,
+    linear_algebra: bool,
+    fixed: bool,
+    motion: bool,
+    dsp: bool,
+    file_access: bool,
+    loader: bool,
+    #[cfg(feature = "offset-addresses")]
+    image: bool,
+    memory_allocation: bool,
+    block: bool,
+    block_buffers: usize,
+    include_path: Option<Vec<PathBuf>>,
+    output_sink: Box<dyn Sink>,
+    error_sink: Box<dyn Sink>,
+    strict_float: bool,
+    safe: bool,
+    core_fs: bool,
+}
+
+impl Default for VMBuilder {
+    fn default() -> Self {
+        VMBuilder::new()
+    }
+}
+
+impl VMBuilder {
+    /// Create a builder with the default configuration.
+    pub fn new() -> VMBuilder {
+        VMBuilder {
+            data_capacity: 64 * 1024,
+            heap_size: 16 * 1024,
+            label_count: 1000,
+            task_count: NUM_TASKS,
+            data_stack_depth: DEFAULT_STACK_DEPTH,
+            return_stack_depth: DEFAULT_STACK_DEPTH,
+            float_stack_depth: DEFAULT_STACK_DEPTH,
+            clock: Clock::Monotonic,
+            output: true,
+            tools: true,
+            environment: true,
+            facility: true,
+            float: true,
+            units: true,
+            linear_algebra: true,
+            fixed: true,
+            motion: true,
+            dsp: true,
+            file_access: true,
+            loader: true,
+            #[cfg(feature = "offset-addresses")]
+            image: true,
+            memory_allocation: true,
+            block: true,
+            block_buffers: DEFAULT_BLOCK_BUFFERS,
+            include_path: None,
+            output_sink: Box::new(Stdout),
+            error_sink: Box::new(Stderr),
+            strict_float: false,
+            safe: false,
+            core_fs: true,
+        }
+    }
+
+    /// Capacity of data space in bytes, without the heap. The block
+    /// buffers are allotted in it, see `block_buffers`.
+    pub fn data_capacity(mut self, bytes: usize) -> Self {
+        self.data_capacity = bytes;
+        self
+    }
+
+    /// Bytes used as the heap of the memory-allocation word set, 16 KiB by
+    /// default. The heap is added at the top of data space, beyond
+    /// `data_capacity`, so it does not take space from the dictionary.
+    pub fn heap_size(mut self, bytes: usize) -> Self {
+        self.heap_size = bytes;
+        self
+    }
+
+    /// Number of labels available to `LABEL`, `GOTO` and `CALL`.
+    pub fn label_count(mut self, n: u32) -> Self {
+        self.label_count = n;
+        self
+    }
+
+    /// Number of tasks, including the operator task. At least 1.
+    pub fn task_count(mut self, n: usize) -> Self {
+        self.task_count = if n == 0 { 1 } else { n };
+        self
+    }
+
+    /// Depth of the data stack of every task, at most `MAX_STACK_DEPTH`.
+    pub fn data_stack_depth(mut self, n: usize) -> Self {
+        self.data_stack_depth = n;
+        self
+    }
+
+    /// Depth of the return stack of every task, at most `MAX_STACK_DEPTH`.
+    pub fn return_stack_depth(mut self, n: usize) -> Self {
+        self.return_stack_depth = n;
+        self
+    }
+
+    /// Depth of the floating-point stack of every task, at most
+    /// `MAX_STACK_DEPTH`.
+    pub fn float_stack_depth(mut self, n: usize) -> Self {
+        self.float_stack_depth = n;
+        self
+    }
+
+    /// Source of `system_time_ns`.
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Install the output word set.
+    pub fn output(mut self, on: bool) -> Self {
+        self.output = on;
+        self
+    }
+
+    /// Install the programming tools word set.
+    pub fn tools(mut self, on: bool) -> Self {
+        self.tools = on;
+        self
+    }
+
+    /// Install the environment query word set.
+    pub fn environment(mut self, on: bool) -> Self {
+        self.environment = on;
+        self
+    }
+
+    /// Install the facility word set.
+    pub fn facility(mut self, on: bool) -> Self {
+        self.facility = on;
+        self
+    }
+
+    /// Install the floating-point word set.
+    pub fn float(mut self, on: bool) -> Self {
+        self.float = on;
+        self
+    }
+
+    /// Install the units word set.
+    pub fn units(mut self, on: bool) -> Self {
+        self.units = on;
+        self
+    }
+
+    /// Install the linear algebra word set, see `linalg::LinearAlgebra`.
+    pub fn linear_algebra(mut self, on: bool) -> Self {
+        self.linear_algebra = on;
+        self
+    }
+
+    /// Install the fixed-point word set, see `fixed::Fixed`.
+    pub fn fixed(mut self, on: bool) -> Self {
+        self.fixed = on;
+        self
+    }
+
+    /// Install the motion profile word set, see `motion::Motion`.
+    pub fn motion(mut self, on: bool) -> Self {
+        self.motion = on;
+        self
+    }
+
+    /// Install the signal processing word set, see `dsp::Dsp`.
+    pub fn dsp(mut self, on: bool) -> Self {
+        self.dsp = on;
+        self
+    }
+
+    /// Install the file-access word set.
+    pub fn file_access(mut self, on: bool) -> Self {
+        self.file_access = on;
+        self
+    }
+
+    /// Install the loader word set.
+    pub fn loader(mut self, on: bool) -> Self {
+        self.loader = on;
+        self
+    }
+
+    /// Install the dictionary image word set. Only with feature
+    /// `offset-addresses`, see `image`.
+    #[cfg(feature = "offset-addresses")]
+    pub fn image(mut self, on: bool) -> Self {
+        self.image = on;
+        self
+    }
+
+    /// Install the memory-allocation word set. Without it, there is no
+    /// heap.
+    pub fn memory_allocation(mut self, on: bool) -> Self {
+        self.memory_allocation = on;
+        self
+    }
+
+    /// Install the block word set.
+    pub fn block(mut self, on: bool) -> Self {
+        self.block = on;
+        self
+    }
+
+    /// Number of block buffers, at least 1. They are allotted in the
+    /// dictionary when the block word set is installed.
+    pub fn block_buffers(mut self, n: usize) -> Self {
+        self.block_buffers = n;
+        self
+    }
+
+    /// Directories searched by INCLUDED and REQUIRED for files not found
+    /// relative to the including file or the current directory. Without
+    /// it, the directories are taken from environment variable
+    /// `RTFORTH_PATH`.
+    pub fn include_path(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.include_path = Some(dirs);
+        self
+    }
+
+    /// Sink written by FLUSH-OUTPUT, `output::Stdout` by default.
+    pub fn output_sink(mut self, sink: Box<dyn Sink>) -> Self {
+        self.output_sink = sink;
+        self
+    }
+
+    /// Sink written by FLUSH-TO-ERR, `output::Stderr` by default.
+    pub fn error_sink(mut self, sink: Box<dyn Sink>) -> Self {
+        self.error_sink = sink;
+        self
+    }
+
+    /// Start in strict float mode, see `Float::is_strict_float`.
+    pub fn strict_float(mut self, on: bool) -> Self {
+        self.strict_float = on;
+        self
+    }
+
+    /// Run in safe mode, see `Core::set_safe_mode`.
+    pub fn safe(mut self, on: bool) -> Self {
+        self.safe = on;
+        self
+    }
+
+    /// Load `core.fs` after the word sets are installed.
+    pub fn core_fs(mut self, on: bool) -> Self {
+        self.core_fs = on;
+        self
+    }
+
+    /// Build a VM without user data.
+    pub fn build(self) -> VM {
+        self.build_with(())
+    }
+
+    /// Build a VM with user data `user_data`.
+    pub fn build_with<T>(self, user_data: T) -> VM<T> {
+        let labels = vec![0; self.label_count as _];
+        let heap_size = if self.memory_allocation {
+            self.heap_size
+        } else {
+            0
+        };
+        let includes = match self.include_path {
+            Some(dirs) => Includes::new(dirs),
+            None => Includes::from_env(),
+        };
+        let mut tasks = Vec::with_capacity(self.task_count);
+        // Only operator task has its own input buffer.
+        tasks.push(Task::new_terminal());
+        for _ in 1..self.task_count {
+            tasks.push(Task::new_background());
+        }
+        for task in &mut tasks {
+            task.s_stk.set_capacity(self.data_stack_depth);
+            task.r_stk.set_capacity(self.return_stack_depth);
+            task.f_stk.set_capacity(self.float_stack_depth);
+        }
+        let mut vm = VM {
+            current_task: 0,
+            tasks,
+            last_error: None,
+            handler: 0,
+            wordlist: Wordlist::with_capacity(1000),
+            data_space: DataSpace::with_heap(self.data_capacity + heap_size, heap_size),
+            tkn: Some(String::with_capacity(64)),
+            outbuf: Some(String::with_capacity(128)),
+            output_sink: self.output_sink,
+            error_sink: self.error_sink,
+            hldbuf: String::with_capacity(128),
+            references: ForwardReferences::new(),
+            closures: Vec::new(),
+            clock: self.clock,
+            start: Instant::now(),
+            now: 0,
+            forward_bitset: BitSet::with_capacity(self.label_count),
+            resolved_bitset: BitSet::with_capacity(self.label_count),
+            labels,
+            includes,
+            blocks: Blocks::new(self.block_buffers),
+            user_data,
+        };
+        if self.safe {
+            vm.set_safe_mode(true);
+        }
+        vm.add_core();
+        if self.output {
+            vm.add_output();
+        }
+        if self.tools {
+            vm.add_tools();
+        }
+        if self.environment {
+            vm.add_environment();
+        }
+        if self.facility {
+            vm.add_facility();
+        }
+        if self.float {
+            vm.add_float();
+        }
+        if self.units {
+            vm.add_units();
+        }
+        if self.linear_algebra {
+            vm.add_linear_algebra();
+        }
+        if self.fixed {
+            vm.add_fixed();
+        }
+        if self.motion {
+            vm.add_motion();
+        }
+        if self.dsp {
+            vm.add_dsp();
+        }
+        if self.file_access {
+            vm.add_file_access();
+        }
+        if self.loader {
+            vm.add_loader();
+        }
+        #[cfg(feature = "offset-addresses")]
+        {
+            if self.image {
+                vm.add_image();
+            }
+        }
+        if self.memory_allocation {
+            vm.add_memory_allocation();
+        }
+        if self.block {
+            vm.add_block();
+        }
+        if self.core_fs {
+            vm.load_core_fs();
+        }
+        if self.strict_float {
+            vm.set_strict_float(true);
+        }
+        vm
+    }
+}
+
+/// Virtual machine
+pub struct VM<T = ()> {
+    current_task: usize,
+    tasks: Vec<Task>,
+    last_error: Option<isize>,
+    handler: usize,
+    wordlist: Wordlist<VM<T>>,
+    data_space: DataSpace,
+    tkn: Option<String>,
+    outbuf: Option<String>,
+    output_sink: Box<dyn Sink>,
+    error_sink: Box<dyn Sink>,
+    hldbuf: String,
+    references: ForwardReferences,
+    closures: Vec<Option<Closure<VM<T>>>>,
+    clock: Clock,
+    start: Instant,
+    now: u64,
+    forward_bitset: BitSet,
+    resolved_bitset: BitSet,
+    labels: Vec<usize>,
+    includes: Includes,
+    blocks: Blocks,
+    user_data: T,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
+    }
+}
+
+impl VM {
+    /// Create VM with the default configuration of `VMBuilder`.
+    pub fn new() -> VM {
+        VMBuilder::new().build()
+    }
+}
+
+impl<T> VM<T> {
+    /// User data
+    pub fn user_data(&self) -> &T {
+        &self.user_data
+    }
+
+    /// Mutable user data
+    pub fn user_data_mut(&mut self) -> &mut T {
+        &mut self.user_data
+    }
+
+    /// Advance time for 1ms. Only meaningful with `Clock::Manual`.
+    pub fn advance(&mut self) {
+        self.now += 1_000_000;
+    }
+}
+
+impl<T> Core for VM<T> {
+    fn last_error(&self) -> Option<isize> {
+        self.last_error
+    }
+    fn set_error(&mut self, e: Option<isize>) {
+        self.last_error = e;
+    }
+    fn handler(&self) -> usize {
+        self.handler
+    }
+    fn set_handler(&mut self, h: usize) {
+        self.handler = h;
+    }
+    fn data_space(&mut self) -> &mut DataSpace {
+        &mut self.data_space
+    }
+    fn data_space_const(&self) -> &DataSpace {
+        &self.data_space
+    }
+    fn hold_buffer(&mut self) -> &mut String {
+        &mut self.hldbuf
+    }
+    fn output_buffer(&mut self) -> &mut Option<String> {
+        &mut self.outbuf
+    }
+    fn set_output_buffer(&mut self, buffer: String) {
+        self.outbuf = Some(buffer);
+    }
+    fn output_sink(&mut self) -> &mut Box<dyn Sink> {
+        &mut self.output_sink
+    }
+    fn error_sink(&mut self) -> &mut Box<dyn Sink> {
+        &mut self.error_sink
+    }
+    fn source_id(&self) -> isize {
+        self.tasks[self.current_task].state.source_id
+    }
+    fn input_buffer(&mut self) -> &mut Option<String> {
+        let source_id = self.source_id();
+        if source_id > 0 {
+            &mut self.lines_mut()[source_id as usize - 1]
+        } else {
+            &mut self.tasks[self.current_task].inbuf
+        }
+    }
+    fn set_input_buffer(&mut self, buffer: String) {
+        *self.input_buffer() = Some(buffer);
+    }
+    fn files(&self) -> &Vec<Option<FileHandle>> {
+        &self.tasks[self.current_task].files
+    }
+    fn files_mut(&mut self) -> &mut Vec<Option<FileHandle>> {
+        &mut self.tasks[self.current_task].files
+    }
+    fn sources(&self) -> &Vec<Option<Source>> {
+        &self.tasks[self.current_task].sources
+    }
+    fn sources_mut(&mut self) -> &mut Vec<Option<Source>> {
+        &mut self.tasks[self.current_task].sources
+    }
+    fn lines(&self) -> &Vec<Option<String>> {
+        &self.tasks[self.current_task].lines
+    }
+    fn lines_mut(&mut self) -> &mut Vec<Option<String>> {
+        &mut self.tasks[self.current_task].lines
+    }
+    fn includes(&mut self) -> &mut Includes {
+        &mut self.includes
+    }
+    fn last_token(&mut self) -> &mut Option<String> {
+        &mut self.tkn
+    }
+    fn set_last_token(&mut self, buffer: String) {
+        self.tkn = Some(buffer);
+    }
+    fn s_stack(&mut self) -> &mut Stack<isize> {
+        &mut self.tasks[self.current_task].s_stk
+    }
+    fn r_stack(&mut self) -> &mut Stack<isize> {
+        &mut self.tasks[self.current_task].r_stk
+    }
+    fn c_stack(&mut self) -> &mut Stack<Control> {
+        &mut self.tasks[self.current_task].c_stk
+    }
+    fn f_stack(&mut self) -> &mut Stack<f64, FloatDims> {
+        &mut self.tasks[self.current_task].f_stk
+    }
+    fn wordlist_mut(&mut self) -> &mut Wordlist<Self> {
+        &mut self.wordlist
+    }
+    fn wordlist(&self) -> &Wordlist<Self> {
+        &self.wordlist
+    }
+    fn state(&mut self) -> &mut State {
+        &mut self.tasks[self.current_task].state
+    }
+    fn references(&mut self) -> &mut ForwardReferences {
+        &mut self.references
+    }
+    fn closures(&mut self) -> &mut Vec<Option<Closure<Self>>> {
+        &mut self.closures
+    }
+    fn sandbox(&mut self) -> &mut Option<Sandbox> {
+        &mut self.tasks[self.current_task].sandbox
+    }
+    fn system_time_ns(&self) -> u64 {
+        match self.clock {
+            Clock::Monotonic => {
+                let elapsed = self.start.elapsed();
+                elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos())
+            }
+            Clock::Manual => self.now,
+            Clock::Custom(ref f) => f(),
+        }
+    }
+    fn current_task(&self) -> usize {
+        self.current_task
+    }
+    fn set_current_task(&mut self, i: usize) {
+        if i < self.tasks.len() {
+            self.current_task = i;
+        } else {
+            // Do nothing.
+        }
+    }
+    fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+    fn awake(&self, i: usize) -> bool {
+        if i < self.tasks.len() {
+            self.tasks[i].awake
+        } else {
+            false
+        }
+    }
+    fn set_awake(&mut self, i: usize, v: bool) {
+        if i < self.tasks.len() {
+            self.tasks[i].awake = v;
+        } else {
+            // Do nothing.
+        }
+    }
+    fn forward_bitset(&self) -> &BitSet {
+        &self.forward_bitset
+    }
+    fn forward_bitset_mut(&mut self) -> &mut BitSet {
+        &mut self.forward_bitset
+    }
+    fn resolved_bitset(&self) -> &BitSet {
+        &self.resolved_bitset
+    }
+    fn resolved_bitset_mut(&mut self) -> &mut BitSet {
+        &mut self.resolved_bitset
+    }
+    fn labels(&self) -> &Vec<usize> {
+        &self.labels
+    }
+    fn labels_mut(&mut self) -> &mut Vec<usize> {
+        &mut self.labels
+    }
+    fn blocks(&mut self) -> &mut Blocks {
+        &mut self.blocks
+    }
+}
+
+impl<T> Block for VM<T> {}
+impl<T> Environment for VM<T> {}
+impl<T> Facility for VM<T> {}
+impl<T> Float for VM<T> {}
+impl<T> Units for VM<T> {}
+
+impl<T> LinearAlgebra for VM<T> {}
+impl<T> Fixed for VM<T> {}
+impl<T> Motion for VM<T> {}
+impl<T> Dsp for VM<T> {}
+impl<T> FileAccess for VM<T> {}
+impl<T> HasLoader for VM<T> {}
+#[cfg(feature = "offset-addresses")]
+impl<T> Image for VM<T> {}
+impl<T> MemoryAllocation for VM<T> {}
+impl<T> Output for VM<T> {}
+impl<T> Tools for VM<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, VMBuilder};
+    use core::Core;
+
+    #[test]
+    fn test_builder_tasks_and_clock() {
+        let vm = &mut VMBuilder::new().task_count(3).clock(Clock::Manual).build();
+        vm.set_source("#tasks");
+        vm.evaluate_input();
+        assert_eq!(vm.last_error(), None);
+        assert_eq!(vm.s_stack().pop(), 3);
+        vm.set_source("4 resume");
+        vm.evaluate_input();
+        assert!(vm.last_error().is_some());
+        vm.reset();
+        vm.advance();
+        assert_eq!(vm.system_time_ns(), 1_000_000);
+    }
+
+    #[test]
+    fn test_builder_without_wordsets() {
+        let vm = &mut VMBuilder::new()
+            .float(false)
+            .units(false)
+            .linear_algebra(false)
+            .fixed(false)
+            .motion(false)
+            .dsp(false)
+            .core_fs(false)
+            .build();
+        assert!(vm.find("f+").is_none());
+        assert!(vm.find("mm").is_none());
+        assert!(vm.find("fv-dot").is_none());
+        assert!(vm.find("q*").is_none());
+        assert!(vm.find("profile@").is_none());
+        assert!(vm.find("pid-step").is_none());
+        assert!(vm.find("dup").is_some());
+    }
+
+    #[test]
+    fn test_user_data() {
+        let vm = &mut VMBuilder::new().core_fs(false).build_with(41);
+        *vm.user_data_mut() += 1;
+        assert_eq!(*vm.user_data(), 42);
+    }
+}